@@ -1,15 +1,56 @@
-use accuchek_core::{self as core, GlucoseSample as CoreSample};
+use accuchek_core::usb::{
+    CancelToken, HealthState, MealRelation, ProtocolHandler, SampleStatus, SampleTime, Tester,
+    TimePolicy, UsbTransport,
+};
+use accuchek_core::{
+    self as core, nonblocking, DownloadOptions, GlucoseSample as CoreSample, SetTimeOptions,
+    SetTimeResult,
+};
 use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, State};
+
+/// Event carrying the [`accuchek_core::usb::Progress`] of a download
+const DOWNLOAD_PROGRESS_EVENT: &str = "download-progress";
+
+/// Cancel token of the download in progress, if any
+#[derive(Default)]
+struct DownloadState {
+    cancel: Mutex<Option<CancelToken>>,
+}
+
+impl DownloadState {
+    /// Cancel token of a new download, refused while another one runs
+    fn start(&self) -> Result<CancelToken, String> {
+        let mut cancel = self.cancel.lock().unwrap();
+        if cancel.is_some() {
+            return Err("A download is already in progress".to_string());
+        }
+        let token = CancelToken::new();
+        *cancel = Some(token.clone());
+        Ok(token)
+    }
+
+    fn finish(&self) {
+        self.cancel.lock().unwrap().take();
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GlucoseSample {
     pub id: usize,
     pub epoch: i64,
     pub timestamp: String,
+    pub time: SampleTime,
     #[serde(rename = "mg/dL")]
-    pub mg_dl: u16,
+    pub mg_dl: Option<u16>,
     #[serde(rename = "mmol/L")]
-    pub mmol_l: f64,
+    pub mmol_l: Option<f64>,
+    pub status: SampleStatus,
+    pub meal: Option<MealRelation>,
+    pub tester: Option<Tester>,
+    pub health: Option<HealthState>,
+    pub carbs_g: Option<f64>,
 }
 
 impl From<CoreSample> for GlucoseSample {
@@ -18,14 +59,22 @@ impl From<CoreSample> for GlucoseSample {
             id: s.id,
             epoch: s.epoch,
             timestamp: s.timestamp,
+            time: s.time,
             mg_dl: s.mg_dl,
             mmol_l: s.mmol_l,
+            status: s.status,
+            meal: s.meal,
+            tester: s.tester,
+            health: s.health,
+            carbs_g: s.carbs_g,
         }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DeviceInfo {
+    /// Serial number, or USB port path, selecting the meter in later commands
+    pub device_id: String,
     pub name: String,
     pub vendor_id: String,
     pub product_id: String,
@@ -39,14 +88,14 @@ async fn scan_devices() -> Result<Vec<DeviceInfo>, String> {
         .try_init()
         .ok();
 
-    let config = core::load_config().map_err(|e| format!("Failed to load config: {}", e))?;
-
-    let devices =
-        core::find_devices(&config).map_err(|e| format!("Failed to find devices: {}", e))?;
+    let devices = nonblocking::find_devices()
+        .await
+        .map_err(|e| format!("Failed to find devices: {}", e))?;
 
     Ok(devices
         .iter()
         .map(|d| DeviceInfo {
+            device_id: d.device_id().to_string(),
             name: d.name.clone(),
             vendor_id: format!("{:04x}", d.vendor_id),
             product_id: format!("{:04x}", d.product_id),
@@ -54,18 +103,100 @@ async fn scan_devices() -> Result<Vec<DeviceInfo>, String> {
         .collect())
 }
 
-// Tauri command to download glucose samples from a device
+// Tauri command to download glucose samples from a device, emitting its
+// progress as events
 #[tauri::command]
-async fn download_data(device_index: usize) -> Result<Vec<GlucoseSample>, String> {
+async fn download_data(
+    app: AppHandle,
+    state: State<'_, DownloadState>,
+    device_id: Option<String>,
+    include_flagged: Option<bool>,
+    timezone: Option<String>,
+    correct_clock: Option<bool>,
+) -> Result<Vec<GlucoseSample>, String> {
     env_logger::Builder::from_default_env()
         .filter_level(log::LevelFilter::Info)
         .try_init()
         .ok();
 
-    let config = core::load_config().map_err(|e| format!("Failed to load config: {}", e))?;
+    let options = DownloadOptions {
+        include_flagged: include_flagged.unwrap_or(false),
+        time_policy: parse_time_policy(timezone)?,
+        correct_clock: correct_clock.unwrap_or(false),
+        ..Default::default()
+    };
 
-    let devices =
-        core::find_devices(&config).map_err(|e| format!("Failed to find devices: {}", e))?;
+    let token = state.start()?;
+    let result = download_samples(app, device_id, options, token).await;
+    state.finish();
+    result
+}
+
+async fn download_samples(
+    app: AppHandle,
+    device_id: Option<String>,
+    options: DownloadOptions,
+    token: CancelToken,
+) -> Result<Vec<GlucoseSample>, String> {
+    let device_info = select_device(device_id).await?;
+
+    let result = nonblocking::run_blocking(move || {
+        let transport = UsbTransport::open(&device_info)?;
+        ProtocolHandler::new(transport)
+            .with_options(options)
+            .with_cancel_token(token)
+            .with_progress(move |progress| {
+                if let Err(e) = app.emit(DOWNLOAD_PROGRESS_EVENT, progress) {
+                    log::warn!("Failed to emit download progress: {}", e);
+                }
+            })
+            .download()
+    })
+    .await
+    .map_err(|e| format!("Failed to download samples: {}", e))?;
+
+    Ok(result
+        .samples
+        .into_iter()
+        .map(GlucoseSample::from)
+        .collect())
+}
+
+// Tauri command to stop the download in progress
+#[tauri::command]
+fn cancel_download(state: State<'_, DownloadState>) -> bool {
+    match state.cancel.lock().unwrap().as_ref() {
+        Some(token) => {
+            token.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+// Tauri command to set the meter clock to the host time
+#[tauri::command]
+async fn set_device_time(
+    device_id: Option<String>,
+    dry_run: Option<bool>,
+    timezone: Option<String>,
+) -> Result<SetTimeResult, String> {
+    let device_info = select_device(device_id).await?;
+
+    let options = SetTimeOptions {
+        time_policy: parse_time_policy(timezone)?,
+        dry_run: dry_run.unwrap_or(false),
+    };
+
+    nonblocking::set_device_time(device_info, options)
+        .await
+        .map_err(|e| format!("Failed to set device time: {}", e))
+}
+
+async fn select_device(device_id: Option<String>) -> Result<core::AccuChekDevice, String> {
+    let mut devices = nonblocking::find_devices()
+        .await
+        .map_err(|e| format!("Failed to find devices: {}", e))?;
 
     if devices.is_empty() {
         return Err(
@@ -74,20 +205,21 @@ async fn download_data(device_index: usize) -> Result<Vec<GlucoseSample>, String
         );
     }
 
-    if device_index >= devices.len() {
-        return Err(format!(
-            "Device index {} out of range (found {} devices)",
-            device_index,
-            devices.len()
-        ));
-    }
-
-    let device_info = &devices[device_index];
-
-    let samples =
-        core::download_samples(device_info).map_err(|e| format!("Failed to download samples: {}", e))?;
+    let Some(device_id) = device_id else {
+        return Ok(devices.swap_remove(0));
+    };
+    devices
+        .iter()
+        .position(|d| d.matches(&device_id))
+        .map(|position| devices.swap_remove(position))
+        .ok_or_else(|| format!("Device {} not found", device_id))
+}
 
-    Ok(samples.into_iter().map(GlucoseSample::from).collect())
+fn parse_time_policy(timezone: Option<String>) -> Result<TimePolicy, String> {
+    match timezone {
+        Some(zone) => zone.parse::<TimePolicy>().map_err(|e| e.to_string()),
+        None => Ok(TimePolicy::default()),
+    }
 }
 
 // Tauri command to export data to JSON file
@@ -96,8 +228,8 @@ async fn export_json(samples: Vec<GlucoseSample>, filename: String) -> Result<St
     use std::fs::File;
     use std::io::Write;
 
-    let json =
-        serde_json::to_string_pretty(&samples).map_err(|e| format!("Failed to serialize data: {}", e))?;
+    let json = serde_json::to_string_pretty(&samples)
+        .map_err(|e| format!("Failed to serialize data: {}", e))?;
 
     let mut file = File::create(&filename).map_err(|e| format!("Failed to create file: {}", e))?;
 
@@ -113,12 +245,28 @@ async fn export_csv(samples: Vec<GlucoseSample>, filename: String) -> Result<Str
     use std::fs::File;
     use std::io::Write;
 
-    let mut csv = String::from("ID,Timestamp,Epoch,mg/dL,mmol/L\n");
+    let mut csv =
+        String::from("ID,Timestamp,UTC,Epoch,mg/dL,mmol/L,Flags,Meal,Tester,Health,Carbs (g)\n");
 
     for sample in samples {
+        let flags: Vec<String> = sample.status.flags.iter().filter_map(label).collect();
+
         csv.push_str(&format!(
-            "{},{},{},{},{:.1}\n",
-            sample.id, sample.timestamp, sample.epoch, sample.mg_dl, sample.mmol_l
+            "{},{},{},{},{},{},{},{},{},{},{}\n",
+            sample.id,
+            sample.timestamp,
+            sample.time.utc.to_rfc3339(),
+            sample.epoch,
+            sample.mg_dl.map(|v| v.to_string()).unwrap_or_default(),
+            sample
+                .mmol_l
+                .map(|v| format!("{:.1}", v))
+                .unwrap_or_default(),
+            flags.join(" "),
+            sample.meal.as_ref().and_then(label).unwrap_or_default(),
+            sample.tester.as_ref().and_then(label).unwrap_or_default(),
+            sample.health.as_ref().and_then(label).unwrap_or_default(),
+            sample.carbs_g.map(|v| v.to_string()).unwrap_or_default()
         ));
     }
 
@@ -130,14 +278,24 @@ async fn export_csv(samples: Vec<GlucoseSample>, filename: String) -> Result<Str
     Ok(format!("Data exported to {}", filename))
 }
 
+/// Serialized name of a flag or context value, as used in the JSON export
+fn label<T: Serialize>(value: &T) -> Option<String> {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
+        .manage(DownloadState::default())
         .invoke_handler(tauri::generate_handler![
             scan_devices,
             download_data,
+            cancel_download,
+            set_device_time,
             export_json,
             export_csv
         ])
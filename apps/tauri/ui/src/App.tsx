@@ -1,19 +1,95 @@
 import { useState } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import { save } from "@tauri-apps/plugin-dialog";
 
 interface DeviceInfo {
+  device_id: string;
   name: string;
   vendor_id: string;
   product_id: string;
 }
 
+interface SampleStatus {
+  measurement_status: number;
+  device_status: number;
+  flags: string[];
+}
+
+interface SampleTime {
+  local: string;
+  utc: string;
+  offset_minutes: number;
+  resolution: "unique" | "ambiguous" | "nonexistent" | "declared";
+  clock_correction_seconds: number;
+}
+
 interface GlucoseSample {
   id: number;
   epoch: number;
   timestamp: string;
-  "mg/dL": number;
-  "mmol/L": number;
+  time: SampleTime;
+  "mg/dL": number | null;
+  "mmol/L": number | null;
+  status: SampleStatus;
+  meal: string | null;
+  tester: string | null;
+  health: string | null;
+  carbs_g: number | null;
+}
+
+type Progress =
+  | { kind: "phase"; phase: number; name: string }
+  | { kind: "segments-listed"; segments: number; expected_entries: number | null }
+  | {
+      kind: "entries-received";
+      segment: number;
+      received_entries: number;
+      decoded_samples: number;
+      expected_entries: number | null;
+    };
+
+interface DownloadProgress {
+  phase: string;
+  received: number;
+  expected: number | null;
+}
+
+const MEAL_LABELS: Record<string, string> = {
+  "before-meal": "Avant repas",
+  "after-meal": "Après repas",
+  fasting: "À jeun",
+  bedtime: "Coucher",
+  other: "Autre",
+};
+
+function formatValue(sample: GlucoseSample, unit: "mg/dL" | "mmol/L"): string {
+  const value = sample[unit];
+  if (value !== null) {
+    return unit === "mmol/L" ? value.toFixed(1) : String(value);
+  }
+  if (sample.status.flags.includes("result-too-high")) {
+    return "HI";
+  }
+  if (sample.status.flags.includes("result-too-low")) {
+    return "LO";
+  }
+  return "—";
+}
+
+function isAmbiguous(time: SampleTime): boolean {
+  return time.resolution === "ambiguous" || time.resolution === "nonexistent";
+}
+
+function updateProgress(current: DownloadProgress, event: Progress): DownloadProgress {
+  switch (event.kind) {
+    case "phase":
+      return { ...current, phase: event.name };
+    case "segments-listed":
+      return { ...current, expected: event.expected_entries };
+    case "entries-received":
+      return { ...current, received: event.received_entries, expected: event.expected_entries };
+  }
 }
 
 function App() {
@@ -21,8 +97,10 @@ function App() {
   const [samples, setSamples] = useState<GlucoseSample[]>([]);
   const [loading, setLoading] = useState(false);
   const [error, setError] = useState<string | null>(null);
-  const [selectedDevice, setSelectedDevice] = useState<number>(0);
+  const [selectedDevice, setSelectedDevice] = useState<string | null>(null);
   const [message, setMessage] = useState<string | null>(null);
+  const [progress, setProgress] = useState<DownloadProgress | null>(null);
+  const [cancelling, setCancelling] = useState(false);
 
   const scanDevices = async () => {
     setLoading(true);
@@ -31,6 +109,7 @@ function App() {
     try {
       const foundDevices = await invoke<DeviceInfo[]>("scan_devices");
       setDevices(foundDevices);
+      setSelectedDevice(foundDevices.length > 0 ? foundDevices[0].device_id : null);
       if (foundDevices.length === 0) {
         setError("Aucun appareil AccuChek trouvé. Assurez-vous que l'appareil est connecté et en mode transfert de données.");
       } else {
@@ -47,19 +126,40 @@ function App() {
     setLoading(true);
     setError(null);
     setMessage(null);
+    setCancelling(false);
+    setProgress({ phase: "", received: 0, expected: null });
+    const unlisten = await listen<Progress>("download-progress", (event) => {
+      setProgress((current) => current && updateProgress(current, event.payload));
+    });
     try {
       const data = await invoke<GlucoseSample[]>("download_data", {
-        deviceIndex: selectedDevice,
+        deviceId: selectedDevice,
+        includeFlagged: true,
       });
       setSamples(data);
       setMessage(`${data.length} mesure(s) téléchargée(s)`);
     } catch (err) {
-      setError(`Erreur lors du téléchargement: ${err}`);
+      if (String(err).includes("Cancelled")) {
+        setMessage("Téléchargement annulé");
+      } else {
+        setError(`Erreur lors du téléchargement: ${err}`);
+      }
     } finally {
+      unlisten();
+      setProgress(null);
       setLoading(false);
     }
   };
 
+  const cancelDownload = async () => {
+    setCancelling(true);
+    try {
+      await invoke<boolean>("cancel_download");
+    } catch (err) {
+      setError(`Erreur lors de l'annulation: ${err}`);
+    }
+  };
+
   const exportData = async (format: "json" | "csv") => {
     if (samples.length === 0) {
       setError("Aucune donnée à exporter");
@@ -115,20 +215,20 @@ function App() {
         {devices.length > 0 && (
           <div className="device-list">
             <h3>Appareils trouvés:</h3>
-            {devices.map((device, index) => (
-              <div key={index} className="device-item">
+            {devices.map((device) => (
+              <div key={device.device_id} className="device-item">
                 <input
                   type="radio"
                   name="device"
-                  id={`device-${index}`}
-                  checked={selectedDevice === index}
-                  onChange={() => setSelectedDevice(index)}
+                  id={`device-${device.device_id}`}
+                  checked={selectedDevice === device.device_id}
+                  onChange={() => setSelectedDevice(device.device_id)}
                 />
-                <label htmlFor={`device-${index}`}>
+                <label htmlFor={`device-${device.device_id}`}>
                   <strong>{device.name}</strong>
                   <br />
                   <small>
-                    Vendor: {device.vendor_id} | Product: {device.product_id}
+                    N° {device.device_id} | Vendor: {device.vendor_id} | Product: {device.product_id}
                   </small>
                 </label>
               </div>
@@ -143,6 +243,23 @@ function App() {
           <button onClick={downloadData} disabled={loading}>
             {loading ? "Téléchargement..." : "Télécharger"}
           </button>
+
+          {progress && (
+            <div className="download-progress">
+              <progress
+                value={progress.expected ? progress.received : undefined}
+                max={progress.expected ?? undefined}
+              />
+              <p className="progress-label">
+                {progress.expected
+                  ? `${progress.received} / ${progress.expected} mesures`
+                  : progress.phase}
+              </p>
+              <button onClick={cancelDownload} disabled={cancelling} className="cancel-btn">
+                {cancelling ? "Annulation..." : "Annuler"}
+              </button>
+            </div>
+          )}
         </div>
       )}
 
@@ -158,15 +275,24 @@ function App() {
                     <th>Date/Heure</th>
                     <th>mg/dL</th>
                     <th>mmol/L</th>
+                    <th>Repas</th>
+                    <th>Glucides (g)</th>
+                    <th>Statut</th>
                   </tr>
                 </thead>
                 <tbody>
                   {samples.slice(0, 10).map((sample) => (
                     <tr key={sample.id}>
                       <td>{sample.id}</td>
-                      <td>{sample.timestamp}</td>
-                      <td>{sample["mg/dL"]}</td>
-                      <td>{sample["mmol/L"].toFixed(1)}</td>
+                      <td title={`UTC ${sample.time.utc}`}>
+                        {sample.timestamp}
+                        {isAmbiguous(sample.time) && " (changement d'heure)"}
+                      </td>
+                      <td>{formatValue(sample, "mg/dL")}</td>
+                      <td>{formatValue(sample, "mmol/L")}</td>
+                      <td>{sample.meal ? MEAL_LABELS[sample.meal] ?? sample.meal : ""}</td>
+                      <td>{sample.carbs_g ?? ""}</td>
+                      <td>{sample.status.flags.join(", ")}</td>
                     </tr>
                   ))}
                 </tbody>
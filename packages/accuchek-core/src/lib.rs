@@ -3,6 +3,8 @@
 //! This library provides functionality to communicate with Roche AccuChek
 //! blood glucose monitoring devices via USB.
 
+#[cfg(feature = "async")]
+pub mod nonblocking;
 pub mod usb;
 
 use serde::{Deserialize, Serialize};
@@ -11,12 +13,121 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GlucoseSample {
     pub id: usize,
+    /// Seconds since the Unix epoch of [`GlucoseSample::time`]
     pub epoch: i64,
+    /// Meter-local time, formatted for display
     pub timestamp: String,
+    pub time: usb::SampleTime,
+    /// `None` for HI/LO and other readings without a value
     #[serde(rename = "mg/dL")]
-    pub mg_dl: u16,
+    pub mg_dl: Option<u16>,
     #[serde(rename = "mmol/L")]
-    pub mmol_l: f64,
+    pub mmol_l: Option<f64>,
+    pub status: usb::SampleStatus,
+    pub meal: Option<usb::MealRelation>,
+    pub tester: Option<usb::Tester>,
+    pub health: Option<usb::HealthState>,
+    /// Carbohydrate intake in grams
+    pub carbs_g: Option<f64>,
+}
+
+/// Options of a download
+#[derive(Debug, Clone, Default)]
+pub struct DownloadOptions {
+    /// Keep readings with status flags (HI/LO, control solution, strip errors...)
+    /// instead of dropping them
+    pub include_flagged: bool,
+    /// How the meter's local times are mapped to UTC
+    pub time_policy: usb::TimePolicy,
+    /// Take the meter clock drift measured at download out of the sample times
+    pub correct_clock: bool,
+    /// Only download readings newer than this cursor
+    pub since: Option<usb::SyncCursor>,
+    /// Largest APDU accepted from the meter, [`usb::DEFAULT_MAX_APDU_SIZE`]
+    /// when unset; the limit of the meter's device specializations applies
+    /// when it is smaller
+    pub max_apdu_size: Option<usize>,
+    pub timeouts: usb::Timeouts,
+    /// Retries of reads failing with a transient USB error
+    pub retry: usb::RetryPolicy,
+    /// When a segment transfer fails, return what was acknowledged until then
+    /// instead of the error
+    pub keep_partial: bool,
+}
+
+/// Everything learned from the meter during one download
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadResult {
+    /// Association request the meter opened the session with
+    pub association: usb::AssociationInfo,
+    /// Identity the meter reported in its MDS attributes
+    pub device: usb::DeviceIdentity,
+    /// Objects the meter declared in its configuration report
+    pub configuration: usb::DeviceConfiguration,
+    /// Meter clock compared to the host clock, when the meter reports one
+    pub clock: Option<usb::ClockCheck>,
+    /// PM-segments of the meter, including those left untransferred
+    pub segments: Vec<usb::PmSegment>,
+    pub samples: Vec<GlucoseSample>,
+    /// Segment entries transferred, including readings that were dropped;
+    /// what [`ClearOptions::confirm_count`] is checked against
+    pub entry_count: u32,
+    /// Newest reading seen, to pass as [`DownloadOptions::since`] next time
+    pub cursor: usb::SyncCursor,
+    /// Error that cut the transfer short with [`DownloadOptions::keep_partial`];
+    /// `samples` and `cursor` then cover the acknowledged entries only
+    pub interrupted: Option<String>,
+}
+
+impl DownloadResult {
+    /// Configuration to pass back on the next download so the meter can skip
+    /// its config report
+    pub fn known_configuration(&self) -> usb::KnownConfiguration {
+        usb::KnownConfiguration {
+            system_id: self.association.system_id.clone(),
+            dev_config_id: self.association.dev_config_id,
+            configuration: self.configuration.clone(),
+        }
+    }
+}
+
+/// Options of a set-time operation
+#[derive(Debug, Clone, Default)]
+pub struct SetTimeOptions {
+    /// Time zone the meter clock is kept in
+    pub time_policy: usb::TimePolicy,
+    /// Only report the current drift, leave the meter clock untouched
+    pub dry_run: bool,
+}
+
+/// Outcome of a set-time operation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetTimeResult {
+    pub device: usb::DeviceIdentity,
+    /// Meter clock before it was set
+    pub clock: Option<usb::ClockCheck>,
+    /// Local time sent to the meter, or that would be sent on a dry run
+    pub new_time: chrono::NaiveDateTime,
+    pub applied: bool,
+}
+
+/// Options of a clear operation
+#[derive(Debug, Clone, Default)]
+pub struct ClearOptions {
+    /// Number of entries the caller archived, as reported by
+    /// [`DownloadResult::entry_count`]
+    pub confirm_count: u32,
+    /// How readings are decoded in the download that precedes the clear
+    pub download: DownloadOptions,
+}
+
+/// Outcome of a clear operation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClearResult {
+    /// Everything the meter held before it was cleared
+    pub download: DownloadResult,
+    /// Instances of the cleared segments
+    pub cleared: Vec<u16>,
 }
 
 /// Represents information about a connected AccuChek device
@@ -41,7 +152,10 @@ pub struct SupportedDevice {
 }
 
 // Re-export main functions
-pub use usb::{find_devices, load_config, download_samples, AccuChekDevice};
+pub use usb::{
+    clear_segments, download, download_samples, find_devices, list_segments, load_config,
+    set_device_time, AccuChekDevice, ProtocolHandler, Transport,
+};
 
 /// Library version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -1,33 +1,106 @@
-mod usb;
-
+use accuchek_core::usb::{self, AccuChekDevice, ProtocolHandler, Transport};
+use accuchek_core::{ClearOptions, DownloadOptions, DownloadResult, SetTimeOptions};
 use anyhow::Result;
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
 use log::{info, warn};
-use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Device index to use (default: first found)
-    #[arg(short, long)]
-    device_index: Option<usize>,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Meter to use, by serial number or USB port path (default: first found)
+    #[arg(short, long, value_name = "SERIAL|PORT")]
+    device: Option<String>,
 
     /// Enable verbose logging
     #[arg(short, long)]
     verbose: bool,
+
+    /// Record every USB transfer of the session into a capture file
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Replay a capture file instead of talking to a device
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+
+    /// Keep HI/LO, control solution and other flagged readings
+    #[arg(long)]
+    include_flagged: bool,
+
+    /// Time zone of the meter clock: host, meter, an IANA name or a UTC offset
+    #[arg(long, value_name = "ZONE", default_value = "host")]
+    timezone: usb::TimePolicy,
+
+    /// Correct sample times by the drift of the meter clock
+    #[arg(long)]
+    correct_clock: bool,
+
+    /// Only download readings taken after this meter time (YYYY-MM-DDTHH:MM:SS)
+    #[arg(long, value_name = "TIME")]
+    since: Option<chrono::NaiveDateTime>,
+
+    /// Only download readings after this cursor, the JSON `cursor` of a
+    /// previous download, which also locates the last entry by index
+    #[arg(long, value_name = "JSON", conflicts_with = "since")]
+    cursor: Option<usb::SyncCursor>,
+
+    /// Largest APDU accepted from the meter, in bytes
+    #[arg(long, value_name = "BYTES")]
+    max_apdu_size: Option<usize>,
+
+    /// Seconds to wait for the meter in each phase of the session
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+
+    /// Retries of a USB read failing with a transient error
+    #[arg(long, value_name = "COUNT")]
+    retries: Option<u32>,
+
+    /// Output the readings received before a failed transfer instead of the error
+    #[arg(long)]
+    keep_partial: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct GlucoseSample {
-    id: usize,
-    epoch: i64,
-    timestamp: String,
-    #[serde(rename = "mg/dL")]
-    mg_dl: u16,
-    #[serde(rename = "mmol/L")]
-    mmol_l: f64,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Set the meter clock to the host time
+    SetTime {
+        /// Only report the current drift, leave the meter clock untouched
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List the PM-segments stored on the meter
+    Segments,
+    /// Download every reading, then erase the meter
+    Clear {
+        /// Number of entries archived from the meter (`entry_count` of the
+        /// last download); nothing is erased unless the meter holds exactly
+        /// this many
+        #[arg(long, value_name = "COUNT")]
+        confirm: u32,
+    },
+    /// Wait for meters to be plugged in and download each one on arrival
+    Watch {
+        /// Write each download to a JSON file in this directory instead of
+        /// standard output
+        #[arg(long, value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+
+        /// Rescan the USB bus instead of relying on hotplug notifications
+        #[arg(long)]
+        poll: bool,
+    },
 }
 
+/// Interval between two scans of the USB bus in watch mode
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
 fn main() -> Result<()> {
     let args = Args::parse();
 
@@ -44,43 +117,265 @@ fn main() -> Result<()> {
 
     info!("AccuChek Rust - Starting");
 
+    if let Some(Command::Watch { output_dir, poll }) = &args.command {
+        // Each meter is downloaded live, there is no single session to capture
+        if args.record.is_some() || args.replay.is_some() {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--record and --replay cannot be used with watch",
+                )
+                .exit();
+        }
+        return watch(&args, output_dir.as_deref(), *poll);
+    }
+
+    let output = if let Some(path) = &args.replay {
+        info!("Replaying capture {}", path.display());
+        run(usb::ReplayTransport::open(path)?, &args)?
+    } else {
+        let device_info = select_device(args.device.as_deref())?;
+        info!("Using device: {}", device_info.name);
+
+        let transport = usb::UsbTransport::open(&device_info)?;
+        match &args.record {
+            Some(path) => {
+                info!("Recording session to {}", path.display());
+                run(usb::RecordingTransport::create(transport, path)?, &args)?
+            }
+            None => run(transport, &args)?,
+        }
+    };
+
+    // Output JSON
+    println!("{}", serde_json::to_string_pretty(&output)?);
+
+    info!("AccuChek Rust - Done");
+    Ok(())
+}
+
+/// Run the requested command over `transport` and return its JSON output
+fn run<T: Transport>(transport: T, args: &Args) -> Result<serde_json::Value> {
+    let mut handler = ProtocolHandler::new(transport).with_options(download_options(args));
+
+    match args.command {
+        None => {
+            let result = handler.download()?;
+
+            report_interruption(&result)?;
+
+            if args.verbose {
+                eprintln!(
+                    "\n=== Downloaded {} samples ({} entries) ===",
+                    result.samples.len(),
+                    result.entry_count
+                );
+            }
+            Ok(serde_json::to_value(result.samples)?)
+        }
+        Some(Command::SetTime { dry_run }) => {
+            let options = SetTimeOptions {
+                time_policy: args.timezone,
+                dry_run,
+            };
+            let result = handler.set_device_time(&options)?;
+
+            match &result.clock {
+                Some(clock) => eprintln!(
+                    "Meter clock reads {} ({:+} s from host time)",
+                    clock.meter_time, clock.drift_seconds
+                ),
+                None => eprintln!("Meter did not report its clock"),
+            }
+            if result.applied {
+                eprintln!("Meter clock set to {}", result.new_time);
+            } else {
+                eprintln!("Dry run: meter clock would be set to {}", result.new_time);
+            }
+            Ok(serde_json::to_value(result)?)
+        }
+        Some(Command::Segments) => {
+            let segments = handler.list_segments()?;
+
+            if args.verbose {
+                eprintln!("\n=== Meter holds {} segments ===", segments.len());
+            }
+            Ok(serde_json::to_value(segments)?)
+        }
+        Some(Command::Clear { confirm }) => {
+            let options = ClearOptions {
+                confirm_count: confirm,
+                download: DownloadOptions {
+                    since: None,
+                    ..download_options(args)
+                },
+            };
+            let result = handler.clear_segments(&options)?;
+
+            eprintln!(
+                "Cleared segments {:?} ({} entries)",
+                result.cleared, result.download.entry_count
+            );
+            Ok(serde_json::to_value(result)?)
+        }
+        Some(Command::Watch { .. }) => unreachable!("watch opens its own devices"),
+    }
+}
+
+/// Download every meter plugged in, until the process is stopped
+fn watch(args: &Args, output_dir: Option<&Path>, poll: bool) -> Result<()> {
+    let config = usb::load_config()?;
+    let watcher = if poll {
+        usb::DeviceWatcher::polling(config, WATCH_POLL_INTERVAL)?
+    } else {
+        usb::DeviceWatcher::start(config, WATCH_POLL_INTERVAL)?
+    };
+    if let Some(dir) = output_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    eprintln!("Waiting for meters, press Ctrl-C to stop");
+
+    while let Some(event) = watcher.recv() {
+        let device_info = match event {
+            // Other meters are left alone when one is selected
+            usb::HotplugEvent::Arrived(device_info) | usb::HotplugEvent::Left(device_info)
+                if !is_selected(&device_info, args.device.as_deref()) =>
+            {
+                continue;
+            }
+            usb::HotplugEvent::Arrived(device_info) => device_info,
+            usb::HotplugEvent::Left(device_info) => {
+                eprintln!("{} disconnected", device_info.name);
+                continue;
+            }
+        };
+        eprintln!("{} connected, downloading...", device_info.name);
+
+        // A failed download should not stop the watch
+        let result = match accuchek_core::download(&device_info, download_options(args)) {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("Download from {} failed: {}", device_info.name, e);
+                continue;
+            }
+        };
+        eprintln!(
+            "Downloaded {} samples from {}{}",
+            result.samples.len(),
+            device_info.name,
+            if result.interrupted.is_some() {
+                " before the transfer failed"
+            } else {
+                ""
+            }
+        );
+        report_interruption(&result)?;
+
+        match output_dir {
+            Some(dir) => {
+                let meter = result
+                    .device
+                    .serial_number
+                    .clone()
+                    .unwrap_or_else(|| device_info.name.clone());
+                // Truncated archives must not pass for complete ones
+                let name = format!(
+                    "{}_{}{}.json",
+                    meter.replace(|c: char| !c.is_ascii_alphanumeric(), "-"),
+                    chrono::Local::now().format("%Y%m%d-%H%M%S"),
+                    if result.interrupted.is_some() {
+                        "_partial"
+                    } else {
+                        ""
+                    }
+                );
+                let path = dir.join(name);
+                std::fs::write(&path, serde_json::to_string_pretty(&result)?)?;
+                eprintln!("Saved to {}", path.display());
+            }
+            None => println!("{}", serde_json::to_string(&result)?),
+        }
+    }
+
+    Ok(())
+}
+
+/// Tell on stderr how to resume a transfer cut short with `--keep-partial`
+fn report_interruption(result: &DownloadResult) -> Result<()> {
+    if let Some(error) = &result.interrupted {
+        eprintln!("Transfer interrupted: {}", error);
+        if result.cursor != usb::SyncCursor::default() {
+            eprintln!(
+                "Resume with --cursor '{}'",
+                serde_json::to_string(&result.cursor)?
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Download options given on the command line
+fn download_options(args: &Args) -> DownloadOptions {
+    let mut options = DownloadOptions {
+        include_flagged: args.include_flagged,
+        time_policy: args.timezone,
+        correct_clock: args.correct_clock,
+        since: args.cursor.clone().or_else(|| {
+            args.since.map(|last_time| usb::SyncCursor {
+                last_time: Some(last_time),
+                last_entry: None,
+            })
+        }),
+        max_apdu_size: args.max_apdu_size,
+        keep_partial: args.keep_partial,
+        ..Default::default()
+    };
+    if let Some(seconds) = args.timeout {
+        options.timeouts = usb::Timeouts::uniform(Duration::from_secs(seconds));
+    }
+    if let Some(retries) = args.retries {
+        options.retry.retries = retries;
+    }
+    options
+}
+
+fn is_selected(device_info: &AccuChekDevice, selector: Option<&str>) -> bool {
+    selector.is_none_or(|selector| device_info.matches(selector))
+}
+
+fn select_device(selector: Option<&str>) -> Result<AccuChekDevice> {
     // Load device configuration
     let config = usb::load_config()?;
 
     // Find all matching devices
-    let devices = usb::find_devices(&config)?;
+    let mut devices = usb::find_devices(&config)?;
 
     if devices.is_empty() {
         warn!("No AccuChek devices found");
-        anyhow::bail!("No devices found. Make sure the device is connected and in data transfer mode.");
-    }
-
-    info!("Found {} device(s)", devices.len());
-
-    // Select device
-    let device_index = args.device_index.unwrap_or(0);
-    if device_index >= devices.len() {
         anyhow::bail!(
-            "Device index {} out of range (found {} devices)",
-            device_index,
-            devices.len()
+            "No devices found. Make sure the device is connected and in data transfer mode."
         );
     }
 
-    let device_info = &devices[device_index];
-    info!("Using device: {}", device_info.name);
-
-    // Connect and download data
-    let samples = usb::download_samples(device_info)?;
+    info!("Found {} device(s)", devices.len());
 
-    // Output JSON
-    if !args.verbose {
-        println!("{}", serde_json::to_string_pretty(&samples)?);
-    } else {
-        eprintln!("\n=== Downloaded {} samples ===", samples.len());
-        println!("{}", serde_json::to_string_pretty(&samples)?);
+    for device in &devices {
+        info!("  {} ({})", device.name, device.device_id());
     }
 
-    info!("AccuChek Rust - Done");
-    Ok(())
+    // Select device
+    let Some(selector) = selector else {
+        return Ok(devices.swap_remove(0));
+    };
+    match devices.iter().position(|d| d.matches(selector)) {
+        Some(position) => Ok(devices.swap_remove(position)),
+        None => {
+            let found: Vec<&str> = devices.iter().map(|d| d.device_id()).collect();
+            anyhow::bail!(
+                "No device matches {} (found {})",
+                selector,
+                found.join(", ")
+            )
+        }
+    }
 }
@@ -0,0 +1,114 @@
+//! Async versions of device discovery and the protocol operations
+//!
+//! USB transfers stay blocking: each call runs on a worker thread of its own
+//! and hands back a future resolving with the result. The futures need no
+//! particular executor, so GUI event loops and servers can await them without
+//! stalling.
+
+use crate::usb::{self, AccuChekDevice, PmSegment};
+use crate::{
+    ClearOptions, ClearResult, DownloadOptions, DownloadResult, GlucoseSample, SetTimeOptions,
+    SetTimeResult,
+};
+use anyhow::Result;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+/// Load the device configuration and scan the USB bus for meters
+pub async fn find_devices() -> Result<Vec<AccuChekDevice>> {
+    run_blocking(|| usb::find_devices(&usb::load_config()?)).await
+}
+
+/// See [`usb::download`]
+pub async fn download(
+    device_info: AccuChekDevice,
+    options: DownloadOptions,
+) -> Result<DownloadResult> {
+    run_blocking(move || usb::download(&device_info, options)).await
+}
+
+/// See [`usb::download_samples`]
+pub async fn download_samples(device_info: AccuChekDevice) -> Result<Vec<GlucoseSample>> {
+    run_blocking(move || usb::download_samples(&device_info)).await
+}
+
+/// See [`usb::set_device_time`]
+pub async fn set_device_time(
+    device_info: AccuChekDevice,
+    options: SetTimeOptions,
+) -> Result<SetTimeResult> {
+    run_blocking(move || usb::set_device_time(&device_info, options)).await
+}
+
+/// See [`usb::list_segments`]
+pub async fn list_segments(device_info: AccuChekDevice) -> Result<Vec<PmSegment>> {
+    run_blocking(move || usb::list_segments(&device_info)).await
+}
+
+/// See [`usb::clear_segments`]
+pub async fn clear_segments(
+    device_info: AccuChekDevice,
+    options: ClearOptions,
+) -> Result<ClearResult> {
+    run_blocking(move || usb::clear_segments(&device_info, options)).await
+}
+
+/// Run blocking work, such as a [`usb::ProtocolHandler`] session over a
+/// custom transport, on a worker thread
+///
+/// A panic of `work` is resumed where the future is polled.
+pub fn run_blocking<R, F>(work: F) -> Blocking<R>
+where
+    R: Send + 'static,
+    F: FnOnce() -> R + Send + 'static,
+{
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        waker: None,
+    }));
+
+    let worker = shared.clone();
+    thread::Builder::new()
+        .name("accuchek-usb".to_string())
+        .spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(work));
+            let mut shared = worker.lock().unwrap_or_else(|e| e.into_inner());
+            shared.result = Some(result);
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        })
+        .expect("failed to spawn the USB worker thread");
+
+    Blocking { shared }
+}
+
+/// Future of work running on a worker thread, see [`run_blocking`]
+pub struct Blocking<R> {
+    shared: Arc<Mutex<Shared<R>>>,
+}
+
+struct Shared<R> {
+    result: Option<thread::Result<R>>,
+    waker: Option<Waker>,
+}
+
+impl<R> Future for Blocking<R> {
+    type Output = R;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        match shared.result.take() {
+            Some(Ok(result)) => Poll::Ready(result),
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -1,346 +0,0 @@
-// Example architecture for multi-transport support (USB + BLE)
-// This file demonstrates the proposed trait-based design
-// NOT CURRENTLY COMPILED - See BLUETOOTH.md for implementation plan
-
-#![allow(dead_code, unused_variables, unused_imports)]
-
-use anyhow::Result;
-use std::time::Duration;
-
-/// Transport abstraction for USB and Bluetooth
-///
-/// This trait allows the protocol handler to work with both
-/// USB and BLE transports without modification.
-pub trait Transport {
-    /// Write data to the device
-    fn write(&mut self, data: &[u8]) -> Result<usize>;
-
-    /// Read data from the device
-    fn read(&mut self, buffer: &mut [u8]) -> Result<usize>;
-
-    /// Control transfer (USB-specific, no-op for BLE)
-    fn control_in(&mut self, buf: &mut [u8]) -> Result<usize> {
-        Ok(0) // Default: no-op for BLE
-    }
-}
-
-// ============================================================================
-// USB Implementation
-// ============================================================================
-
-pub struct UsbTransport {
-    handle: rusb::DeviceHandle<rusb::GlobalContext>,
-    bulk_in: u8,
-    bulk_out: u8,
-    timeout: Duration,
-}
-
-impl UsbTransport {
-    pub fn new(
-        handle: rusb::DeviceHandle<rusb::GlobalContext>,
-        bulk_in: u8,
-        bulk_out: u8,
-    ) -> Self {
-        Self {
-            handle,
-            bulk_in,
-            bulk_out,
-            timeout: Duration::from_secs(5),
-        }
-    }
-}
-
-impl Transport for UsbTransport {
-    fn write(&mut self, data: &[u8]) -> Result<usize> {
-        let written = self.handle.write_bulk(self.bulk_out, data, self.timeout)?;
-        Ok(written)
-    }
-
-    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
-        let read = self.handle.read_bulk(self.bulk_in, buffer, self.timeout)?;
-        Ok(read)
-    }
-
-    fn control_in(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let result = self.handle.read_control(
-            rusb::request_type(
-                rusb::Direction::In,
-                rusb::RequestType::Standard,
-                rusb::Recipient::Device,
-            ),
-            rusb::constants::LIBUSB_REQUEST_GET_STATUS,
-            0,
-            0,
-            buf,
-            self.timeout,
-        )?;
-        Ok(result)
-    }
-}
-
-// ============================================================================
-// BLE Implementation (requires btleplug)
-// ============================================================================
-
-/* Uncomment when btleplug is added:
-
-use btleplug::api::{Characteristic, Peripheral, WriteType};
-use uuid::Uuid;
-
-// Continua Health Alliance standard UUIDs
-// NOTE: These are EXAMPLES - real UUIDs must be obtained by scanning device
-const CONTINUA_SERVICE_UUID: Uuid = Uuid::from_u128(0x00001523_1212_efde_1523_785feabcd123);
-const CONTINUA_TX_CHAR_UUID: Uuid = Uuid::from_u128(0x00001524_1212_efde_1523_785feabcd123);
-const CONTINUA_RX_CHAR_UUID: Uuid = Uuid::from_u128(0x00001525_1212_efde_1523_785feabcd123);
-
-pub struct BleTransport {
-    peripheral: Box<dyn Peripheral>,
-    tx_characteristic: Characteristic,
-    rx_characteristic: Characteristic,
-    timeout: Duration,
-    buffer: std::collections::VecDeque<u8>,
-}
-
-impl BleTransport {
-    pub async fn new(peripheral: Box<dyn Peripheral>) -> Result<Self> {
-        // Connect to peripheral
-        peripheral.connect().await?;
-        peripheral.discover_services().await?;
-
-        // Find Continua characteristics
-        let characteristics = peripheral.characteristics();
-
-        let tx_char = characteristics
-            .iter()
-            .find(|c| c.uuid == CONTINUA_TX_CHAR_UUID)
-            .ok_or_else(|| anyhow::anyhow!("TX characteristic not found"))?
-            .clone();
-
-        let rx_char = characteristics
-            .iter()
-            .find(|c| c.uuid == CONTINUA_RX_CHAR_UUID)
-            .ok_or_else(|| anyhow::anyhow!("RX characteristic not found"))?
-            .clone();
-
-        // Subscribe to notifications
-        peripheral.subscribe(&rx_char).await?;
-
-        Ok(Self {
-            peripheral,
-            tx_characteristic: tx_char,
-            rx_characteristic: rx_char,
-            timeout: Duration::from_secs(5),
-            buffer: std::collections::VecDeque::new(),
-        })
-    }
-
-    async fn write_async(&mut self, data: &[u8]) -> Result<usize> {
-        // BLE has MTU limits - may need to fragment
-        let mtu = 512; // Typical max, can be negotiated
-
-        for chunk in data.chunks(mtu) {
-            self.peripheral
-                .write(&self.tx_characteristic, chunk, WriteType::WithResponse)
-                .await?;
-        }
-
-        Ok(data.len())
-    }
-
-    async fn read_async(&mut self, buffer: &mut [u8]) -> Result<usize> {
-        // Read from buffered notifications or wait for new ones
-        if self.buffer.is_empty() {
-            // Wait for notification with timeout
-            let notifications = self.peripheral.notifications().await?;
-            let result = tokio::time::timeout(self.timeout, notifications.recv()).await??;
-
-            // Buffer the received data
-            self.buffer.extend(&result.value);
-        }
-
-        // Copy from buffer to output
-        let len = buffer.len().min(self.buffer.len());
-        for i in 0..len {
-            buffer[i] = self.buffer.pop_front().unwrap();
-        }
-
-        Ok(len)
-    }
-}
-
-impl Transport for BleTransport {
-    fn write(&mut self, data: &[u8]) -> Result<usize> {
-        // Block on async write
-        futures::executor::block_on(self.write_async(data))
-    }
-
-    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
-        // Block on async read
-        futures::executor::block_on(self.read_async(buffer))
-    }
-
-    // control_in is no-op for BLE (uses default trait implementation)
-}
-
-*/
-
-// ============================================================================
-// Generic Protocol Handler
-// ============================================================================
-
-pub struct ProtocolHandler<T: Transport> {
-    transport: T,
-    buffer: Vec<u8>,
-    invoke_id: u16,
-    phase: usize,
-}
-
-impl<T: Transport> ProtocolHandler<T> {
-    pub fn new(transport: T) -> Self {
-        Self {
-            transport,
-            buffer: vec![0u8; 1024],
-            invoke_id: 0,
-            phase: 1,
-        }
-    }
-
-    pub fn execute(&mut self) -> Result<Vec<GlucoseSample>> {
-        // Phase 1: Initial control transfer (USB only, no-op for BLE)
-        self.control_transfer_in()?;
-
-        // Phase 2: Wait for pairing request
-        self.bulk_in("pairing request", 64)?;
-
-        // Phase 3-13: Same protocol for both USB and BLE
-        // The transport abstraction handles the differences
-
-        // ... rest of protocol implementation
-
-        Ok(vec![])
-    }
-
-    fn control_transfer_in(&mut self) -> Result<()> {
-        let mut buf = [0u8; 2];
-        self.transport.control_in(&mut buf)?;
-        self.phase += 1;
-        Ok(())
-    }
-
-    fn bulk_in(&mut self, name: &str, max_len: usize) -> Result<usize> {
-        self.buffer.resize(max_len, 0);
-        let bytes_read = self.transport.read(&mut self.buffer[..max_len])?;
-        self.phase += 1;
-        Ok(bytes_read)
-    }
-
-    fn bulk_out(&mut self, name: &str, data: &[u8]) -> Result<()> {
-        self.transport.write(data)?;
-        self.phase += 1;
-        Ok(())
-    }
-}
-
-// Placeholder for sample type
-#[derive(Debug)]
-pub struct GlucoseSample {
-    pub id: usize,
-    pub timestamp: String,
-    pub mg_dl: u16,
-}
-
-// ============================================================================
-// Usage Example
-// ============================================================================
-
-#[allow(unreachable_code, unused_mut)]
-pub fn example_usage() -> Result<()> {
-    // USB example (current implementation)
-    {
-        let devices = rusb::devices()?;
-        let device = devices.iter().next().unwrap();
-        let handle = device.open()?;
-
-        let usb = UsbTransport::new(handle, 0x81, 0x01);
-        let mut protocol = ProtocolHandler::new(usb);
-
-        let samples = protocol.execute()?;
-        println!("Downloaded {} samples via USB", samples.len());
-    }
-
-    /* BLE example (requires btleplug):
-    {
-        use btleplug::api::{Manager as _, Central as _};
-        use btleplug::platform::Manager;
-
-        let manager = Manager::new().await?;
-        let adapters = manager.adapters().await?;
-        let central = adapters.into_iter().next().unwrap();
-
-        central.start_scan(ScanFilter::default()).await?;
-        let peripherals = central.peripherals().await?;
-
-        let accuchek = peripherals
-            .into_iter()
-            .find(|p| {
-                // Find AccuChek device by name
-                p.properties().await.ok()
-                    .and_then(|props| props.local_name)
-                    .map(|name| name.contains("Accu-Chek"))
-                    .unwrap_or(false)
-            })
-            .ok_or_else(|| anyhow::anyhow!("AccuChek not found"))?;
-
-        let ble = BleTransport::new(Box::new(accuchek)).await?;
-        let mut protocol = ProtocolHandler::new(ble);
-
-        let samples = protocol.execute()?;
-        println!("Downloaded {} samples via BLE", samples.len());
-    }
-    */
-
-    Ok(())
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    // Mock transport for testing
-    struct MockTransport {
-        write_log: Vec<Vec<u8>>,
-        read_responses: Vec<Vec<u8>>,
-    }
-
-    impl Transport for MockTransport {
-        fn write(&mut self, data: &[u8]) -> Result<usize> {
-            self.write_log.push(data.to_vec());
-            Ok(data.len())
-        }
-
-        fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
-            if let Some(response) = self.read_responses.pop() {
-                let len = response.len().min(buffer.len());
-                buffer[..len].copy_from_slice(&response[..len]);
-                Ok(len)
-            } else {
-                Ok(0)
-            }
-        }
-    }
-
-    #[test]
-    fn test_protocol_with_mock_transport() {
-        let mock = MockTransport {
-            write_log: Vec::new(),
-            read_responses: vec![
-                vec![0xE2, 0x00], // Fake pairing request
-            ],
-        };
-
-        let mut handler = ProtocolHandler::new(mock);
-
-        // This would fail in real scenario but shows the pattern
-        // handler.execute().ok();
-    }
-}
@@ -0,0 +1,182 @@
+//! SFLOAT-Type and FLOAT-Type observation values
+//!
+//! Both are base-10 floats: a signed exponent (4 bits for SFLOAT, 8 bits for
+//! FLOAT) followed by a signed mantissa (12 or 24 bits). A handful of
+//! mantissa values with a zero exponent are reserved for special values.
+
+use super::nomenclature::*;
+
+/// Molar mass of glucose divided by 10: mg/dL per mmol/L
+pub const GLUCOSE_MG_DL_PER_MMOL_L: f64 = 18.016;
+
+/// Decoded SFLOAT-Type or FLOAT-Type value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MderFloat {
+    /// `mantissa * 10^exponent`
+    Finite {
+        mantissa: i32,
+        exponent: i8,
+    },
+    /// Not a number
+    NaN,
+    /// Not at this resolution
+    NRes,
+    PositiveInfinity,
+    NegativeInfinity,
+    /// Reserved for future use
+    Reserved,
+}
+
+impl MderFloat {
+    /// Decode a 16-bit SFLOAT-Type
+    pub fn from_sfloat(raw: u16) -> Self {
+        let exponent = (raw as i16 >> 12) as i8;
+        let mantissa = ((raw << 4) as i16 >> 4) as i32;
+
+        match raw {
+            0x07FF => MderFloat::NaN,
+            0x0800 => MderFloat::NRes,
+            0x07FE => MderFloat::PositiveInfinity,
+            0x0802 => MderFloat::NegativeInfinity,
+            0x0801 => MderFloat::Reserved,
+            _ => MderFloat::Finite { mantissa, exponent },
+        }
+    }
+
+    /// Decode a 32-bit FLOAT-Type
+    pub fn from_float(raw: u32) -> Self {
+        let exponent = (raw >> 24) as i8;
+        let mantissa = ((raw << 8) as i32) >> 8;
+
+        match raw {
+            0x007FFFFF => MderFloat::NaN,
+            0x00800000 => MderFloat::NRes,
+            0x007FFFFE => MderFloat::PositiveInfinity,
+            0x00800002 => MderFloat::NegativeInfinity,
+            0x00800001 => MderFloat::Reserved,
+            _ => MderFloat::Finite { mantissa, exponent },
+        }
+    }
+
+    /// Encode as SFLOAT-Type, `None` if the value does not fit
+    pub fn to_sfloat(self) -> Option<u16> {
+        match self {
+            MderFloat::NaN => Some(0x07FF),
+            MderFloat::NRes => Some(0x0800),
+            MderFloat::PositiveInfinity => Some(0x07FE),
+            MderFloat::NegativeInfinity => Some(0x0802),
+            MderFloat::Reserved => Some(0x0801),
+            MderFloat::Finite { mantissa, exponent } => {
+                if !(-2048..=2047).contains(&mantissa) || !(-8..=7).contains(&exponent) {
+                    return None;
+                }
+                Some(((exponent as u16) << 12) | (mantissa as u16 & 0x0FFF))
+            }
+        }
+    }
+
+    /// Numeric value; special values map to NaN or infinities
+    pub fn to_f64(self) -> f64 {
+        match self {
+            MderFloat::Finite { mantissa, exponent } => {
+                mantissa as f64 * 10f64.powi(exponent as i32)
+            }
+            MderFloat::PositiveInfinity => f64::INFINITY,
+            MderFloat::NegativeInfinity => f64::NEG_INFINITY,
+            MderFloat::NaN | MderFloat::NRes | MderFloat::Reserved => f64::NAN,
+        }
+    }
+
+    pub fn is_finite(self) -> bool {
+        matches!(self, MderFloat::Finite { .. })
+    }
+}
+
+/// Observation value with the unit it was reported in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumericValue {
+    pub value: MderFloat,
+    /// MDC_DIM_* unit code
+    pub unit_code: u16,
+}
+
+impl NumericValue {
+    /// Glucose concentration in mg/dL, `None` for special values and unknown units
+    pub fn mg_dl(&self) -> Option<f64> {
+        let value = self.finite()?;
+        match self.unit_code {
+            MDC_DIM_MILLI_G_PER_DL => Some(value),
+            MDC_DIM_MILLI_MOLE_PER_L => Some(value * GLUCOSE_MG_DL_PER_MMOL_L),
+            _ => None,
+        }
+    }
+
+    /// Glucose concentration in mmol/L, `None` for special values and unknown units
+    pub fn mmol_l(&self) -> Option<f64> {
+        let value = self.finite()?;
+        match self.unit_code {
+            MDC_DIM_MILLI_G_PER_DL => Some(value / GLUCOSE_MG_DL_PER_MMOL_L),
+            MDC_DIM_MILLI_MOLE_PER_L => Some(value),
+            _ => None,
+        }
+    }
+
+    fn finite(&self) -> Option<f64> {
+        self.value.is_finite().then(|| self.value.to_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_sfloat() {
+        assert_eq!(
+            MderFloat::from_sfloat(0x0069),
+            MderFloat::Finite {
+                mantissa: 105,
+                exponent: 0
+            }
+        );
+        assert!((MderFloat::from_sfloat(0xF03A).to_f64() - 5.8).abs() < 1e-9);
+        assert_eq!(MderFloat::from_sfloat(0x0FFF).to_f64(), -1.0);
+        assert_eq!(MderFloat::from_sfloat(0x07FF), MderFloat::NaN);
+        assert_eq!(MderFloat::from_sfloat(0x0800), MderFloat::NRes);
+        assert_eq!(MderFloat::from_sfloat(0x07FE), MderFloat::PositiveInfinity);
+        assert_eq!(MderFloat::from_sfloat(0x0802), MderFloat::NegativeInfinity);
+        assert_eq!(MderFloat::from_sfloat(0xF03A).to_sfloat(), Some(0xF03A));
+    }
+
+    #[test]
+    fn decodes_float() {
+        assert!((MderFloat::from_float(0xFE00_1FA4).to_f64() - 81.0).abs() < 1e-9);
+        assert_eq!(MderFloat::from_float(0x00FF_FFFF).to_f64(), -1.0);
+        assert_eq!(MderFloat::from_float(0x007F_FFFF), MderFloat::NaN);
+        assert_eq!(MderFloat::from_float(0x0080_0000), MderFloat::NRes);
+        assert_eq!(
+            MderFloat::from_float(0x007F_FFFE),
+            MderFloat::PositiveInfinity
+        );
+        assert_eq!(
+            MderFloat::from_float(0x0080_0002),
+            MderFloat::NegativeInfinity
+        );
+    }
+
+    #[test]
+    fn converts_glucose_units() {
+        let mmol = NumericValue {
+            value: MderFloat::from_sfloat(0xF03A),
+            unit_code: MDC_DIM_MILLI_MOLE_PER_L,
+        };
+        assert!((mmol.mg_dl().unwrap() - 104.4928).abs() < 1e-9);
+        assert!((mmol.mmol_l().unwrap() - 5.8).abs() < 1e-9);
+
+        let nres = NumericValue {
+            value: MderFloat::NRes,
+            unit_code: MDC_DIM_MILLI_G_PER_DL,
+        };
+        assert_eq!(nres.mg_dl(), None);
+    }
+}
@@ -0,0 +1,164 @@
+//! MDER (Medical Device Encoding Rules) primitives
+//!
+//! All integers are big-endian, octet strings and `ANY DEFINED BY` fields
+//! carry a 16-bit length prefix and lists are a 16-bit count followed by a
+//! 16-bit byte length.
+
+use crate::usb::UsbError;
+use anyhow::Result;
+
+/// Bounds-checked cursor over an MDER encoded buffer
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    /// Bytes left to read
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(UsbError::Parse(format!(
+                "Truncated data: need {} bytes at offset {}, only {} left",
+                len,
+                self.offset,
+                self.remaining()
+            ))
+            .into());
+        }
+
+        let bytes = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    pub fn skip(&mut self, len: usize) -> Result<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn i16(&mut self) -> Result<i16> {
+        Ok(self.u16()? as i16)
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Length-prefixed octet string
+    pub fn octet_string(&mut self) -> Result<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.bytes(len)
+    }
+
+    /// Reader limited to the next length-prefixed field
+    pub fn nested(&mut self) -> Result<Reader<'a>> {
+        Ok(Reader::new(self.octet_string()?))
+    }
+
+    /// Decode a counted list, checking both the count and the byte length
+    pub fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Reader<'a>) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        let count = self.u16()? as usize;
+        let mut list = self.nested()?;
+
+        let mut items = Vec::with_capacity(count.min(list.remaining()));
+        for _ in 0..count {
+            items.push(item(&mut list)?);
+        }
+
+        if !list.is_empty() {
+            return Err(UsbError::Parse(format!(
+                "List of {} items has {} trailing bytes",
+                count,
+                list.remaining()
+            ))
+            .into());
+        }
+
+        Ok(items)
+    }
+}
+
+/// MDER encoder computing length fields as it goes
+#[derive(Debug, Clone, Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(data);
+        self
+    }
+
+    pub fn u8(&mut self, val: u8) -> &mut Self {
+        self.buf.push(val);
+        self
+    }
+
+    pub fn u16(&mut self, val: u16) -> &mut Self {
+        self.bytes(&val.to_be_bytes())
+    }
+
+    pub fn i16(&mut self, val: i16) -> &mut Self {
+        self.u16(val as u16)
+    }
+
+    pub fn u32(&mut self, val: u32) -> &mut Self {
+        self.bytes(&val.to_be_bytes())
+    }
+
+    /// Length-prefixed octet string
+    pub fn octet_string(&mut self, data: &[u8]) -> &mut Self {
+        self.u16(data.len() as u16).bytes(data)
+    }
+
+    /// Length-prefixed field whose content is written by `content`
+    pub fn nested(&mut self, content: impl FnOnce(&mut Writer)) -> &mut Self {
+        let mut inner = Writer::new();
+        content(&mut inner);
+        self.octet_string(&inner.buf)
+    }
+
+    /// Counted list with its byte length
+    pub fn list<T>(&mut self, items: &[T], mut item: impl FnMut(&mut Writer, &T)) -> &mut Self {
+        self.u16(items.len() as u16);
+        self.nested(|w| {
+            for value in items {
+                item(w, value);
+            }
+        })
+    }
+}
@@ -0,0 +1,812 @@
+//! Typed ISO/IEEE 11073-20601 APDUs
+//!
+//! Every message exchanged with the meter is modelled here and encoded or
+//! decoded with MDER. Length fields are always computed from the content and
+//! every read is bounds-checked, so a malformed message is reported as a
+//! [`UsbError::Parse`] instead of being read at a wrong offset.
+
+pub mod float;
+pub mod mder;
+pub mod nomenclature;
+
+use super::UsbError;
+use anyhow::Result;
+use mder::{Reader, Writer};
+use nomenclature::*;
+
+/// Application protocol data unit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Apdu {
+    /// AARQ: association request
+    AssociationRequest(AssociationRequest),
+    /// AARE: association response
+    AssociationResponse(AssociationResponse),
+    /// RLRQ: association release request
+    ReleaseRequest(ReleaseReason),
+    /// RLRE: association release response
+    ReleaseResponse(ReleaseReason),
+    /// ABRT: association abort
+    Abort(AbortReason),
+    /// PRST: presentation APDU carrying a data APDU
+    Presentation(DataApdu),
+}
+
+impl Apdu {
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut r = Reader::new(data);
+        let choice = r.u16()?;
+        let mut body = r.nested()?;
+
+        let apdu = match choice {
+            APDU_TYPE_ASSOCIATION_REQUEST => {
+                Apdu::AssociationRequest(AssociationRequest::decode(&mut body)?)
+            }
+            APDU_TYPE_ASSOCIATION_RESPONSE => {
+                Apdu::AssociationResponse(AssociationResponse::decode(&mut body)?)
+            }
+            APDU_TYPE_ASSOCIATION_RELEASE_REQUEST => {
+                Apdu::ReleaseRequest(ReleaseReason(body.u16()?))
+            }
+            APDU_TYPE_ASSOCIATION_RELEASE_RESPONSE => {
+                Apdu::ReleaseResponse(ReleaseReason(body.u16()?))
+            }
+            APDU_TYPE_ASSOCIATION_ABORT => Apdu::Abort(AbortReason(body.u16()?)),
+            APDU_TYPE_PRESENTATION_APDU => {
+                Apdu::Presentation(DataApdu::decode(&mut body.nested()?)?)
+            }
+            other => {
+                return Err(UsbError::Parse(format!("Unknown APDU type 0x{:04x}", other)).into());
+            }
+        };
+
+        Ok(apdu)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        match self {
+            Apdu::AssociationRequest(aarq) => {
+                w.u16(APDU_TYPE_ASSOCIATION_REQUEST)
+                    .nested(|w| aarq.encode(w));
+            }
+            Apdu::AssociationResponse(aare) => {
+                w.u16(APDU_TYPE_ASSOCIATION_RESPONSE)
+                    .nested(|w| aare.encode(w));
+            }
+            Apdu::ReleaseRequest(reason) => {
+                w.u16(APDU_TYPE_ASSOCIATION_RELEASE_REQUEST).nested(|w| {
+                    w.u16(reason.0);
+                });
+            }
+            Apdu::ReleaseResponse(reason) => {
+                w.u16(APDU_TYPE_ASSOCIATION_RELEASE_RESPONSE).nested(|w| {
+                    w.u16(reason.0);
+                });
+            }
+            Apdu::Abort(reason) => {
+                w.u16(APDU_TYPE_ASSOCIATION_ABORT).nested(|w| {
+                    w.u16(reason.0);
+                });
+            }
+            Apdu::Presentation(data) => {
+                w.u16(APDU_TYPE_PRESENTATION_APDU).nested(|w| {
+                    w.nested(|w| data.encode(w));
+                });
+            }
+        }
+        w.into_bytes()
+    }
+
+    /// Short name used in logs and errors
+    pub fn name(&self) -> &'static str {
+        match self {
+            Apdu::AssociationRequest(_) => "AARQ",
+            Apdu::AssociationResponse(_) => "AARE",
+            Apdu::ReleaseRequest(_) => "RLRQ",
+            Apdu::ReleaseResponse(_) => "RLRE",
+            Apdu::Abort(_) => "ABRT",
+            Apdu::Presentation(data) => data.message.name(),
+        }
+    }
+}
+
+/// Reason carried by RLRQ and RLRE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReleaseReason(pub u16);
+
+impl ReleaseReason {
+    pub const NORMAL: ReleaseReason = ReleaseReason(0);
+}
+
+/// Reason carried by ABRT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbortReason(pub u16);
+
+impl AbortReason {
+    pub const UNDEFINED: AbortReason = AbortReason(0);
+    pub const BUFFER_OVERFLOW: AbortReason = AbortReason(1);
+    pub const RESPONSE_TIMEOUT: AbortReason = AbortReason(2);
+    pub const CONFIGURATION_TIMEOUT: AbortReason = AbortReason(3);
+}
+
+/// AARQ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssociationRequest {
+    pub assoc_version: u32,
+    pub data_protos: Vec<DataProto>,
+}
+
+impl AssociationRequest {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        Ok(Self {
+            assoc_version: r.u32()?,
+            data_protos: r.list(DataProto::decode)?,
+        })
+    }
+
+    fn encode(&self, w: &mut Writer) {
+        w.u32(self.assoc_version)
+            .list(&self.data_protos, |w, proto| proto.encode(w));
+    }
+}
+
+/// AARE
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssociationResponse {
+    pub result: u16,
+    pub selected_data_proto: DataProto,
+}
+
+impl AssociationResponse {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        Ok(Self {
+            result: r.u16()?,
+            selected_data_proto: DataProto::decode(r)?,
+        })
+    }
+
+    fn encode(&self, w: &mut Writer) {
+        w.u16(self.result);
+        self.selected_data_proto.encode(w);
+    }
+}
+
+/// Data protocol identifier with its (still encoded) protocol information
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataProto {
+    pub id: u16,
+    pub info: Vec<u8>,
+}
+
+impl DataProto {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        Ok(Self {
+            id: r.u16()?,
+            info: r.octet_string()?.to_vec(),
+        })
+    }
+
+    fn encode(&self, w: &mut Writer) {
+        w.u16(self.id).octet_string(&self.info);
+    }
+}
+
+/// PhdAssociationInformation, the 20601 data protocol information
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhdAssociationInformation {
+    pub protocol_version: u32,
+    pub encoding_rules: u16,
+    pub nomenclature_version: u32,
+    pub functional_units: u32,
+    pub system_type: u32,
+    pub system_id: Vec<u8>,
+    pub dev_config_id: u16,
+    pub data_req_mode_flags: u16,
+    pub data_req_init_agent_count: u8,
+    pub data_req_init_manager_count: u8,
+    pub option_list: AttributeList,
+}
+
+impl PhdAssociationInformation {
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut r = Reader::new(data);
+        Ok(Self {
+            protocol_version: r.u32()?,
+            encoding_rules: r.u16()?,
+            nomenclature_version: r.u32()?,
+            functional_units: r.u32()?,
+            system_type: r.u32()?,
+            system_id: r.octet_string()?.to_vec(),
+            dev_config_id: r.u16()?,
+            data_req_mode_flags: r.u16()?,
+            data_req_init_agent_count: r.u8()?,
+            data_req_init_manager_count: r.u8()?,
+            option_list: AttributeList::decode(&mut r)?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u32(self.protocol_version)
+            .u16(self.encoding_rules)
+            .u32(self.nomenclature_version)
+            .u32(self.functional_units)
+            .u32(self.system_type)
+            .octet_string(&self.system_id)
+            .u16(self.dev_config_id)
+            .u16(self.data_req_mode_flags)
+            .u8(self.data_req_init_agent_count)
+            .u8(self.data_req_init_manager_count);
+        self.option_list.encode(&mut w);
+        w.into_bytes()
+    }
+}
+
+/// DataApdu: invoke-id and remote operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataApdu {
+    pub invoke_id: u16,
+    pub message: DataMessage,
+}
+
+impl DataApdu {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        let invoke_id = r.u16()?;
+        let choice = r.u16()?;
+        let mut body = r.nested()?;
+
+        let message = match choice {
+            DATA_APDU_INVOKE_EVENT_REPORT => {
+                DataMessage::EventReport(EventReport::decode(&mut body)?)
+            }
+            DATA_APDU_INVOKE_CONFIRMED_EVENT_REPORT => {
+                DataMessage::ConfirmedEventReport(EventReport::decode(&mut body)?)
+            }
+            DATA_APDU_INVOKE_GET => DataMessage::Get(GetArgument::decode(&mut body)?),
+            DATA_APDU_INVOKE_ACTION => DataMessage::Action(Action::decode(&mut body)?),
+            DATA_APDU_INVOKE_CONFIRMED_ACTION => {
+                DataMessage::ConfirmedAction(Action::decode(&mut body)?)
+            }
+            DATA_APDU_RESPONSE_CONFIRMED_EVENT_REPORT => {
+                DataMessage::EventReportResult(EventReport::decode(&mut body)?)
+            }
+            DATA_APDU_RESPONSE_GET => DataMessage::GetResult(GetResult::decode(&mut body)?),
+            DATA_APDU_RESPONSE_CONFIRMED_ACTION => {
+                DataMessage::ActionResult(Action::decode(&mut body)?)
+            }
+            DATA_APDU_ERROR => DataMessage::Error(ErrorResult::decode(&mut body)?),
+            DATA_APDU_REJECT => DataMessage::Reject(RejectResult {
+                problem: body.u16()?,
+            }),
+            other => {
+                return Err(
+                    UsbError::Parse(format!("Unknown data APDU choice 0x{:04x}", other)).into(),
+                );
+            }
+        };
+
+        Ok(Self { invoke_id, message })
+    }
+
+    fn encode(&self, w: &mut Writer) {
+        w.u16(self.invoke_id).u16(self.message.choice());
+        w.nested(|w| match &self.message {
+            DataMessage::EventReport(report)
+            | DataMessage::ConfirmedEventReport(report)
+            | DataMessage::EventReportResult(report) => report.encode(w),
+            DataMessage::Get(get) => get.encode(w),
+            DataMessage::GetResult(result) => result.encode(w),
+            DataMessage::Action(action)
+            | DataMessage::ConfirmedAction(action)
+            | DataMessage::ActionResult(action) => action.encode(w),
+            DataMessage::Error(error) => error.encode(w),
+            DataMessage::Reject(reject) => {
+                w.u16(reject.problem);
+            }
+        });
+    }
+}
+
+/// Remote operation invoke (ROIV), result (RORS), error (ROER) or reject (RORJ)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataMessage {
+    EventReport(EventReport),
+    ConfirmedEventReport(EventReport),
+    Get(GetArgument),
+    Action(Action),
+    ConfirmedAction(Action),
+    EventReportResult(EventReport),
+    GetResult(GetResult),
+    ActionResult(Action),
+    Error(ErrorResult),
+    Reject(RejectResult),
+}
+
+impl DataMessage {
+    pub fn choice(&self) -> u16 {
+        match self {
+            DataMessage::EventReport(_) => DATA_APDU_INVOKE_EVENT_REPORT,
+            DataMessage::ConfirmedEventReport(_) => DATA_APDU_INVOKE_CONFIRMED_EVENT_REPORT,
+            DataMessage::Get(_) => DATA_APDU_INVOKE_GET,
+            DataMessage::Action(_) => DATA_APDU_INVOKE_ACTION,
+            DataMessage::ConfirmedAction(_) => DATA_APDU_INVOKE_CONFIRMED_ACTION,
+            DataMessage::EventReportResult(_) => DATA_APDU_RESPONSE_CONFIRMED_EVENT_REPORT,
+            DataMessage::GetResult(_) => DATA_APDU_RESPONSE_GET,
+            DataMessage::ActionResult(_) => DATA_APDU_RESPONSE_CONFIRMED_ACTION,
+            DataMessage::Error(_) => DATA_APDU_ERROR,
+            DataMessage::Reject(_) => DATA_APDU_REJECT,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DataMessage::EventReport(_) => "roiv-cmip-event-report",
+            DataMessage::ConfirmedEventReport(_) => "roiv-cmip-confirmed-event-report",
+            DataMessage::Get(_) => "roiv-cmip-get",
+            DataMessage::Action(_) => "roiv-cmip-action",
+            DataMessage::ConfirmedAction(_) => "roiv-cmip-confirmed-action",
+            DataMessage::EventReportResult(_) => "rors-cmip-confirmed-event-report",
+            DataMessage::GetResult(_) => "rors-cmip-get",
+            DataMessage::ActionResult(_) => "rors-cmip-confirmed-action",
+            DataMessage::Error(_) => "roer",
+            DataMessage::Reject(_) => "rorj",
+        }
+    }
+}
+
+/// EventReportArgumentSimple, also used for EventReportResultSimple whose
+/// layout is identical (the time is then the manager's current time)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventReport {
+    pub obj_handle: u16,
+    pub event_time: u32,
+    pub event_type: u16,
+    pub info: Vec<u8>,
+}
+
+impl EventReport {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        Ok(Self {
+            obj_handle: r.u16()?,
+            event_time: r.u32()?,
+            event_type: r.u16()?,
+            info: r.octet_string()?.to_vec(),
+        })
+    }
+
+    fn encode(&self, w: &mut Writer) {
+        w.u16(self.obj_handle)
+            .u32(self.event_time)
+            .u16(self.event_type)
+            .octet_string(&self.info);
+    }
+}
+
+/// GetArgumentSimple; an empty id list asks for every attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetArgument {
+    pub obj_handle: u16,
+    pub attribute_ids: Vec<u16>,
+}
+
+impl GetArgument {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        Ok(Self {
+            obj_handle: r.u16()?,
+            attribute_ids: r.list(|r| r.u16())?,
+        })
+    }
+
+    fn encode(&self, w: &mut Writer) {
+        w.u16(self.obj_handle).list(&self.attribute_ids, |w, id| {
+            w.u16(*id);
+        });
+    }
+}
+
+/// GetResultSimple
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetResult {
+    pub obj_handle: u16,
+    pub attributes: AttributeList,
+}
+
+impl GetResult {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        Ok(Self {
+            obj_handle: r.u16()?,
+            attributes: AttributeList::decode(r)?,
+        })
+    }
+
+    fn encode(&self, w: &mut Writer) {
+        w.u16(self.obj_handle);
+        self.attributes.encode(w);
+    }
+}
+
+/// ActionArgumentSimple, also used for ActionResultSimple
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Action {
+    pub obj_handle: u16,
+    pub action_type: u16,
+    pub info: Vec<u8>,
+}
+
+impl Action {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        Ok(Self {
+            obj_handle: r.u16()?,
+            action_type: r.u16()?,
+            info: r.octet_string()?.to_vec(),
+        })
+    }
+
+    fn encode(&self, w: &mut Writer) {
+        w.u16(self.obj_handle)
+            .u16(self.action_type)
+            .octet_string(&self.info);
+    }
+}
+
+/// ErrorResult (ROER)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorResult {
+    pub error_value: u16,
+    pub parameter: Vec<u8>,
+}
+
+impl ErrorResult {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        Ok(Self {
+            error_value: r.u16()?,
+            parameter: r.octet_string()?.to_vec(),
+        })
+    }
+
+    fn encode(&self, w: &mut Writer) {
+        w.u16(self.error_value).octet_string(&self.parameter);
+    }
+}
+
+/// RejectResult (RORJ)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectResult {
+    pub problem: u16,
+}
+
+/// Attribute value assertion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ava {
+    pub id: u16,
+    pub value: Vec<u8>,
+}
+
+/// AttributeList
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AttributeList(pub Vec<Ava>);
+
+impl AttributeList {
+    pub fn decode(r: &mut Reader) -> Result<Self> {
+        let attributes = r.list(|r| {
+            Ok(Ava {
+                id: r.u16()?,
+                value: r.octet_string()?.to_vec(),
+            })
+        })?;
+        Ok(Self(attributes))
+    }
+
+    pub fn encode(&self, w: &mut Writer) {
+        w.list(&self.0, |w, ava| {
+            w.u16(ava.id).octet_string(&ava.value);
+        });
+    }
+
+    /// Value of the first attribute with this id
+    pub fn get(&self, id: u16) -> Option<&[u8]> {
+        self.0
+            .iter()
+            .find(|ava| ava.id == id)
+            .map(|ava| ava.value.as_slice())
+    }
+
+    pub fn push(&mut self, id: u16, value: Vec<u8>) {
+        self.0.push(Ava { id, value });
+    }
+}
+
+/// ConfigReport (MDC_NOTI_CONFIG event info)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigReport {
+    pub config_report_id: u16,
+    pub objects: Vec<ConfigObject>,
+}
+
+impl ConfigReport {
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut r = Reader::new(data);
+        Ok(Self {
+            config_report_id: r.u16()?,
+            objects: r.list(|r| {
+                Ok(ConfigObject {
+                    obj_class: r.u16()?,
+                    obj_handle: r.u16()?,
+                    attributes: AttributeList::decode(r)?,
+                })
+            })?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u16(self.config_report_id)
+            .list(&self.objects, |w, object| {
+                w.u16(object.obj_class).u16(object.obj_handle);
+                object.attributes.encode(w);
+            });
+        w.into_bytes()
+    }
+}
+
+/// ConfigObject
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigObject {
+    pub obj_class: u16,
+    pub obj_handle: u16,
+    pub attributes: AttributeList,
+}
+
+/// ConfigReportRsp
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigReportResponse {
+    pub config_report_id: u16,
+    pub config_result: u16,
+}
+
+impl ConfigReportResponse {
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut r = Reader::new(data);
+        Ok(Self {
+            config_report_id: r.u16()?,
+            config_result: r.u16()?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u16(self.config_report_id).u16(self.config_result);
+        w.into_bytes()
+    }
+}
+
+/// SegmSelection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SegmentSelection {
+    AllSegments,
+    Segments(Vec<u16>),
+}
+
+impl SegmentSelection {
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut r = Reader::new(data);
+        let choice = r.u16()?;
+        let mut body = r.nested()?;
+        match choice {
+            1 => Ok(SegmentSelection::AllSegments),
+            2 => Ok(SegmentSelection::Segments(body.list(|r| r.u16())?)),
+            other => {
+                Err(UsbError::Parse(format!("Unsupported segment selection {}", other)).into())
+            }
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        match self {
+            SegmentSelection::AllSegments => {
+                w.u16(1).nested(|w| {
+                    w.u16(0);
+                });
+            }
+            SegmentSelection::Segments(ids) => {
+                w.u16(2).nested(|w| {
+                    w.list(ids, |w, id| {
+                        w.u16(*id);
+                    });
+                });
+            }
+        }
+        w.into_bytes()
+    }
+}
+
+/// One entry of SegmentInfoList
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentInfo {
+    pub instance: u16,
+    pub attributes: AttributeList,
+}
+
+impl SegmentInfo {
+    /// Decode the SegmentInfoList answered to MDC_ACT_SEG_GET_INFO
+    pub fn list_from_bytes(data: &[u8]) -> Result<Vec<Self>> {
+        Reader::new(data).list(|r| {
+            Ok(Self {
+                instance: r.u16()?,
+                attributes: AttributeList::decode(r)?,
+            })
+        })
+    }
+
+    pub fn list_to_bytes(list: &[Self]) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.list(list, |w, info| {
+            w.u16(info.instance);
+            info.attributes.encode(w);
+        });
+        w.into_bytes()
+    }
+}
+
+/// TrigSegmDataXferRsp
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TriggerTransferResponse {
+    pub instance: u16,
+    pub result: u16,
+}
+
+impl TriggerTransferResponse {
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut r = Reader::new(data);
+        Ok(Self {
+            instance: r.u16()?,
+            result: r.u16()?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u16(self.instance).u16(self.result);
+        w.into_bytes()
+    }
+}
+
+/// SegmDataEventDescr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentDataDescriptor {
+    pub instance: u16,
+    pub entry_index: u32,
+    pub entry_count: u32,
+    pub status: u16,
+}
+
+impl SegmentDataDescriptor {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        Ok(Self {
+            instance: r.u16()?,
+            entry_index: r.u32()?,
+            entry_count: r.u32()?,
+            status: r.u16()?,
+        })
+    }
+
+    fn encode(&self, w: &mut Writer) {
+        w.u16(self.instance)
+            .u32(self.entry_index)
+            .u32(self.entry_count)
+            .u16(self.status);
+    }
+
+    /// SegmentDataResult confirming this event
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        self.encode(&mut w);
+        w.into_bytes()
+    }
+}
+
+/// SegmentDataEvent (MDC_NOTI_SEGMENT_DATA event info)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentDataEvent {
+    pub descriptor: SegmentDataDescriptor,
+    pub entries: Vec<u8>,
+}
+
+impl SegmentDataEvent {
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut r = Reader::new(data);
+        Ok(Self {
+            descriptor: SegmentDataDescriptor::decode(&mut r)?,
+            entries: r.octet_string()?.to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        self.descriptor.encode(&mut w);
+        w.octet_string(&self.entries);
+        w.into_bytes()
+    }
+}
+
+/// Lowercase hex of raw bytes, as system-ids and opaque fields are reported
+pub(crate) fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_request_matches_wire_format() {
+        let apdu = Apdu::ReleaseRequest(ReleaseReason::NORMAL);
+        assert_eq!(apdu.encode(), vec![0xE4, 0x00, 0x00, 0x02, 0x00, 0x00]);
+        assert_eq!(Apdu::decode(&apdu.encode()).unwrap(), apdu);
+    }
+
+    #[test]
+    fn presentation_lengths_are_computed() {
+        let apdu = Apdu::Presentation(DataApdu {
+            invoke_id: 7,
+            message: DataMessage::Get(GetArgument {
+                obj_handle: 0,
+                attribute_ids: vec![],
+            }),
+        });
+
+        let bytes = apdu.encode();
+        assert_eq!(
+            bytes,
+            vec![
+                0xE7, 0x00, 0x00, 0x0E, 0x00, 0x0C, 0x00, 0x07, 0x01, 0x03, 0x00, 0x06, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00
+            ]
+        );
+        assert_eq!(Apdu::decode(&bytes).unwrap(), apdu);
+    }
+
+    #[test]
+    fn config_report_round_trips() {
+        let mut attributes = AttributeList::default();
+        attributes.push(MDC_ATTR_NUM_SEG, vec![0x00, 0x01]);
+        let report = ConfigReport {
+            config_report_id: EXTENDED_CONFIG_START,
+            objects: vec![ConfigObject {
+                obj_class: MDC_MOC_VMO_PMSTORE,
+                obj_handle: 0x0100,
+                attributes,
+            }],
+        };
+
+        assert_eq!(
+            ConfigReport::from_bytes(&report.to_bytes()).unwrap(),
+            report
+        );
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected() {
+        let bytes = Apdu::Presentation(DataApdu {
+            invoke_id: 1,
+            message: DataMessage::Reject(RejectResult { problem: 3 }),
+        })
+        .encode();
+
+        for len in 0..bytes.len() {
+            assert!(
+                Apdu::decode(&bytes[..len]).is_err(),
+                "accepted {} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn list_count_must_match_length() {
+        // count says 2 attributes but only one is present
+        let data = [0x00, 0x02, 0x00, 0x06, 0x09, 0x96, 0x00, 0x02, 0x08, 0x52];
+        assert!(AttributeList::decode(&mut Reader::new(&data)).is_err());
+    }
+}
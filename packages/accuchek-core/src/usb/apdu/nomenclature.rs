@@ -0,0 +1,196 @@
+//! Nomenclature codes from ISO/IEEE 11073-20601 and 11073-10101
+
+// APDU choices
+pub const APDU_TYPE_ASSOCIATION_REQUEST: u16 = 0xE200;
+pub const APDU_TYPE_ASSOCIATION_RESPONSE: u16 = 0xE300;
+pub const APDU_TYPE_ASSOCIATION_RELEASE_REQUEST: u16 = 0xE400;
+pub const APDU_TYPE_ASSOCIATION_RELEASE_RESPONSE: u16 = 0xE500;
+pub const APDU_TYPE_ASSOCIATION_ABORT: u16 = 0xE600;
+pub const APDU_TYPE_PRESENTATION_APDU: u16 = 0xE700;
+
+// Data APDU message choices
+pub const DATA_APDU_INVOKE_EVENT_REPORT: u16 = 0x0100;
+pub const DATA_APDU_INVOKE_CONFIRMED_EVENT_REPORT: u16 = 0x0101;
+pub const DATA_APDU_INVOKE_GET: u16 = 0x0103;
+pub const DATA_APDU_INVOKE_ACTION: u16 = 0x0106;
+pub const DATA_APDU_INVOKE_CONFIRMED_ACTION: u16 = 0x0107;
+pub const DATA_APDU_RESPONSE_CONFIRMED_EVENT_REPORT: u16 = 0x0201;
+pub const DATA_APDU_RESPONSE_GET: u16 = 0x0203;
+pub const DATA_APDU_RESPONSE_CONFIRMED_ACTION: u16 = 0x0207;
+pub const DATA_APDU_ERROR: u16 = 0x0300;
+pub const DATA_APDU_REJECT: u16 = 0x0400;
+
+// RORJ problems
+pub const RORJ_UNRECOGNIZED_APDU: u16 = 0;
+pub const RORJ_BADLY_STRUCTURED_APDU: u16 = 2;
+pub const RORJ_UNRECOGNIZED_OPERATION: u16 = 101;
+pub const RORJ_RESOURCE_LIMITATION: u16 = 103;
+pub const RORJ_UNEXPECTED_ERROR: u16 = 303;
+
+// Association
+pub const ASSOC_VERSION_1: u32 = 0x80000000;
+pub const DATA_PROTO_ID_20601: u16 = 20601;
+pub const PROTOCOL_VERSION_1: u32 = 0x80000000;
+pub const PROTOCOL_VERSION_2: u32 = 0x40000000;
+pub const ENCODING_RULES_MDER: u16 = 0x8000;
+pub const NOMENCLATURE_VERSION_1: u32 = 0x80000000;
+pub const SYS_TYPE_MANAGER: u32 = 0x80000000;
+pub const SYS_TYPE_AGENT: u32 = 0x00800000;
+
+// Events
+pub const MDC_NOTI_CONFIG: u16 = 0x0D1C;
+pub const MDC_NOTI_SEGMENT_DATA: u16 = 0x0D21;
+
+// Actions
+pub const MDC_ACT_SEG_CLR: u16 = 0x0C0C;
+pub const MDC_ACT_SEG_GET_INFO: u16 = 0x0C0D;
+pub const MDC_ACT_SET_TIME: u16 = 0x0C17;
+pub const MDC_ACT_SEG_TRIG_XFER: u16 = 0x0C1C;
+
+// Object classes
+pub const MDC_MOC_VMO_METRIC_ENUM: u16 = 5;
+pub const MDC_MOC_VMO_METRIC_NU: u16 = 6;
+pub const MDC_MOC_VMO_PMSTORE: u16 = 61;
+
+// Attributes
+pub const MDC_ATTR_ID_MODEL: u16 = 0x0928;
+pub const MDC_ATTR_ID_PROD_SPECN: u16 = 0x092D;
+pub const MDC_ATTR_ID_TYPE: u16 = 0x092F;
+pub const MDC_ATTR_MSMT_STAT: u16 = 0x0947;
+pub const MDC_ATTR_NUM_SEG: u16 = 0x0951;
+pub const MDC_ATTR_POWER_STAT: u16 = 0x0955;
+pub const MDC_ATTR_SEG_USAGE_CNT: u16 = 0x097B;
+pub const MDC_ATTR_SYS_ID: u16 = 0x0984;
+pub const MDC_ATTR_TIME_ABS: u16 = 0x0987;
+pub const MDC_ATTR_TIME_STAMP_ABS: u16 = 0x0990;
+pub const MDC_ATTR_UNIT_CODE: u16 = 0x0996;
+pub const MDC_ATTR_VAL_BATT_CHARGE: u16 = 0x099C;
+pub const MDC_ATTR_MDS_TIME_INFO: u16 = 0x0A45;
+pub const MDC_ATTR_METRIC_SPEC_SMALL: u16 = 0x0A46;
+pub const MDC_ATTR_ENUM_OBS_VAL_SIMP_OID: u16 = 0x0A49;
+pub const MDC_ATTR_REG_CERT_DATA_LIST: u16 = 0x0A4B;
+pub const MDC_ATTR_NU_VAL_OBS_BASIC: u16 = 0x0A4C;
+pub const MDC_ATTR_PM_STORE_CAPAB: u16 = 0x0A4D;
+pub const MDC_ATTR_PM_SEG_MAP: u16 = 0x0A4E;
+pub const MDC_ATTR_ATTRIBUTE_VAL_MAP: u16 = 0x0A55;
+pub const MDC_ATTR_NU_VAL_OBS_SIMP: u16 = 0x0A56;
+pub const MDC_ATTR_SYS_TYPE_SPEC_LIST: u16 = 0x0A5A;
+pub const MDC_ATTR_ENUM_OBS_VAL_BASIC_BIT_STR: u16 = 0x0A66;
+pub const MDC_ATTR_TIME_START_SEG: u16 = 0x0A79;
+pub const MDC_ATTR_TIME_END_SEG: u16 = 0x0A7A;
+pub const MDC_ATTR_TIME_BO: u16 = 0x0A82;
+pub const MDC_ATTR_PM_SEG_LABEL_STRING: u16 = 0x0A8B;
+
+// PM-store capabilities
+pub const PMSC_VAR_NO_OF_SEGM: u16 = 0x8000;
+pub const PMSC_EPI_SEG_ENTRIES: u16 = 0x0800;
+pub const PMSC_PERI_SEG_ENTRIES: u16 = 0x0400;
+pub const PMSC_ABS_TIME_SELECT: u16 = 0x0200;
+pub const PMSC_CLEAR_SEGM_BY_LIST_SUP: u16 = 0x0100;
+pub const PMSC_CLEAR_SEGM_BY_TIME_SUP: u16 = 0x0080;
+pub const PMSC_CLEAR_SEGM_REMOVE: u16 = 0x0040;
+pub const PMSC_CLEAR_SEGM_ALL_SUP: u16 = 0x0020;
+pub const PMSC_MULTI_PERSON: u16 = 0x0008;
+
+// Mds-Time-Info capabilities and state
+pub const MDS_TIME_CAPAB_REAL_TIME_CLOCK: u16 = 0x8000;
+pub const MDS_TIME_CAPAB_SET_CLOCK: u16 = 0x4000;
+pub const MDS_TIME_CAPAB_RELATIVE_TIME: u16 = 0x2000;
+pub const MDS_TIME_CAPAB_HIGH_RES_RELATIVE_TIME: u16 = 0x1000;
+pub const MDS_TIME_CAPAB_SYNC_ABS_TIME: u16 = 0x0800;
+pub const MDS_TIME_CAPAB_SYNC_REL_TIME: u16 = 0x0400;
+pub const MDS_TIME_CAPAB_SYNC_HI_RES_RELATIVE_TIME: u16 = 0x0200;
+pub const MDS_TIME_STATE_ABS_TIME_SYNCED: u16 = 0x0080;
+pub const MDS_TIME_STATE_REL_TIME_SYNCED: u16 = 0x0040;
+pub const MDS_TIME_STATE_HI_RES_RELATIVE_TIME_SYNCED: u16 = 0x0020;
+pub const MDS_TIME_MGR_SET_TIME: u16 = 0x0010;
+
+// PM-segment entry header
+pub const SEG_ELEM_HDR_ABSOLUTE_TIME: u16 = 0x8000;
+pub const SEG_ELEM_HDR_RELATIVE_TIME: u16 = 0x4000;
+pub const SEG_ELEM_HDR_HIRES_RELATIVE_TIME: u16 = 0x2000;
+pub const SEG_ELEM_HDR_BO_TIME: u16 = 0x1000;
+
+// Measurement status
+pub const MSMT_STATUS_INVALID: u16 = 0x8000;
+pub const MSMT_STATUS_QUESTIONABLE: u16 = 0x4000;
+pub const MSMT_STATUS_NOT_AVAILABLE: u16 = 0x2000;
+pub const MSMT_STATUS_CALIBRATION_ONGOING: u16 = 0x1000;
+pub const MSMT_STATUS_TEST_DATA: u16 = 0x0800;
+pub const MSMT_STATUS_DEMO_DATA: u16 = 0x0400;
+pub const MSMT_STATUS_VALIDATED_DATA: u16 = 0x0080;
+pub const MSMT_STATUS_EARLY_INDICATION: u16 = 0x0040;
+pub const MSMT_STATUS_MSMT_ONGOING: u16 = 0x0020;
+
+// Glucose meter device and sensor annunciation (MDC_GLU_METER_DEV_STATUS)
+pub const GLU_DEV_STATUS_BATTERY_LOW: u16 = 0x8000;
+pub const GLU_DEV_STATUS_SENSOR_MALFUNCTION: u16 = 0x4000;
+pub const GLU_DEV_STATUS_SAMPLE_SIZE_INSUFFICIENT: u16 = 0x2000;
+pub const GLU_DEV_STATUS_STRIP_INSERTION: u16 = 0x1000;
+pub const GLU_DEV_STATUS_STRIP_TYPE_INCORRECT: u16 = 0x0800;
+pub const GLU_DEV_STATUS_RESULT_TOO_HIGH: u16 = 0x0400;
+pub const GLU_DEV_STATUS_RESULT_TOO_LOW: u16 = 0x0200;
+pub const GLU_DEV_STATUS_TEMP_TOO_HIGH: u16 = 0x0100;
+pub const GLU_DEV_STATUS_TEMP_TOO_LOW: u16 = 0x0080;
+pub const GLU_DEV_STATUS_READ_INTERRUPT: u16 = 0x0040;
+pub const GLU_DEV_STATUS_GENERAL_FAULT: u16 = 0x0020;
+
+// Device specializations, partition MDC_PART_INFRA
+pub const MDC_DEV_SPEC_PROFILE_GLUCOSE: u16 = 4113;
+
+// Production specification entry types
+pub const PROD_SPEC_SERIAL_NUMBER: u16 = 1;
+pub const PROD_SPEC_PART_NUMBER: u16 = 2;
+pub const PROD_SPEC_HW_REVISION: u16 = 3;
+pub const PROD_SPEC_SW_REVISION: u16 = 4;
+pub const PROD_SPEC_FW_REVISION: u16 = 5;
+pub const PROD_SPEC_PROTOCOL_REVISION: u16 = 6;
+
+// Power status
+pub const POWER_STATUS_ON_MAINS: u16 = 0x8000;
+pub const POWER_STATUS_ON_BATTERY: u16 = 0x4000;
+
+// Partitions, metric types and units
+pub const MDC_PART_SCADA: u16 = 2;
+pub const MDC_CONC_GLU_CAPILLARY_WHOLEBLOOD: u16 = 29112;
+pub const MDC_CONC_GLU_CONTROL: u16 = 29136;
+pub const MDC_GLU_METER_DEV_STATUS: u16 = 29144;
+pub const MDC_DIM_MILLI_G_PER_DL: u16 = 2130;
+pub const MDC_DIM_MILLI_MOLE_PER_L: u16 = 4722;
+pub const MDC_DIM_X_G: u16 = 1728;
+
+// Glucose context (IEEE 11073-10417), partition MDC_PART_PHD_DM
+pub const MDC_PART_PHD_DM: u16 = 128;
+pub const MDC_CTXT_GLU_CARB: u16 = 29156;
+pub const MDC_CTXT_GLU_HEALTH: u16 = 29212;
+pub const MDC_CTXT_GLU_HEALTH_MINOR: u16 = 29216;
+pub const MDC_CTXT_GLU_HEALTH_MAJOR: u16 = 29220;
+pub const MDC_CTXT_GLU_HEALTH_MENSES: u16 = 29224;
+pub const MDC_CTXT_GLU_HEALTH_STRESS: u16 = 29228;
+pub const MDC_CTXT_GLU_HEALTH_NONE: u16 = 29232;
+pub const MDC_CTXT_GLU_MEAL: u16 = 29256;
+pub const MDC_CTXT_GLU_MEAL_PREPRANDIAL: u16 = 29260;
+pub const MDC_CTXT_GLU_MEAL_POSTPRANDIAL: u16 = 29264;
+pub const MDC_CTXT_GLU_MEAL_FASTING: u16 = 29268;
+pub const MDC_CTXT_GLU_MEAL_CASUAL: u16 = 29272;
+pub const MDC_CTXT_GLU_TESTER: u16 = 29276;
+pub const MDC_CTXT_GLU_TESTER_SELF: u16 = 29280;
+pub const MDC_CTXT_GLU_TESTER_HCP: u16 = 29284;
+pub const MDC_CTXT_GLU_TESTER_LAB: u16 = 29288;
+pub const MDC_CTXT_GLU_MEAL_BEDTIME: u16 = 29300;
+
+// Config report
+pub const EXTENDED_CONFIG_START: u16 = 0x4000;
+pub const CONFIG_RESULT_ACCEPTED: u16 = 0;
+
+// Segment data event status
+pub const SEGM_EVT_STATUS_FIRST_ENTRY: u16 = 0x8000;
+pub const SEGM_EVT_STATUS_LAST_ENTRY: u16 = 0x4000;
+pub const SEGM_EVT_STATUS_MANAGER_CONFIRM: u16 = 0x0080;
+
+// Segment transfer trigger results
+pub const TSXR_SUCCESSFUL: u16 = 0;
+pub const TSXR_FAIL_NO_SUCH_SEGMENT: u16 = 1;
+pub const TSXR_FAIL_SEGM_TRY_LATER: u16 = 2;
+pub const TSXR_FAIL_SEGM_EMPTY: u16 = 3;
+pub const TSXR_FAIL_OTHER: u16 = 512;
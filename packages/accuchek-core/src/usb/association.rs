@@ -0,0 +1,187 @@
+//! Association request decoding and acceptance policy
+
+use super::apdu::nomenclature::*;
+use super::apdu::{hex_encode, AssociationRequest, AttributeList, PhdAssociationInformation};
+use super::DeviceConfiguration;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Protocol versions this manager speaks, newest first
+const SUPPORTED_PROTOCOL_VERSIONS: [u32; 2] = [PROTOCOL_VERSION_2, PROTOCOL_VERSION_1];
+
+/// EUI-64 the manager announces in its association responses
+///
+/// Locally administered (bit 1 of the first octet set) so it cannot collide
+/// with a vendor-assigned identifier.
+pub const MANAGER_SYSTEM_ID: [u8; 8] = [0x02, 0x41, 0x43, 0x43, 0x55, 0x43, 0x48, 0x4B];
+
+/// Association request of the meter, as decoded from its AARQ
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssociationInfo {
+    pub assoc_version: u32,
+    pub data_proto_id: u16,
+    pub protocol_version: u32,
+    pub encoding_rules: u16,
+    pub nomenclature_version: u32,
+    pub functional_units: u32,
+    pub system_type: u32,
+    /// Agent system-id (EUI-64), hex encoded
+    pub system_id: String,
+    pub dev_config_id: u16,
+}
+
+/// Result codes of an AARE
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AssociationResult {
+    Accepted,
+    RejectedPermanent,
+    RejectedTransient,
+    AcceptedUnknownConfig,
+    RejectedNoCommonProtocol,
+    RejectedNoCommonParameter,
+    RejectedUnknown,
+    RejectedUnauthorized,
+    RejectedUnsupportedAssocVersion,
+}
+
+impl AssociationResult {
+    pub fn code(self) -> u16 {
+        match self {
+            AssociationResult::Accepted => 0,
+            AssociationResult::RejectedPermanent => 1,
+            AssociationResult::RejectedTransient => 2,
+            AssociationResult::AcceptedUnknownConfig => 3,
+            AssociationResult::RejectedNoCommonProtocol => 4,
+            AssociationResult::RejectedNoCommonParameter => 5,
+            AssociationResult::RejectedUnknown => 6,
+            AssociationResult::RejectedUnauthorized => 7,
+            AssociationResult::RejectedUnsupportedAssocVersion => 8,
+        }
+    }
+
+    pub fn is_accepted(self) -> bool {
+        matches!(
+            self,
+            AssociationResult::Accepted | AssociationResult::AcceptedUnknownConfig
+        )
+    }
+}
+
+/// Configuration already received from a meter in an earlier session
+///
+/// When the meter announces the same system-id and dev-config-id again, the
+/// association is accepted as a known configuration and the meter skips
+/// sending its config report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownConfiguration {
+    /// Agent system-id (EUI-64), hex encoded
+    pub system_id: String,
+    pub dev_config_id: u16,
+    pub configuration: DeviceConfiguration,
+}
+
+/// Outcome of evaluating an AARQ
+#[derive(Debug, Clone)]
+pub(crate) struct AssociationDecision {
+    pub result: AssociationResult,
+    /// Decoded request, when it could be decoded at all
+    pub info: Option<AssociationInfo>,
+    /// Protocol version to answer with
+    pub protocol_version: u32,
+    pub known_configuration: Option<KnownConfiguration>,
+}
+
+/// Decide how to answer an association request
+pub(crate) fn evaluate(
+    request: &AssociationRequest,
+    known: &[KnownConfiguration],
+) -> Result<AssociationDecision> {
+    let mut decision = AssociationDecision {
+        result: AssociationResult::RejectedUnknown,
+        info: None,
+        protocol_version: PROTOCOL_VERSION_1,
+        known_configuration: None,
+    };
+
+    if request.assoc_version & ASSOC_VERSION_1 == 0 {
+        decision.result = AssociationResult::RejectedUnsupportedAssocVersion;
+        return Ok(decision);
+    }
+
+    let Some(proto) = request
+        .data_protos
+        .iter()
+        .find(|proto| proto.id == DATA_PROTO_ID_20601)
+    else {
+        decision.result = AssociationResult::RejectedNoCommonProtocol;
+        return Ok(decision);
+    };
+
+    let phd = PhdAssociationInformation::from_bytes(&proto.info)?;
+    let info = AssociationInfo {
+        assoc_version: request.assoc_version,
+        data_proto_id: proto.id,
+        protocol_version: phd.protocol_version,
+        encoding_rules: phd.encoding_rules,
+        nomenclature_version: phd.nomenclature_version,
+        functional_units: phd.functional_units,
+        system_type: phd.system_type,
+        system_id: hex_encode(&phd.system_id),
+        dev_config_id: phd.dev_config_id,
+    };
+
+    let protocol_version = SUPPORTED_PROTOCOL_VERSIONS
+        .iter()
+        .copied()
+        .find(|version| phd.protocol_version & version != 0);
+
+    decision.result = match protocol_version {
+        None => AssociationResult::RejectedNoCommonParameter,
+        Some(_) if phd.encoding_rules & ENCODING_RULES_MDER == 0 => {
+            AssociationResult::RejectedNoCommonParameter
+        }
+        Some(_) if phd.nomenclature_version & NOMENCLATURE_VERSION_1 == 0 => {
+            AssociationResult::RejectedNoCommonParameter
+        }
+        Some(_) => {
+            decision.known_configuration = known
+                .iter()
+                .find(|c| c.system_id == info.system_id && c.dev_config_id == info.dev_config_id)
+                .cloned();
+
+            if decision.known_configuration.is_some() {
+                AssociationResult::Accepted
+            } else {
+                AssociationResult::AcceptedUnknownConfig
+            }
+        }
+    };
+
+    if let Some(version) = protocol_version {
+        decision.protocol_version = version;
+    }
+    decision.info = Some(info);
+
+    Ok(decision)
+}
+
+/// Association information the manager answers with
+pub(crate) fn manager_information(
+    protocol_version: u32,
+    system_id: &[u8; 8],
+) -> PhdAssociationInformation {
+    PhdAssociationInformation {
+        protocol_version,
+        encoding_rules: ENCODING_RULES_MDER,
+        nomenclature_version: NOMENCLATURE_VERSION_1,
+        functional_units: 0,
+        system_type: SYS_TYPE_MANAGER,
+        system_id: system_id.to_vec(),
+        dev_config_id: 0, // manager-config
+        data_req_mode_flags: 0,
+        data_req_init_agent_count: 0,
+        data_req_init_manager_count: 0,
+        option_list: AttributeList::default(),
+    }
+}
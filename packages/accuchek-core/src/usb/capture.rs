@@ -0,0 +1,296 @@
+//! Record-and-replay of USB sessions
+//!
+//! A capture file holds one JSON [`CaptureRecord`] per line, written as the
+//! session goes so that a crashed download still leaves a usable file.
+//! [`ReplayTransport`] feeds such a file back into the protocol handler.
+
+use super::apdu::hex_encode;
+use super::{Transport, UsbError};
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+const TIMEOUT_ERROR: &str = "timeout";
+
+/// Kind of transfer a record was captured from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+    ControlIn,
+    BulkOut,
+    BulkIn,
+}
+
+/// One transfer of a captured session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    pub phase: usize,
+    pub name: String,
+    pub direction: TransferDirection,
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: i64,
+    /// Transferred bytes, hex encoded
+    pub data: String,
+    /// Error reported by the transport instead of data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CaptureRecord {
+    /// Decoded bytes of the record
+    pub fn bytes(&self) -> Result<Vec<u8>> {
+        hex_decode(&self.data)
+    }
+}
+
+/// Transport wrapper writing every transfer of the inner transport to a capture
+pub struct RecordingTransport<T: Transport, W: Write = BufWriter<File>> {
+    inner: T,
+    writer: W,
+    phase: usize,
+    name: String,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    /// Record into a new capture file at `path`
+    pub fn create<P: AsRef<Path>>(inner: T, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create capture file {}", path.display()))?;
+        Ok(Self::new(inner, BufWriter::new(file)))
+    }
+}
+
+impl<T: Transport, W: Write> RecordingTransport<T, W> {
+    pub fn new(inner: T, writer: W) -> Self {
+        Self {
+            inner,
+            writer,
+            phase: 0,
+            name: String::new(),
+        }
+    }
+
+    /// Give back the inner transport and the capture writer
+    pub fn into_inner(self) -> (T, W) {
+        (self.inner, self.writer)
+    }
+
+    fn record(&mut self, direction: TransferDirection, result: &Result<usize>, data: &[u8]) {
+        let (data, error) = match result {
+            Ok(len) => (hex_encode(&data[..(*len).min(data.len())]), None),
+            Err(e) => (String::new(), Some(describe_error(e))),
+        };
+
+        let record = CaptureRecord {
+            phase: self.phase,
+            name: self.name.clone(),
+            direction,
+            timestamp_ms: chrono::Utc::now().timestamp_millis(),
+            data,
+            error,
+        };
+
+        // A failing capture must never break the session being captured
+        let written = serde_json::to_string(&record)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| {
+                writeln!(self.writer, "{}", line)?;
+                self.writer.flush()?;
+                Ok(())
+            });
+        if let Err(e) = written {
+            warn!("Failed to write capture record: {}", e);
+        }
+    }
+}
+
+impl<T: Transport, W: Write> Transport for RecordingTransport<T, W> {
+    fn write(&mut self, data: &[u8]) -> Result<usize> {
+        let result = self.inner.write(data);
+        self.record(TransferDirection::BulkOut, &result, data);
+        result
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let result = self.inner.read(buffer);
+        self.record(TransferDirection::BulkIn, &result, buffer);
+        result
+    }
+
+    fn control_in(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let result = self.inner.control_in(buffer);
+        self.record(TransferDirection::ControlIn, &result, buffer);
+        result
+    }
+
+    fn begin_phase(&mut self, phase: usize, name: &str) {
+        self.phase = phase;
+        self.name = name.to_string();
+        self.inner.begin_phase(phase, name);
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        self.inner.set_timeout(timeout);
+    }
+}
+
+/// Transport answering from a capture instead of a meter
+///
+/// Reads return the captured bulk IN transfers in order. Writes are checked
+/// against the captured bulk OUT transfers and differences are logged, so a
+/// modified protocol handler can still be driven by an older capture.
+pub struct ReplayTransport {
+    control: VecDeque<CaptureRecord>,
+    incoming: VecDeque<CaptureRecord>,
+    outgoing: VecDeque<CaptureRecord>,
+    mismatches: usize,
+}
+
+impl ReplayTransport {
+    /// Load a capture file written by [`RecordingTransport`]
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open capture file {}", path.display()))?;
+        Self::from_reader(BufReader::new(file))
+    }
+
+    /// Load a capture from any line-oriented reader
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Self> {
+        let mut records = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: CaptureRecord = serde_json::from_str(&line)
+                .with_context(|| format!("Invalid capture record on line {}", i + 1))?;
+            records.push(record);
+        }
+        Ok(Self::from_records(records))
+    }
+
+    pub fn from_records(records: Vec<CaptureRecord>) -> Self {
+        let mut replay = Self {
+            control: VecDeque::new(),
+            incoming: VecDeque::new(),
+            outgoing: VecDeque::new(),
+            mismatches: 0,
+        };
+
+        for record in records {
+            match record.direction {
+                TransferDirection::ControlIn => replay.control.push_back(record),
+                TransferDirection::BulkIn => replay.incoming.push_back(record),
+                TransferDirection::BulkOut => replay.outgoing.push_back(record),
+            }
+        }
+
+        replay
+    }
+
+    /// Number of host writes that differed from the capture
+    pub fn mismatches(&self) -> usize {
+        self.mismatches
+    }
+
+    fn replay_into(
+        records: &mut VecDeque<CaptureRecord>,
+        buffer: &mut [u8],
+        what: &str,
+    ) -> Result<usize> {
+        let mut record = records
+            .pop_front()
+            .ok_or_else(|| UsbError::Transfer(format!("Capture has no more {}", what)))?;
+        debug!("Replaying phase {} ({})", record.phase, record.name);
+
+        if let Some(error) = record.error {
+            return Err(replayed_error(&error));
+        }
+
+        // A transfer larger than the buffer is read in several pieces
+        let data = record.bytes()?;
+        let len = data.len().min(buffer.len());
+        if len < data.len() {
+            record.data = hex_encode(&data[len..]);
+            records.push_front(record);
+        }
+        buffer[..len].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn write(&mut self, data: &[u8]) -> Result<usize> {
+        let Some(record) = self.outgoing.pop_front() else {
+            warn!(
+                "Host wrote {} bytes past the end of the capture",
+                data.len()
+            );
+            self.mismatches += 1;
+            return Ok(data.len());
+        };
+
+        if let Some(error) = record.error {
+            return Err(replayed_error(&error));
+        }
+
+        if record.bytes()? != data {
+            warn!(
+                "Host {} differs from the capture (phase {})",
+                record.name, record.phase
+            );
+            self.mismatches += 1;
+        }
+        Ok(data.len())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        Self::replay_into(&mut self.incoming, buffer, "bulk IN transfers")
+    }
+
+    fn control_in(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        Self::replay_into(&mut self.control, buffer, "control transfers")
+    }
+}
+
+fn describe_error(error: &anyhow::Error) -> String {
+    let timeout = matches!(
+        error.downcast_ref::<rusb::Error>(),
+        Some(rusb::Error::Timeout)
+    ) || matches!(error.downcast_ref::<UsbError>(), Some(UsbError::Timeout));
+
+    if timeout {
+        TIMEOUT_ERROR.to_string()
+    } else {
+        error.to_string()
+    }
+}
+
+fn replayed_error(error: &str) -> anyhow::Error {
+    if error == TIMEOUT_ERROR {
+        UsbError::Timeout.into()
+    } else {
+        UsbError::Transfer(format!("Captured error: {}", error)).into()
+    }
+}
+
+fn hex_decode(hex: &str) -> Result<Vec<u8>> {
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return Err(UsbError::Parse(format!("Invalid hex data ({} digits)", hex.len())).into());
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| UsbError::Parse(format!("Invalid hex data at offset {}", i)).into())
+        })
+        .collect()
+}
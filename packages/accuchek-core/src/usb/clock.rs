@@ -0,0 +1,223 @@
+//! Meter clock and time zone handling
+//!
+//! Meters store their readings in local wall-clock time without any zone
+//! information. [`TimePolicy`] decides which UTC offset applies to those
+//! times; the result is kept in a [`SampleTime`].
+
+use super::segment::BaseOffsetTime;
+use anyhow::{anyhow, Result};
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, LocalResult, NaiveDateTime, Offset, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Meter clocks earlier than this were reset (after a battery change) rather
+/// than drifting
+const CLOCK_RESET_BEFORE_YEAR: i32 = 2010;
+
+/// How the meter's local times are mapped to UTC
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimePolicy {
+    /// Time zone of the host running the download
+    #[default]
+    Host,
+    /// Named IANA time zone, such as `Europe/Paris`
+    Zone(Tz),
+    /// Fixed offset from UTC, without daylight saving time
+    Fixed(FixedOffset),
+    /// Offset declared by the meter (Base-Offset-Time), falling back to the
+    /// host time zone for meters that declare none
+    Meter,
+}
+
+impl TimePolicy {
+    /// Resolve a meter-local time
+    ///
+    /// `meter_offset_minutes` is the UTC offset the meter declared, only used
+    /// by [`TimePolicy::Meter`].
+    pub fn resolve(&self, local: NaiveDateTime, meter_offset_minutes: Option<i16>) -> SampleTime {
+        match (self, meter_offset_minutes) {
+            (TimePolicy::Host, _) | (TimePolicy::Meter, None) => resolve_in(&chrono::Local, local),
+            (TimePolicy::Zone(zone), _) => resolve_in(zone, local),
+            (TimePolicy::Fixed(offset), _) => resolve_in(offset, local),
+            (TimePolicy::Meter, Some(minutes)) => match offset_from_minutes(minutes) {
+                Some(offset) => resolve_in(&offset, local),
+                None => resolve_in(&chrono::Local, local),
+            },
+        }
+    }
+
+    /// Wall-clock time of a UTC instant, the inverse of [`TimePolicy::resolve`]
+    pub fn local_time(
+        &self,
+        utc: DateTime<Utc>,
+        meter_offset_minutes: Option<i16>,
+    ) -> NaiveDateTime {
+        let meter_offset = meter_offset_minutes.and_then(offset_from_minutes);
+        match (*self, meter_offset) {
+            (TimePolicy::Zone(zone), _) => utc.with_timezone(&zone).naive_local(),
+            (TimePolicy::Fixed(offset), _) | (TimePolicy::Meter, Some(offset)) => {
+                utc.with_timezone(&offset).naive_local()
+            }
+            (TimePolicy::Host, _) | (TimePolicy::Meter, None) => {
+                utc.with_timezone(&chrono::Local).naive_local()
+            }
+        }
+    }
+}
+
+impl FromStr for TimePolicy {
+    type Err = anyhow::Error;
+
+    /// Parse `host`, `meter`, an IANA zone name, `UTC` or an offset such as
+    /// `+02:00`
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "host" | "local" => return Ok(TimePolicy::Host),
+            "meter" => return Ok(TimePolicy::Meter),
+            _ => {}
+        }
+
+        if s.starts_with(['+', '-']) {
+            let offset = FixedOffset::from_str(s)
+                .map_err(|_| anyhow!("Invalid UTC offset '{}', expected +HH:MM", s))?;
+            return Ok(TimePolicy::Fixed(offset));
+        }
+
+        s.parse::<Tz>()
+            .map(TimePolicy::Zone)
+            .map_err(|_| anyhow!("Unknown time zone '{}'", s))
+    }
+}
+
+/// How a local time was mapped to UTC
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimeResolution {
+    /// The local time exists exactly once in the zone
+    #[default]
+    Unique,
+    /// The local time occurs twice (clocks turned back); the earlier instant
+    /// was used
+    Ambiguous,
+    /// The local time does not exist (clocks turned forward); the offset in
+    /// effect before the change was used
+    Nonexistent,
+    /// Taken from the meter's Base-Offset-Time
+    Declared,
+}
+
+/// Time of a reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SampleTime {
+    /// Wall-clock time as shown on the meter
+    pub local: NaiveDateTime,
+    pub utc: DateTime<Utc>,
+    /// Offset from UTC applied to `local`
+    pub offset_minutes: i32,
+    pub resolution: TimeResolution,
+    /// Meter clock drift subtracted from `utc`, see [`ClockCheck`]
+    #[serde(default)]
+    pub clock_correction_seconds: i64,
+}
+
+impl SampleTime {
+    /// Time of an entry stamped with Base-Offset-Time, `None` if out of range
+    pub fn from_base_offset(time: BaseOffsetTime) -> Option<Self> {
+        let offset = offset_from_minutes(time.offset_minutes)?;
+        let nanos = ((time.fraction as u64 * 1_000_000_000) >> 16) as u32;
+        let utc = DateTime::from_timestamp(time.seconds as i64, nanos)?;
+
+        Some(Self {
+            local: utc.with_timezone(&offset).naive_local(),
+            utc,
+            offset_minutes: time.offset_minutes as i32,
+            resolution: TimeResolution::Declared,
+            clock_correction_seconds: 0,
+        })
+    }
+
+    /// Whether the UTC instant had to be guessed around a DST change
+    pub fn is_ambiguous(&self) -> bool {
+        matches!(
+            self.resolution,
+            TimeResolution::Ambiguous | TimeResolution::Nonexistent
+        )
+    }
+
+    pub fn offset(&self) -> FixedOffset {
+        FixedOffset::east_opt(self.offset_minutes * 60).unwrap_or(Utc.fix())
+    }
+
+    /// Same reading with the meter clock drift taken out of its UTC instant
+    pub(crate) fn corrected(mut self, drift_seconds: i64) -> Self {
+        self.utc -= Duration::seconds(drift_seconds);
+        self.clock_correction_seconds = drift_seconds;
+        self
+    }
+}
+
+/// Meter clock compared to the host clock at download
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockCheck {
+    /// Meter clock as read from its MDS attributes
+    pub meter_time: NaiveDateTime,
+    pub host_time: DateTime<Utc>,
+    /// Meter clock minus host clock; positive when the meter runs ahead
+    pub drift_seconds: i64,
+    /// The meter clock went back to its factory default, typically after a
+    /// battery change
+    pub reset_detected: bool,
+    /// Whether sample times were corrected by `drift_seconds`
+    pub corrected: bool,
+}
+
+impl ClockCheck {
+    pub fn measure(meter: &SampleTime, host_time: DateTime<Utc>) -> Self {
+        Self {
+            meter_time: meter.local,
+            host_time,
+            drift_seconds: (meter.utc - host_time).num_seconds(),
+            reset_detected: meter.local.year() < CLOCK_RESET_BEFORE_YEAR,
+            corrected: false,
+        }
+    }
+
+    /// Whether a reading was recorded before the clock was reset: it is dated
+    /// after what the meter clock reads now
+    pub fn predates_reset(&self, time: &SampleTime) -> bool {
+        self.reset_detected && time.local > self.meter_time
+    }
+}
+
+fn resolve_in<Z: TimeZone>(zone: &Z, local: NaiveDateTime) -> SampleTime {
+    let (time, resolution) = match zone.from_local_datetime(&local) {
+        LocalResult::Single(time) => (time.fixed_offset(), TimeResolution::Unique),
+        LocalResult::Ambiguous(earliest, _) => (earliest.fixed_offset(), TimeResolution::Ambiguous),
+        LocalResult::None => {
+            // Gaps last hours at most: a day earlier is before the change
+            let before = zone
+                .offset_from_utc_datetime(&(local - Duration::days(1)))
+                .fix();
+            let utc = local - Duration::seconds(before.local_minus_utc() as i64);
+            (
+                DateTime::from_naive_utc_and_offset(utc, before),
+                TimeResolution::Nonexistent,
+            )
+        }
+    };
+
+    SampleTime {
+        local,
+        utc: time.to_utc(),
+        offset_minutes: time.offset().local_minus_utc() / 60,
+        resolution,
+        clock_correction_seconds: 0,
+    }
+}
+
+fn offset_from_minutes(minutes: i16) -> Option<FixedOffset> {
+    FixedOffset::east_opt(minutes as i32 * 60)
+}
@@ -0,0 +1,189 @@
+//! Object model of the meter's configuration report
+
+use super::apdu::mder::Reader;
+use super::apdu::nomenclature::*;
+use super::apdu::{AttributeList, ConfigObject, ConfigReport};
+use super::UsbError;
+use anyhow::Result;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+/// Everything the meter declared in its configuration report
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceConfiguration {
+    pub config_report_id: u16,
+    pub numerics: Vec<NumericObject>,
+    pub enumerations: Vec<EnumerationObject>,
+    pub pm_stores: Vec<PmStoreObject>,
+}
+
+impl DeviceConfiguration {
+    pub fn from_report(report: &ConfigReport) -> Result<Self> {
+        let mut config = DeviceConfiguration {
+            config_report_id: report.config_report_id,
+            ..Default::default()
+        };
+
+        debug!("Config has {} objects", report.objects.len());
+
+        for object in &report.objects {
+            debug!(
+                "Object class={}, handle={}, attributes={}",
+                object.obj_class,
+                object.obj_handle,
+                object.attributes.0.len()
+            );
+
+            match object.obj_class {
+                MDC_MOC_VMO_METRIC_NU => config.numerics.push(NumericObject::decode(object)?),
+                MDC_MOC_VMO_METRIC_ENUM => {
+                    config.enumerations.push(EnumerationObject::decode(object)?)
+                }
+                MDC_MOC_VMO_PMSTORE => config.pm_stores.push(PmStoreObject::decode(object)?),
+                other => debug!("Ignoring object of class {}", other),
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// The PM-store holding the stored measurements
+    pub fn pm_store(&self) -> Result<&PmStoreObject> {
+        self.pm_stores
+            .first()
+            .ok_or_else(|| UsbError::Parse("PM Store not found in config".to_string()).into())
+    }
+
+    pub fn numeric(&self, handle: u16) -> Option<&NumericObject> {
+        self.numerics.iter().find(|object| object.handle == handle)
+    }
+
+    pub fn enumeration(&self, handle: u16) -> Option<&EnumerationObject> {
+        self.enumerations
+            .iter()
+            .find(|object| object.handle == handle)
+    }
+}
+
+/// Nomenclature partition and code of a metric
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetricType {
+    pub partition: u16,
+    pub code: u16,
+}
+
+impl MetricType {
+    fn decode(value: &[u8]) -> Result<Self> {
+        let mut r = Reader::new(value);
+        Ok(Self {
+            partition: r.u16()?,
+            code: r.u16()?,
+        })
+    }
+}
+
+/// One entry of an Attribute-Value-Map: which attribute an observation
+/// carries and how many bytes it takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttributeValueMapEntry {
+    pub attribute_id: u16,
+    pub attribute_len: u16,
+}
+
+/// Numeric metric object (MDC_MOC_VMO_METRIC_NU)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NumericObject {
+    pub handle: u16,
+    pub metric_type: Option<MetricType>,
+    pub metric_spec_small: Option<u16>,
+    pub unit_code: Option<u16>,
+    pub attribute_value_map: Vec<AttributeValueMapEntry>,
+}
+
+impl NumericObject {
+    fn decode(object: &ConfigObject) -> Result<Self> {
+        let attributes = &object.attributes;
+
+        Ok(Self {
+            handle: object.obj_handle,
+            metric_type: metric_type(attributes)?,
+            metric_spec_small: u16_attribute(attributes, MDC_ATTR_METRIC_SPEC_SMALL)?,
+            unit_code: u16_attribute(attributes, MDC_ATTR_UNIT_CODE)?,
+            attribute_value_map: attribute_value_map(attributes)?,
+        })
+    }
+}
+
+/// Enumeration metric object (MDC_MOC_VMO_METRIC_ENUM)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnumerationObject {
+    pub handle: u16,
+    pub metric_type: Option<MetricType>,
+    pub metric_spec_small: Option<u16>,
+    pub attribute_value_map: Vec<AttributeValueMapEntry>,
+}
+
+impl EnumerationObject {
+    fn decode(object: &ConfigObject) -> Result<Self> {
+        let attributes = &object.attributes;
+
+        Ok(Self {
+            handle: object.obj_handle,
+            metric_type: metric_type(attributes)?,
+            metric_spec_small: u16_attribute(attributes, MDC_ATTR_METRIC_SPEC_SMALL)?,
+            attribute_value_map: attribute_value_map(attributes)?,
+        })
+    }
+}
+
+/// Persistent metric store object (MDC_MOC_VMO_PMSTORE)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PmStoreObject {
+    pub handle: u16,
+    /// PM-Store-Capab bits (`PMSC_*`)
+    pub capabilities: u16,
+    pub number_of_segments: Option<u16>,
+}
+
+impl PmStoreObject {
+    fn decode(object: &ConfigObject) -> Result<Self> {
+        let attributes = &object.attributes;
+
+        Ok(Self {
+            handle: object.obj_handle,
+            capabilities: u16_attribute(attributes, MDC_ATTR_PM_STORE_CAPAB)?.unwrap_or(0),
+            number_of_segments: u16_attribute(attributes, MDC_ATTR_NUM_SEG)?,
+        })
+    }
+
+    pub fn supports(&self, capability: u16) -> bool {
+        self.capabilities & capability != 0
+    }
+}
+
+fn u16_attribute(attributes: &AttributeList, id: u16) -> Result<Option<u16>> {
+    attributes
+        .get(id)
+        .map(|value| Reader::new(value).u16())
+        .transpose()
+}
+
+fn metric_type(attributes: &AttributeList) -> Result<Option<MetricType>> {
+    attributes
+        .get(MDC_ATTR_ID_TYPE)
+        .map(MetricType::decode)
+        .transpose()
+}
+
+fn attribute_value_map(attributes: &AttributeList) -> Result<Vec<AttributeValueMapEntry>> {
+    let Some(value) = attributes.get(MDC_ATTR_ATTRIBUTE_VAL_MAP) else {
+        return Ok(Vec::new());
+    };
+
+    Reader::new(value).list(|r| {
+        Ok(AttributeValueMapEntry {
+            attribute_id: r.u16()?,
+            attribute_len: r.u16()?,
+        })
+    })
+}
@@ -0,0 +1,136 @@
+//! Glucose context recorded alongside a reading (IEEE 11073-10417)
+
+use super::apdu::float::MderFloat;
+use super::apdu::mder::Reader;
+use super::apdu::nomenclature::*;
+use super::segment::{EntryElement, SegmentEntry};
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// When the reading was taken relative to a meal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MealRelation {
+    BeforeMeal,
+    AfterMeal,
+    Fasting,
+    Bedtime,
+    /// "Other" / casual reading
+    Other,
+}
+
+/// Who took the reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Tester {
+    #[serde(rename = "self")]
+    SelfTest,
+    HealthCareProfessional,
+    Laboratory,
+}
+
+/// Health condition the user reported with the reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HealthState {
+    MinorIssues,
+    MajorIssues,
+    Menses,
+    Stress,
+    NoIssues,
+}
+
+/// Context objects found in one segment entry
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SampleContext {
+    pub meal: Option<MealRelation>,
+    pub tester: Option<Tester>,
+    pub health: Option<HealthState>,
+    /// Carbohydrate intake in grams
+    pub carbs_g: Option<f64>,
+}
+
+impl SampleContext {
+    /// Decode the glucose context elements of an entry
+    ///
+    /// Unknown context values are logged and left empty.
+    pub fn from_entry(entry: &SegmentEntry) -> Result<Self> {
+        let mut context = SampleContext::default();
+
+        for element in &entry.elements {
+            if element.metric_type.partition != MDC_PART_PHD_DM {
+                continue;
+            }
+
+            match (element.class_id, element.metric_type.code) {
+                (MDC_MOC_VMO_METRIC_ENUM, MDC_CTXT_GLU_MEAL) => {
+                    context.meal = enum_value(element)?.and_then(meal_relation);
+                }
+                (MDC_MOC_VMO_METRIC_ENUM, MDC_CTXT_GLU_TESTER) => {
+                    context.tester = enum_value(element)?.and_then(tester);
+                }
+                (MDC_MOC_VMO_METRIC_ENUM, MDC_CTXT_GLU_HEALTH) => {
+                    context.health = enum_value(element)?.and_then(health_state);
+                }
+                (MDC_MOC_VMO_METRIC_NU, MDC_CTXT_GLU_CARB) => {
+                    if let Some(value) = element.get(MDC_ATTR_NU_VAL_OBS_BASIC) {
+                        let value = MderFloat::from_sfloat(Reader::new(value).u16()?);
+                        context.carbs_g = value.is_finite().then(|| value.to_f64());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(context)
+    }
+}
+
+/// Enum-Observed-Value-Simple-OID of a context element
+fn enum_value(element: &EntryElement) -> Result<Option<u16>> {
+    element
+        .get(MDC_ATTR_ENUM_OBS_VAL_SIMP_OID)
+        .map(|value| Reader::new(value).u16())
+        .transpose()
+}
+
+fn meal_relation(code: u16) -> Option<MealRelation> {
+    match code {
+        MDC_CTXT_GLU_MEAL_PREPRANDIAL => Some(MealRelation::BeforeMeal),
+        MDC_CTXT_GLU_MEAL_POSTPRANDIAL => Some(MealRelation::AfterMeal),
+        MDC_CTXT_GLU_MEAL_FASTING => Some(MealRelation::Fasting),
+        MDC_CTXT_GLU_MEAL_BEDTIME => Some(MealRelation::Bedtime),
+        MDC_CTXT_GLU_MEAL_CASUAL => Some(MealRelation::Other),
+        other => {
+            warn!("Unknown meal context {}", other);
+            None
+        }
+    }
+}
+
+fn tester(code: u16) -> Option<Tester> {
+    match code {
+        MDC_CTXT_GLU_TESTER_SELF => Some(Tester::SelfTest),
+        MDC_CTXT_GLU_TESTER_HCP => Some(Tester::HealthCareProfessional),
+        MDC_CTXT_GLU_TESTER_LAB => Some(Tester::Laboratory),
+        other => {
+            warn!("Unknown tester context {}", other);
+            None
+        }
+    }
+}
+
+fn health_state(code: u16) -> Option<HealthState> {
+    match code {
+        MDC_CTXT_GLU_HEALTH_MINOR => Some(HealthState::MinorIssues),
+        MDC_CTXT_GLU_HEALTH_MAJOR => Some(HealthState::MajorIssues),
+        MDC_CTXT_GLU_HEALTH_MENSES => Some(HealthState::Menses),
+        MDC_CTXT_GLU_HEALTH_STRESS => Some(HealthState::Stress),
+        MDC_CTXT_GLU_HEALTH_NONE => Some(HealthState::NoIssues),
+        other => {
+            warn!("Unknown health context {}", other);
+            None
+        }
+    }
+}
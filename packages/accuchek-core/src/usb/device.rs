@@ -1,18 +1,40 @@
+use super::UsbError;
 use anyhow::Result;
 use log::{debug, info};
+use rusb::GlobalContext;
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::fs;
+use std::sync::Mutex;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AccuChekDevice {
     pub vendor_id: u16,
     pub product_id: u16,
     pub name: String,
+    /// Bus number and address of this enumeration, both change on a replug
     pub bus: u8,
     pub address: u8,
+    /// Physical port the meter is plugged into, as `bus-port.port` (e.g. `1-2.3`)
+    pub port_path: String,
+    /// iSerialNumber string descriptor, if the meter has one and could be opened
+    pub serial_number: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+impl AccuChekDevice {
+    /// Identifier surviving re-enumeration: the serial number, or the port
+    /// path when it is unknown
+    pub fn device_id(&self) -> &str {
+        self.serial_number.as_deref().unwrap_or(&self.port_path)
+    }
+
+    /// Whether `selector` names this meter, by serial number or port path
+    pub fn matches(&self, selector: &str) -> bool {
+        self.serial_number.as_deref() == Some(selector) || self.port_path == selector
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct DeviceConfig {
     pub devices: Vec<SupportedDevice>,
 }
@@ -37,13 +59,25 @@ pub fn load_config() -> Result<DeviceConfig> {
     };
 
     let config: DeviceConfig = toml::from_str(&config_content)?;
-    info!("Loaded configuration with {} supported devices", config.devices.len());
+    info!(
+        "Loaded configuration with {} supported devices",
+        config.devices.len()
+    );
 
     Ok(config)
 }
 
 /// Find all AccuChek devices connected to the system
 pub fn find_devices(config: &DeviceConfig) -> Result<Vec<AccuChekDevice>> {
+    rescan_devices(config, &[])
+}
+
+/// Find all AccuChek devices, reusing the serial number of the meters in
+/// `known` that are still connected instead of opening them again
+pub(crate) fn rescan_devices(
+    config: &DeviceConfig,
+    known: &[AccuChekDevice],
+) -> Result<Vec<AccuChekDevice>> {
     let mut found_devices = Vec::new();
 
     info!("Scanning for USB devices...");
@@ -51,43 +85,194 @@ pub fn find_devices(config: &DeviceConfig) -> Result<Vec<AccuChekDevice>> {
     let devices = rusb::devices()?;
 
     for device in devices.iter() {
-        let desc = device.device_descriptor()?;
-
-        debug!(
-            "Checking device: vendor={:04x}, product={:04x}",
-            desc.vendor_id(),
-            desc.product_id()
-        );
-
-        // Check if this device matches our supported devices
-        if let Some(supported) = config.devices.iter().find(|d| {
-            d.vendor_id == desc.vendor_id() && d.product_id == desc.product_id()
-        }) {
-            // Verify device configuration matches AccuChek specs
-            if is_valid_accuchek(&device)? {
-                info!(
-                    "Found matching device: {} (vendor={:04x}, product={:04x})",
-                    supported.name,
-                    desc.vendor_id(),
-                    desc.product_id()
-                );
-
-                found_devices.push(AccuChekDevice {
-                    vendor_id: desc.vendor_id(),
-                    product_id: desc.product_id(),
-                    name: supported.name.clone(),
-                    bus: device.bus_number(),
-                    address: device.address(),
-                });
-            }
+        if let Some(mut found) = match_device(config, &device)? {
+            found.serial_number = match known.iter().find(|k| same_enumeration(k, &found)) {
+                Some(known) => known.serial_number.clone(),
+                None => read_serial_number(&device),
+            };
+            remember_port(&found);
+            found_devices.push(found);
         }
     }
 
     Ok(found_devices)
 }
 
+/// Whether both describe the same connection of a meter, which lasts until
+/// it is unplugged
+pub(crate) fn same_enumeration(a: &AccuChekDevice, b: &AccuChekDevice) -> bool {
+    a.bus == b.bus && a.address == b.address
+}
+
+/// The device as an [`AccuChekDevice`], if it is one of the supported meters
+///
+/// Only descriptors are read, the serial number is left to
+/// [`read_serial_number`] since it needs the device opened.
+pub(crate) fn match_device(
+    config: &DeviceConfig,
+    device: &rusb::Device<GlobalContext>,
+) -> Result<Option<AccuChekDevice>> {
+    let desc = device.device_descriptor()?;
+
+    debug!(
+        "Checking device: vendor={:04x}, product={:04x}",
+        desc.vendor_id(),
+        desc.product_id()
+    );
+
+    // Check if this device matches our supported devices
+    let Some(supported) = config
+        .devices
+        .iter()
+        .find(|d| d.vendor_id == desc.vendor_id() && d.product_id == desc.product_id())
+    else {
+        return Ok(None);
+    };
+
+    // Verify device configuration matches AccuChek specs
+    if !is_valid_accuchek(device)? {
+        return Ok(None);
+    }
+
+    info!(
+        "Found matching device: {} (vendor={:04x}, product={:04x})",
+        supported.name,
+        desc.vendor_id(),
+        desc.product_id()
+    );
+
+    Ok(Some(AccuChekDevice {
+        vendor_id: desc.vendor_id(),
+        product_id: desc.product_id(),
+        name: supported.name.clone(),
+        bus: device.bus_number(),
+        address: device.address(),
+        port_path: port_path(device)?,
+        serial_number: None,
+    }))
+}
+
+/// Port path of the device, in the form Linux uses in sysfs
+fn port_path(device: &rusb::Device<GlobalContext>) -> Result<String> {
+    let ports: Vec<String> = device
+        .port_numbers()?
+        .iter()
+        .map(|port| port.to_string())
+        .collect();
+    Ok(format!("{}-{}", device.bus_number(), ports.join(".")))
+}
+
+/// Read the iSerialNumber string descriptor of the device
+pub(crate) fn read_serial_number(device: &rusb::Device<GlobalContext>) -> Option<String> {
+    device
+        .device_descriptor()
+        .ok()?
+        .serial_number_string_index()?;
+    match device.open() {
+        Ok(handle) => read_serial_number_from(&handle),
+        Err(e) => {
+            debug!("Cannot open the device for its serial number: {}", e);
+            None
+        }
+    }
+}
+
+fn read_serial_number_from(handle: &rusb::DeviceHandle<GlobalContext>) -> Option<String> {
+    let desc = handle.device().device_descriptor().ok()?;
+    desc.serial_number_string_index()?;
+    match handle.read_serial_number_string_ascii(&desc) {
+        Ok(serial) => Some(serial.trim().to_string()).filter(|s| !s.is_empty()),
+        Err(e) => {
+            debug!("Cannot read the serial number: {}", e);
+            None
+        }
+    }
+}
+
+/// Port path each serial number was last seen at
+static LAST_PORTS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// Record where the meter is plugged in, for [`open_device`]
+pub(crate) fn remember_port(device: &AccuChekDevice) {
+    if let Some(serial) = &device.serial_number {
+        set_last_port(serial, device.port_path.clone());
+    }
+}
+
+fn set_last_port(serial: &str, port: String) {
+    LAST_PORTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(serial.to_string(), port);
+}
+
+fn last_port(serial: &str) -> Option<String> {
+    LAST_PORTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(serial)
+        .cloned()
+}
+
+/// Open the meter, even if it was re-enumerated since it was scanned
+///
+/// The meter is expected at the port its serial number was last seen at,
+/// where a serial number that cannot be read is accepted. Other meters are
+/// only opened to compare serial numbers when a different one is found there.
+pub(crate) fn open_device(
+    device_info: &AccuChekDevice,
+) -> Result<rusb::DeviceHandle<GlobalContext>> {
+    let serial = device_info.serial_number.as_deref();
+    let port = serial
+        .and_then(last_port)
+        .unwrap_or_else(|| device_info.port_path.clone());
+
+    let devices = rusb::devices()?;
+    let candidates: Vec<_> = devices
+        .iter()
+        .filter(|device| {
+            device.device_descriptor().is_ok_and(|desc| {
+                desc.vendor_id() == device_info.vendor_id
+                    && desc.product_id() == device_info.product_id
+            })
+        })
+        .collect();
+    let at_port = |device: &rusb::Device<GlobalContext>| port_path(device).is_ok_and(|p| p == port);
+
+    if let Some(device) = candidates.iter().find(|device| at_port(device)) {
+        let handle = device.open()?;
+        match (serial, read_serial_number_from(&handle)) {
+            (Some(expected), Some(found)) if expected != found => {
+                debug!("Meter {} is plugged into port {} instead", found, port);
+            }
+            _ => return Ok(handle),
+        }
+    }
+
+    // The meter moved to another port
+    if let Some(expected) = serial {
+        for device in candidates.iter().filter(|device| !at_port(device)) {
+            let handle = match device.open() {
+                Ok(handle) => handle,
+                Err(e) => {
+                    debug!("Cannot open a candidate device: {}", e);
+                    continue;
+                }
+            };
+            if read_serial_number_from(&handle).as_deref() == Some(expected) {
+                if let Ok(port_path) = port_path(device) {
+                    set_last_port(expected, port_path);
+                }
+                return Ok(handle);
+            }
+        }
+    }
+
+    Err(UsbError::DeviceNotFound.into())
+}
+
 /// Verify if a device matches AccuChek hardware specifications
-fn is_valid_accuchek(device: &rusb::Device<rusb::GlobalContext>) -> Result<bool> {
+fn is_valid_accuchek(device: &rusb::Device<GlobalContext>) -> Result<bool> {
     // AccuChek devices should have:
     // - 1 configuration
     // - 1 interface with 1 alternate setting
@@ -129,8 +314,7 @@ fn is_valid_accuchek(device: &rusb::Device<rusb::GlobalContext>) -> Result<bool>
     let mut has_bulk_out = false;
 
     for endpoint in alt_setting.endpoint_descriptors() {
-        if endpoint.max_packet_size() == 64
-            && endpoint.transfer_type() == rusb::TransferType::Bulk
+        if endpoint.max_packet_size() == 64 && endpoint.transfer_type() == rusb::TransferType::Bulk
         {
             match endpoint.direction() {
                 rusb::Direction::In => has_bulk_in = true,
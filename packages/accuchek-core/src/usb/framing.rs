@@ -0,0 +1,96 @@
+//! APDU reassembly over bulk transfers
+//!
+//! The meter sends its APDUs as a stream of 64-byte bulk packets, so a large
+//! segment data event spans many reads. The length field of the APDU header
+//! tells how many more bytes belong to it.
+
+use super::UsbError;
+use anyhow::Result;
+use log::debug;
+
+/// Max packet size of the meter's bulk endpoints
+pub(crate) const PACKET_SIZE: usize = 64;
+
+/// Choice and length, both 16 bits
+const APDU_HEADER_SIZE: usize = 4;
+
+/// Largest APDU a glucose meter may send (IEEE 11073-10417)
+pub const DEFAULT_MAX_APDU_SIZE: usize = 63 * 1024;
+
+#[derive(Debug, Default)]
+pub(crate) struct ApduFramer {
+    /// Bytes received past the end of the last APDU
+    pending: Vec<u8>,
+}
+
+impl ApduFramer {
+    /// Read packets with `read` until a whole APDU is assembled
+    pub(crate) fn read_apdu(
+        &mut self,
+        mut read: impl FnMut(&mut [u8]) -> Result<usize>,
+        max_apdu_size: usize,
+    ) -> Result<Vec<u8>> {
+        let mut apdu = std::mem::take(&mut self.pending);
+        let mut packets = 0;
+
+        loop {
+            let expected = apdu_len(&apdu, max_apdu_size)?;
+            if let Some(len) = expected.filter(|&len| apdu.len() >= len) {
+                self.pending = apdu.split_off(len);
+                if packets > 1 {
+                    debug!("Reassembled {} bytes from {} reads", len, packets);
+                }
+                return Ok(apdu);
+            }
+
+            // Whole packets, as many as the rest of the APDU needs
+            let wanted = match expected {
+                Some(len) => (len - apdu.len()).div_ceil(PACKET_SIZE) * PACKET_SIZE,
+                None => PACKET_SIZE,
+            };
+            let start = apdu.len();
+            apdu.resize(start + wanted, 0);
+            let received = match read(&mut apdu[start..]) {
+                Ok(received) => received,
+                Err(error) => {
+                    // Keep what arrived so far for a later attempt
+                    apdu.truncate(start);
+                    self.pending = apdu;
+                    return Err(error);
+                }
+            };
+            apdu.truncate(start + received);
+            packets += 1;
+
+            if received == 0 {
+                return Err(UsbError::Transfer(format!(
+                    "Transfer ended after {} bytes of an APDU",
+                    apdu.len()
+                ))
+                .into());
+            }
+        }
+    }
+
+    /// Drop whatever is left of a broken APDU stream
+    pub(crate) fn reset(&mut self) {
+        self.pending.clear();
+    }
+}
+
+/// Length of the APDU starting `data`, once its header is complete
+fn apdu_len(data: &[u8], max_apdu_size: usize) -> Result<Option<usize>> {
+    if data.len() < APDU_HEADER_SIZE {
+        return Ok(None);
+    }
+
+    let len = APDU_HEADER_SIZE + u16::from_be_bytes([data[2], data[3]]) as usize;
+    if len > max_apdu_size {
+        return Err(UsbError::ApduTooLarge {
+            size: len,
+            max: max_apdu_size,
+        }
+        .into());
+    }
+    Ok(Some(len))
+}
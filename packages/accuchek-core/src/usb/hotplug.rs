@@ -0,0 +1,234 @@
+//! Arrival and removal of meters on the USB bus
+//!
+//! [`DeviceWatcher`] uses libusb hotplug callbacks where the platform has
+//! them and rescans the bus at a fixed interval elsewhere. Events are
+//! delivered on a channel, so the callbacks never run USB transfers
+//! themselves: devices are inspected, and their serial numbers read, on the
+//! watching thread once the callback has returned.
+
+use super::device::{
+    match_device, read_serial_number, remember_port, rescan_devices, same_enumeration, DeviceConfig,
+};
+use super::AccuChekDevice;
+use anyhow::Result;
+use log::{debug, info, warn};
+use rusb::UsbContext;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long the watching thread blocks before checking whether it should stop
+const EVENT_WAIT: Duration = Duration::from_millis(250);
+
+/// Change of a supported meter on the USB bus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HotplugEvent {
+    /// The meter was plugged in, or was already connected when watching started
+    Arrived(AccuChekDevice),
+    Left(AccuChekDevice),
+}
+
+/// Background watcher reporting [`HotplugEvent`]s
+///
+/// The watching thread stops when the watcher is dropped.
+pub struct DeviceWatcher {
+    events: Receiver<HotplugEvent>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DeviceWatcher {
+    /// Watch for the meters listed in `config`, through hotplug callbacks
+    /// when libusb supports them, otherwise by rescanning every `poll_interval`
+    pub fn start(config: DeviceConfig, poll_interval: Duration) -> Result<Self> {
+        if rusb::has_hotplug() {
+            Self::hotplug(config)
+        } else {
+            info!("libusb has no hotplug support, polling the USB bus");
+            Self::polling(config, poll_interval)
+        }
+    }
+
+    /// Watch through libusb hotplug callbacks
+    pub fn hotplug(config: DeviceConfig) -> Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let (changes, changed) = mpsc::channel();
+
+        let registration = rusb::HotplugBuilder::new().enumerate(true).register(
+            rusb::GlobalContext::default(),
+            Box::new(HotplugCallback(changes)),
+        )?;
+
+        Ok(Self::spawn(events, move |stop| {
+            let context = rusb::GlobalContext::default();
+            // Meters reported as arrived, since descriptors may be gone on removal
+            let mut connected: Vec<AccuChekDevice> = Vec::new();
+            while !stop.load(Ordering::SeqCst) {
+                if let Err(e) = context.handle_events(Some(EVENT_WAIT)) {
+                    warn!("Failed to handle USB events: {}", e);
+                    thread::sleep(EVENT_WAIT);
+                }
+                for change in changed.try_iter() {
+                    let event = match change {
+                        DeviceChange::Arrived(device) => arrived(&config, &device),
+                        DeviceChange::Left(device) => left(&mut connected, &device),
+                    };
+                    if let Some(event) = event {
+                        if let HotplugEvent::Arrived(found) = &event {
+                            connected.push(found.clone());
+                        }
+                        let _ = sender.send(event);
+                    }
+                }
+            }
+            drop(registration);
+        }))
+    }
+
+    /// Watch by rescanning the USB bus for the meters listed in `config`
+    pub fn polling(config: DeviceConfig, interval: Duration) -> Result<Self> {
+        let mut known = Vec::new();
+        Ok(Self::with_scanner(interval, move || {
+            known = rescan_devices(&config, &known)?;
+            Ok(known.clone())
+        }))
+    }
+
+    /// Watch by calling `scan` every `interval` and comparing what it finds
+    /// with the previous scan
+    ///
+    /// Meters are compared by bus and address, so a detail that could not be
+    /// read again, such as the serial number, does not look like a replug.
+    pub fn with_scanner(
+        interval: Duration,
+        mut scan: impl FnMut() -> Result<Vec<AccuChekDevice>> + Send + 'static,
+    ) -> Self {
+        let (sender, events) = mpsc::channel();
+
+        Self::spawn(events, move |stop| {
+            let mut connected: Vec<AccuChekDevice> = Vec::new();
+            while !stop.load(Ordering::SeqCst) {
+                match scan() {
+                    Ok(found) => {
+                        let (kept, gone): (Vec<_>, Vec<_>) = std::mem::take(&mut connected)
+                            .into_iter()
+                            .partition(|d| found.iter().any(|f| same_enumeration(d, f)));
+                        for device in gone {
+                            let _ = sender.send(HotplugEvent::Left(device));
+                        }
+                        connected = kept;
+                        for device in found {
+                            if !connected.iter().any(|d| same_enumeration(d, &device)) {
+                                let _ = sender.send(HotplugEvent::Arrived(device.clone()));
+                                connected.push(device);
+                            }
+                        }
+                    }
+                    Err(e) => warn!("USB scan failed: {}", e),
+                }
+                sleep_unless_stopped(&stop, interval);
+            }
+        })
+    }
+
+    fn spawn(
+        events: Receiver<HotplugEvent>,
+        watch: impl FnOnce(Arc<AtomicBool>) + Send + 'static,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || watch(stop))
+        };
+
+        Self {
+            events,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Wait for the next event
+    pub fn recv(&self) -> Option<HotplugEvent> {
+        self.events.recv().ok()
+    }
+
+    /// Wait at most `timeout` for the next event
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<HotplugEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+}
+
+impl Drop for DeviceWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("USB watcher thread panicked");
+            }
+        }
+    }
+}
+
+/// Sleep for `duration`, waking up early once `stop` is set
+fn sleep_unless_stopped(stop: &AtomicBool, duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while !stop.load(Ordering::SeqCst) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        thread::sleep(left.min(EVENT_WAIT));
+    }
+}
+
+/// Device added to or removed from the bus, as libusb reports it
+enum DeviceChange {
+    Arrived(rusb::Device<rusb::GlobalContext>),
+    Left(rusb::Device<rusb::GlobalContext>),
+}
+
+/// libusb hotplug callback handing every change over to the watching thread
+struct HotplugCallback(Sender<DeviceChange>);
+
+impl rusb::Hotplug<rusb::GlobalContext> for HotplugCallback {
+    fn device_arrived(&mut self, device: rusb::Device<rusb::GlobalContext>) {
+        let _ = self.0.send(DeviceChange::Arrived(device));
+    }
+
+    fn device_left(&mut self, device: rusb::Device<rusb::GlobalContext>) {
+        let _ = self.0.send(DeviceChange::Left(device));
+    }
+}
+
+/// Arrival event for the device, if it is a supported meter
+fn arrived(
+    config: &DeviceConfig,
+    device: &rusb::Device<rusb::GlobalContext>,
+) -> Option<HotplugEvent> {
+    match match_device(config, device) {
+        Ok(Some(mut found)) => {
+            found.serial_number = read_serial_number(device);
+            remember_port(&found);
+            Some(HotplugEvent::Arrived(found))
+        }
+        Ok(None) => None,
+        Err(e) => {
+            debug!("Ignoring USB device: {}", e);
+            None
+        }
+    }
+}
+
+/// Removal event for the device, if it was reported as arrived
+fn left(
+    connected: &mut Vec<AccuChekDevice>,
+    device: &rusb::Device<rusb::GlobalContext>,
+) -> Option<HotplugEvent> {
+    let position = connected
+        .iter()
+        .position(|found| found.bus == device.bus_number() && found.address == device.address())?;
+    Some(HotplugEvent::Left(connected.swap_remove(position)))
+}
@@ -0,0 +1,78 @@
+//! Invoke-id bookkeeping
+//!
+//! The manager numbers its own requests and matches every response the meter
+//! sends against them; the meter numbers its invocations, which the manager
+//! echoes in its answers.
+
+use super::UsbError;
+use anyhow::Result;
+use std::collections::VecDeque;
+
+/// Answered invoke-ids remembered to recognise duplicate responses
+const COMPLETED_HISTORY: usize = 8;
+
+/// How a response relates to the manager's requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ResponseMatch {
+    /// Answers the oldest outstanding request
+    Expected,
+    /// Repeats the answer to a request already completed
+    Duplicate,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct InvokeTracker {
+    next: u16,
+    /// Requests sent and not answered yet, oldest first
+    outstanding: VecDeque<u16>,
+    completed: VecDeque<u16>,
+    /// Invoke-id of the meter's last invocation
+    last_agent_invoke: Option<u16>,
+}
+
+impl InvokeTracker {
+    /// Invoke-id for a new request, now outstanding
+    pub(crate) fn start(&mut self) -> u16 {
+        let invoke_id = self.next;
+        self.next = self.next.wrapping_add(1);
+        self.outstanding.push_back(invoke_id);
+        invoke_id
+    }
+
+    /// Match a response received from the meter
+    pub(crate) fn complete(&mut self, invoke_id: u16) -> Result<ResponseMatch> {
+        match self.outstanding.front() {
+            Some(&expected) if expected == invoke_id => {
+                self.outstanding.pop_front();
+                self.completed.push_back(invoke_id);
+                if self.completed.len() > COMPLETED_HISTORY {
+                    self.completed.pop_front();
+                }
+                Ok(ResponseMatch::Expected)
+            }
+            Some(&expected) if self.outstanding.contains(&invoke_id) => {
+                Err(UsbError::OutOfOrderResponse {
+                    expected,
+                    received: invoke_id,
+                }
+                .into())
+            }
+            _ if self.completed.contains(&invoke_id) => Ok(ResponseMatch::Duplicate),
+            expected => Err(UsbError::InvokeIdMismatch {
+                expected: expected.copied(),
+                received: invoke_id,
+            }
+            .into()),
+        }
+    }
+
+    /// Record an invocation of the meter; `true` if it repeats the previous one
+    pub(crate) fn agent_invoke(&mut self, invoke_id: u16) -> bool {
+        self.last_agent_invoke.replace(invoke_id) == Some(invoke_id)
+    }
+
+    /// Forget everything at the end of an association
+    pub(crate) fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
@@ -0,0 +1,199 @@
+//! Medical Device System (MDS) attributes of the meter
+
+use super::apdu::mder::Reader;
+use super::apdu::nomenclature::*;
+use super::apdu::{hex_encode, AttributeList};
+use super::framing::DEFAULT_MAX_APDU_SIZE;
+use anyhow::Result;
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// Identity of one physical meter, decoded from its MDS attributes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceIdentity {
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    /// EUI-64 system-id, hex encoded
+    pub system_id: Option<String>,
+    pub serial_number: Option<String>,
+    pub part_number: Option<String>,
+    pub hardware_revision: Option<String>,
+    pub software_revision: Option<String>,
+    pub firmware_revision: Option<String>,
+    pub protocol_revision: Option<String>,
+    /// Meter clock when the attributes were read
+    pub clock: Option<NaiveDateTime>,
+    /// UTC offset of the meter clock, when it reports a Base-Offset-Time
+    pub utc_offset_minutes: Option<i16>,
+    /// Mds-Time-Info capability and state bits (`MDS_TIME_*`)
+    pub time_capabilities: Option<u16>,
+    pub certifications: Vec<RegulatoryCertification>,
+    /// Raw Power-Status bits
+    pub power_status: Option<u16>,
+    /// Remaining battery charge in percent
+    pub battery_level: Option<u16>,
+    /// Device specializations the meter implements (`MDC_DEV_SPEC_PROFILE_*`)
+    pub specializations: Vec<u16>,
+}
+
+impl DeviceIdentity {
+    /// Decode the attributes of an MDS GET response
+    ///
+    /// Attributes the meter does not report are left empty.
+    pub fn from_attributes(attributes: &AttributeList) -> Result<Self> {
+        let mut identity = DeviceIdentity::default();
+
+        if let Some(value) = attributes.get(MDC_ATTR_ID_MODEL) {
+            let mut r = Reader::new(value);
+            identity.manufacturer = Some(text(r.octet_string()?));
+            identity.model = Some(text(r.octet_string()?));
+        }
+
+        if let Some(value) = attributes.get(MDC_ATTR_SYS_ID) {
+            identity.system_id = Some(hex_encode(Reader::new(value).octet_string()?));
+        }
+
+        if let Some(value) = attributes.get(MDC_ATTR_ID_PROD_SPECN) {
+            let entries = Reader::new(value).list(|r| {
+                let spec_type = r.u16()?;
+                let _component_id = r.u16()?;
+                Ok((spec_type, text(r.octet_string()?)))
+            })?;
+
+            for (spec_type, spec) in entries {
+                let field = match spec_type {
+                    PROD_SPEC_SERIAL_NUMBER => &mut identity.serial_number,
+                    PROD_SPEC_PART_NUMBER => &mut identity.part_number,
+                    PROD_SPEC_HW_REVISION => &mut identity.hardware_revision,
+                    PROD_SPEC_SW_REVISION => &mut identity.software_revision,
+                    PROD_SPEC_FW_REVISION => &mut identity.firmware_revision,
+                    PROD_SPEC_PROTOCOL_REVISION => &mut identity.protocol_revision,
+                    _ => continue,
+                };
+                field.get_or_insert(spec);
+            }
+        }
+
+        if let Some(value) = attributes.get(MDC_ATTR_TIME_ABS) {
+            identity.clock = absolute_time(Reader::new(value).bytes(8)?);
+            if identity.clock.is_none() {
+                warn!("Meter reported an invalid clock: {:02x?}", value);
+            }
+        }
+
+        if let Some(value) = attributes.get(MDC_ATTR_TIME_BO) {
+            let mut r = Reader::new(value);
+            let _seconds = r.u32()?;
+            let _fraction = r.u16()?;
+            identity.utc_offset_minutes = Some(r.i16()?);
+        }
+
+        if let Some(value) = attributes.get(MDC_ATTR_MDS_TIME_INFO) {
+            identity.time_capabilities = Some(Reader::new(value).u16()?);
+        }
+
+        if let Some(value) = attributes.get(MDC_ATTR_REG_CERT_DATA_LIST) {
+            identity.certifications = Reader::new(value).list(|r| {
+                Ok(RegulatoryCertification {
+                    auth_body: r.u8()?,
+                    auth_body_struct_type: r.u8()?,
+                    data: hex_encode(r.octet_string()?),
+                })
+            })?;
+        }
+
+        if let Some(value) = attributes.get(MDC_ATTR_POWER_STAT) {
+            identity.power_status = Some(Reader::new(value).u16()?);
+        }
+
+        if let Some(value) = attributes.get(MDC_ATTR_VAL_BATT_CHARGE) {
+            identity.battery_level = Some(Reader::new(value).u16()?);
+        }
+
+        if let Some(value) = attributes.get(MDC_ATTR_SYS_TYPE_SPEC_LIST) {
+            identity.specializations = Reader::new(value).list(|r| {
+                let specialization = r.u16()?;
+                let _version = r.u16()?;
+                Ok(specialization)
+            })?;
+        }
+
+        Ok(identity)
+    }
+
+    /// Whether the meter accepts a Set-Time action
+    pub fn can_set_clock(&self) -> bool {
+        self.time_capabilities
+            .is_some_and(|capabilities| capabilities & MDS_TIME_CAPAB_SET_CLOCK != 0)
+    }
+
+    pub fn is_on_battery(&self) -> bool {
+        self.power_status
+            .is_some_and(|status| status & POWER_STATUS_ON_BATTERY != 0)
+    }
+
+    /// Largest APDU the meter may send, the smallest limit of the device
+    /// specializations it implements
+    ///
+    /// IEEE 11073-20601 has no APDU size in the association, each
+    /// specialization fixes it instead.
+    pub fn max_apdu_size(&self) -> Option<usize> {
+        self.specializations
+            .iter()
+            .filter_map(|&specialization| match specialization {
+                MDC_DEV_SPEC_PROFILE_GLUCOSE => Some(DEFAULT_MAX_APDU_SIZE),
+                _ => None,
+            })
+            .min()
+    }
+}
+
+/// One entry of the Reg-Cert-Data-List attribute
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegulatoryCertification {
+    pub auth_body: u8,
+    pub auth_body_struct_type: u8,
+    /// Body specific certification data, hex encoded
+    pub data: String,
+}
+
+/// Decode an 8-byte BCD AbsoluteTime, `None` when it is not a valid date
+pub(crate) fn absolute_time(bytes: &[u8]) -> Option<NaiveDateTime> {
+    let field = |i: usize| bcd_decode(bytes[i]);
+    let year = (field(0) * 100 + field(1)) as i32;
+
+    NaiveDate::from_ymd_opt(year, field(2), field(3))?.and_hms_opt(field(4), field(5), field(6))
+}
+
+/// Encode an AbsoluteTime, the inverse of [`absolute_time`]
+pub(crate) fn encode_absolute_time(time: &NaiveDateTime) -> [u8; 8] {
+    [
+        bcd_encode((time.year() / 100) as u8),
+        bcd_encode((time.year() % 100) as u8),
+        bcd_encode(time.month() as u8),
+        bcd_encode(time.day() as u8),
+        bcd_encode(time.hour() as u8),
+        bcd_encode(time.minute() as u8),
+        bcd_encode(time.second() as u8),
+        0,
+    ]
+}
+
+/// Decode BCD (Binary-Coded Decimal)
+fn bcd_decode(val: u8) -> u32 {
+    let high = (val >> 4) & 0x0F;
+    let low = val & 0x0F;
+    (high * 10 + low) as u32
+}
+
+fn bcd_encode(val: u8) -> u8 {
+    ((val / 10) << 4) | (val % 10)
+}
+
+/// Printable form of a fixed-size string attribute
+pub(crate) fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches(['\0', ' '])
+        .to_string()
+}
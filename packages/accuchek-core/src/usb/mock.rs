@@ -0,0 +1,547 @@
+//! Scripted mock meter
+//!
+//! [`MockMeter`] plays the device side of an IEEE 11073-20601 session from a
+//! [`MeterScript`], so the whole download can run without a meter attached.
+//! Every APDU the host writes is kept for later inspection.
+
+use super::apdu::nomenclature::*;
+use super::apdu::{
+    Action, Apdu, AssociationRequest, AttributeList, ConfigObject, ConfigReport, DataApdu,
+    DataMessage, DataProto, EventReport, GetResult, PhdAssociationInformation, ReleaseReason,
+    SegmentDataDescriptor, SegmentDataEvent, SegmentInfo, TriggerTransferResponse,
+};
+use super::mds::encode_absolute_time;
+use super::{DeviceConfiguration, PmSegmentEntryMap, Transport, UsbError};
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Config report id used by the scripted meter (first extended configuration)
+pub const MOCK_CONFIG_REPORT_ID: u16 = 0x4000;
+
+/// PM-store handle declared by [`MeterScript::standard`]
+pub const MOCK_PM_STORE_HANDLE: u16 = 0x0100;
+
+/// System-id announced by the scripted meter
+pub const MOCK_SYSTEM_ID: [u8; 8] = [0x00, 0x60, 0x19, 0x00, 0x00, 0x01, 0x02, 0x03];
+
+/// Serial number reported in the scripted meter's production specification
+pub const MOCK_SERIAL_NUMBER: &str = "92345678";
+
+/// Firmware revision reported in the scripted meter's production specification
+pub const MOCK_FIRMWARE_REVISION: &str = "02.05.00";
+
+/// One message sent by the meter
+#[derive(Debug, Clone)]
+pub enum MeterStep {
+    /// Unsolicited APDU (association request, event reports, ...), sent as is
+    Send(Vec<u8>),
+    /// Answer to the host's last request; its invoke-id is echoed back
+    Respond(DataMessage),
+    /// Nothing arrives: the read times out
+    Silence,
+}
+
+/// A stored reading as the meter keeps it in its PM-store
+#[derive(Debug, Clone)]
+pub struct MockEntry {
+    pub time: NaiveDateTime,
+    pub value: u16,
+    pub status: u16,
+}
+
+impl MockEntry {
+    pub fn new(time: NaiveDateTime, value: u16) -> Self {
+        Self {
+            time,
+            value,
+            status: 0,
+        }
+    }
+
+    pub fn with_status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+/// Declarative list of what the meter sends, in order
+#[derive(Debug, Clone, Default)]
+pub struct MeterScript {
+    steps: Vec<MeterStep>,
+    invoke_id: u16,
+}
+
+impl MeterScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Full session of an Accu-Chek Guide holding `entries`, sent in
+    /// segment data events of at most `per_event` entries each
+    pub fn standard(entries: &[MockEntry], per_event: usize) -> Self {
+        let mut script = Self::new()
+            .association_request()
+            .config_report(MOCK_PM_STORE_HANDLE)
+            .mds_attributes()
+            .segment_info(MOCK_PM_STORE_HANDLE)
+            .trigger_transfer(MOCK_PM_STORE_HANDLE);
+
+        let chunks: Vec<_> = entries.chunks(per_event.max(1)).collect();
+        let mut index = 0;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let last = i + 1 == chunks.len();
+            script = script.segment_data(MOCK_PM_STORE_HANDLE, index, chunk, last);
+            index += chunk.len() as u32;
+        }
+
+        script.release_response()
+    }
+
+    /// Append a raw step
+    pub fn step(mut self, step: MeterStep) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Append an unsolicited APDU
+    pub fn send(self, apdu: Apdu) -> Self {
+        self.step(MeterStep::Send(apdu.encode()))
+    }
+
+    /// Append a confirmed event report with the meter's next invoke-id
+    pub fn event_report(mut self, obj_handle: u16, event_type: u16, info: Vec<u8>) -> Self {
+        self.invoke_id = self.invoke_id.wrapping_add(1);
+        let invoke_id = self.invoke_id;
+
+        self.send(Apdu::Presentation(DataApdu {
+            invoke_id,
+            message: DataMessage::ConfirmedEventReport(EventReport {
+                obj_handle,
+                event_time: 0xFFFFFFFF,
+                event_type,
+                info,
+            }),
+        }))
+    }
+
+    /// Append a read that times out
+    pub fn silence(self) -> Self {
+        self.step(MeterStep::Silence)
+    }
+
+    /// Append the answer to the host's pending request
+    pub fn respond(self, message: DataMessage) -> Self {
+        self.step(MeterStep::Respond(message))
+    }
+
+    /// AARQ announcing an extended configuration
+    pub fn association_request(self) -> Self {
+        self.association_request_with(mock_association_information())
+    }
+
+    /// AARQ carrying the given association information
+    pub fn association_request_with(self, info: PhdAssociationInformation) -> Self {
+        self.send(Apdu::AssociationRequest(AssociationRequest {
+            assoc_version: ASSOC_VERSION_1,
+            data_protos: vec![DataProto {
+                id: DATA_PROTO_ID_20601,
+                info: info.to_bytes(),
+            }],
+        }))
+    }
+
+    /// Config report declaring a glucose numeric object and a PM-store
+    pub fn config_report(self, pm_store_handle: u16) -> Self {
+        let glucose = object(
+            MDC_MOC_VMO_METRIC_NU,
+            1,
+            &[
+                (
+                    MDC_ATTR_ID_TYPE,
+                    [
+                        MDC_PART_SCADA.to_be_bytes(),
+                        MDC_CONC_GLU_CAPILLARY_WHOLEBLOOD.to_be_bytes(),
+                    ]
+                    .concat(),
+                ),
+                (MDC_ATTR_METRIC_SPEC_SMALL, 0xF040u16.to_be_bytes().to_vec()),
+                (
+                    MDC_ATTR_UNIT_CODE,
+                    MDC_DIM_MILLI_G_PER_DL.to_be_bytes().to_vec(),
+                ),
+                (
+                    MDC_ATTR_ATTRIBUTE_VAL_MAP,
+                    [
+                        2u16.to_be_bytes(),
+                        8u16.to_be_bytes(),
+                        MDC_ATTR_NU_VAL_OBS_BASIC.to_be_bytes(),
+                        2u16.to_be_bytes(),
+                        MDC_ATTR_TIME_STAMP_ABS.to_be_bytes(),
+                        8u16.to_be_bytes(),
+                    ]
+                    .concat(),
+                ),
+            ],
+        );
+        let pm_store = object(
+            MDC_MOC_VMO_PMSTORE,
+            pm_store_handle,
+            &[
+                (
+                    MDC_ATTR_PM_STORE_CAPAB,
+                    (PMSC_EPI_SEG_ENTRIES | PMSC_CLEAR_SEGM_BY_LIST_SUP)
+                        .to_be_bytes()
+                        .to_vec(),
+                ),
+                (MDC_ATTR_NUM_SEG, 1u16.to_be_bytes().to_vec()),
+            ],
+        );
+        self.config_report_with(vec![glucose, pm_store])
+    }
+
+    /// Config report made of the given objects
+    pub fn config_report_with(self, objects: Vec<ConfigObject>) -> Self {
+        let report = ConfigReport {
+            config_report_id: MOCK_CONFIG_REPORT_ID,
+            objects,
+        };
+
+        self.event_report(0, MDC_NOTI_CONFIG, report.to_bytes())
+    }
+
+    /// Response to the MDS attribute GET
+    pub fn mds_attributes(self) -> Self {
+        self.mds_attributes_with(mock_mds_attributes())
+    }
+
+    /// Response to the MDS attribute GET with custom attributes
+    pub fn mds_attributes_with(self, attributes: AttributeList) -> Self {
+        self.respond(DataMessage::GetResult(GetResult {
+            obj_handle: 0,
+            attributes,
+        }))
+    }
+
+    /// Response to MDC_ACT_SEG_GET_INFO describing a single segment
+    pub fn segment_info(self, pm_store_handle: u16) -> Self {
+        let mut attributes = AttributeList::default();
+        attributes.push(
+            MDC_ATTR_PM_SEG_MAP,
+            PmSegmentEntryMap::legacy(&DeviceConfiguration::default()).to_bytes(),
+        );
+        self.segment_info_with(pm_store_handle, attributes)
+    }
+
+    /// Response to MDC_ACT_SEG_GET_INFO with custom segment attributes
+    pub fn segment_info_with(self, pm_store_handle: u16, attributes: AttributeList) -> Self {
+        let info = SegmentInfo {
+            instance: 0,
+            attributes,
+        };
+
+        self.segment_info_list(pm_store_handle, &[info])
+    }
+
+    /// Response to MDC_ACT_SEG_GET_INFO describing several segments
+    pub fn segment_info_list(self, pm_store_handle: u16, segments: &[SegmentInfo]) -> Self {
+        self.action_response(
+            pm_store_handle,
+            MDC_ACT_SEG_GET_INFO,
+            SegmentInfo::list_to_bytes(segments),
+        )
+    }
+
+    /// Response to MDC_ACT_SEG_TRIG_XFER for segment 0
+    pub fn trigger_transfer(self, pm_store_handle: u16) -> Self {
+        self.trigger_transfer_of(pm_store_handle, 0, TSXR_SUCCESSFUL)
+    }
+
+    /// Response to MDC_ACT_SEG_TRIG_XFER for any segment, with a `TSXR_*` result
+    pub fn trigger_transfer_of(self, pm_store_handle: u16, instance: u16, result: u16) -> Self {
+        let response = TriggerTransferResponse { instance, result };
+
+        self.action_response(pm_store_handle, MDC_ACT_SEG_TRIG_XFER, response.to_bytes())
+    }
+
+    /// MDC_NOTI_SEGMENT_DATA event carrying `entries` starting at `first_index`
+    pub fn segment_data(
+        self,
+        pm_store_handle: u16,
+        first_index: u32,
+        entries: &[MockEntry],
+        last: bool,
+    ) -> Self {
+        self.segment_data_of(pm_store_handle, 0, first_index, entries, last)
+    }
+
+    /// Segment data event of segment `instance`
+    pub fn segment_data_of(
+        self,
+        pm_store_handle: u16,
+        instance: u16,
+        first_index: u32,
+        entries: &[MockEntry],
+        last: bool,
+    ) -> Self {
+        let mut data = Vec::new();
+        for entry in entries {
+            data.extend_from_slice(&encode_absolute_time(&entry.time));
+            data.extend_from_slice(&entry.value.to_be_bytes());
+            data.extend_from_slice(&entry.status.to_be_bytes());
+        }
+
+        self.segment_event(
+            pm_store_handle,
+            instance,
+            first_index,
+            entries.len() as u32,
+            data,
+            last,
+        )
+    }
+
+    /// Segment data event carrying pre-encoded entries
+    pub fn segment_data_bytes(
+        self,
+        pm_store_handle: u16,
+        first_index: u32,
+        entry_count: u32,
+        entries: Vec<u8>,
+        last: bool,
+    ) -> Self {
+        self.segment_event(pm_store_handle, 0, first_index, entry_count, entries, last)
+    }
+
+    fn segment_event(
+        self,
+        pm_store_handle: u16,
+        instance: u16,
+        first_index: u32,
+        entry_count: u32,
+        entries: Vec<u8>,
+        last: bool,
+    ) -> Self {
+        let mut status = 0;
+        if first_index == 0 {
+            status |= SEGM_EVT_STATUS_FIRST_ENTRY;
+        }
+        if last {
+            status |= SEGM_EVT_STATUS_LAST_ENTRY;
+        }
+
+        let event = SegmentDataEvent {
+            descriptor: SegmentDataDescriptor {
+                instance,
+                entry_index: first_index,
+                entry_count,
+                status,
+            },
+            entries,
+        };
+
+        self.event_report(pm_store_handle, MDC_NOTI_SEGMENT_DATA, event.to_bytes())
+    }
+
+    /// Response to MDC_ACT_SET_TIME
+    pub fn set_time_response(self) -> Self {
+        self.action_response(0, MDC_ACT_SET_TIME, Vec::new())
+    }
+
+    /// Response to MDC_ACT_SEG_CLR
+    pub fn clear_segments_response(self, pm_store_handle: u16) -> Self {
+        self.action_response(pm_store_handle, MDC_ACT_SEG_CLR, Vec::new())
+    }
+
+    /// RLRE answering the host's release request
+    pub fn release_response(self) -> Self {
+        self.send(Apdu::ReleaseResponse(ReleaseReason::NORMAL))
+    }
+
+    fn action_response(self, obj_handle: u16, action_type: u16, info: Vec<u8>) -> Self {
+        self.respond(DataMessage::ActionResult(Action {
+            obj_handle,
+            action_type,
+            info,
+        }))
+    }
+}
+
+/// [`Transport`] that plays a [`MeterScript`]
+pub struct MockMeter {
+    steps: VecDeque<MeterStep>,
+    sent: Vec<Vec<u8>>,
+    last_invoke_id: u16,
+    timeouts: Vec<Duration>,
+}
+
+impl MockMeter {
+    pub fn new(script: MeterScript) -> Self {
+        Self {
+            steps: script.steps.into(),
+            sent: Vec::new(),
+            last_invoke_id: 0,
+            timeouts: Vec::new(),
+        }
+    }
+
+    /// Every APDU written by the host, in order
+    pub fn sent(&self) -> &[Vec<u8>] {
+        &self.sent
+    }
+
+    /// Every APDU written by the host, decoded
+    pub fn sent_apdus(&self) -> Result<Vec<Apdu>> {
+        self.sent.iter().map(|msg| Apdu::decode(msg)).collect()
+    }
+
+    /// Number of scripted messages the host has not read
+    pub fn remaining(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Every timeout the host set, in order
+    pub fn timeouts(&self) -> &[Duration] {
+        &self.timeouts
+    }
+}
+
+impl Transport for MockMeter {
+    fn write(&mut self, data: &[u8]) -> Result<usize> {
+        // Answers to the meter's own event reports are not requests
+        if let Ok(Apdu::Presentation(apdu)) = Apdu::decode(data) {
+            if matches!(
+                apdu.message,
+                DataMessage::Get(_) | DataMessage::ConfirmedAction(_)
+            ) {
+                self.last_invoke_id = apdu.invoke_id;
+            }
+        }
+
+        self.sent.push(data.to_vec());
+        Ok(data.len())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let msg = match self.steps.pop_front() {
+            Some(MeterStep::Send(msg)) => msg,
+            Some(MeterStep::Respond(message)) => Apdu::Presentation(DataApdu {
+                invoke_id: self.last_invoke_id,
+                message,
+            })
+            .encode(),
+            Some(MeterStep::Silence) | None => return Err(UsbError::Timeout.into()),
+        };
+
+        // Like a bulk pipe, hand out what fits and keep the rest for the next read
+        let len = msg.len().min(buffer.len());
+        if len < msg.len() {
+            self.steps.push_front(MeterStep::Send(msg[len..].to_vec()));
+        }
+        buffer[..len].copy_from_slice(&msg[..len]);
+        Ok(len)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        self.timeouts.push(timeout);
+    }
+
+    fn control_in(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let len = buffer.len().min(2);
+        buffer[..len].fill(0);
+        Ok(len)
+    }
+}
+
+/// Association information of the scripted meter
+pub fn mock_association_information() -> PhdAssociationInformation {
+    PhdAssociationInformation {
+        protocol_version: 0x80000000,
+        encoding_rules: 0xA000, // MDER | PER
+        nomenclature_version: NOMENCLATURE_VERSION_1,
+        functional_units: 0,
+        system_type: SYS_TYPE_AGENT,
+        system_id: MOCK_SYSTEM_ID.to_vec(),
+        dev_config_id: MOCK_CONFIG_REPORT_ID,
+        data_req_mode_flags: 0x0001,
+        data_req_init_agent_count: 1,
+        data_req_init_manager_count: 0,
+        option_list: AttributeList::default(),
+    }
+}
+
+/// MDS attributes of the scripted meter
+pub fn mock_mds_attributes() -> AttributeList {
+    let mut model = Vec::new();
+    octet_string(&mut model, b"Roche");
+    octet_string(&mut model, b"Guide");
+
+    let mut sys_id = Vec::new();
+    octet_string(&mut sys_id, &MOCK_SYSTEM_ID);
+
+    let specs: [(u16, &[u8]); 3] = [
+        (PROD_SPEC_SERIAL_NUMBER, MOCK_SERIAL_NUMBER.as_bytes()),
+        (PROD_SPEC_HW_REVISION, b"1.0\0"),
+        (PROD_SPEC_FW_REVISION, MOCK_FIRMWARE_REVISION.as_bytes()),
+    ];
+    let mut entries = Vec::new();
+    for (spec_type, spec) in specs {
+        entries.extend_from_slice(&spec_type.to_be_bytes());
+        entries.extend_from_slice(&0u16.to_be_bytes()); // component-id
+        octet_string(&mut entries, spec);
+    }
+    let mut prod_spec = (specs.len() as u16).to_be_bytes().to_vec();
+    octet_string(&mut prod_spec, &entries);
+
+    // Real-time clock that can be set, no time sync protocol
+    let mut time_info = (MDS_TIME_CAPAB_REAL_TIME_CLOCK | MDS_TIME_CAPAB_SET_CLOCK)
+        .to_be_bytes()
+        .to_vec();
+    time_info.extend_from_slice(&[0x00; 2 + 4 + 2 + 2 + 4]);
+
+    let mut reg_cert = vec![0x00, 0x01];
+    octet_string(&mut reg_cert, &[0x02, 0x00, 0x00, 0x02, 0x12, 0x34]);
+
+    let mut attributes = AttributeList::default();
+    attributes.push(MDC_ATTR_ID_MODEL, model);
+    attributes.push(MDC_ATTR_SYS_ID, sys_id);
+    attributes.push(MDC_ATTR_ID_PROD_SPECN, prod_spec);
+    attributes.push(
+        MDC_ATTR_TIME_ABS,
+        vec![0x20, 0x24, 0x11, 0x14, 0x10, 0x30, 0x00, 0x00],
+    );
+    attributes.push(MDC_ATTR_MDS_TIME_INFO, time_info);
+    attributes.push(MDC_ATTR_REG_CERT_DATA_LIST, reg_cert);
+    attributes.push(
+        MDC_ATTR_POWER_STAT,
+        POWER_STATUS_ON_BATTERY.to_be_bytes().to_vec(),
+    );
+    attributes.push(MDC_ATTR_VAL_BATT_CHARGE, 80u16.to_be_bytes().to_vec());
+
+    let mut specialization = MDC_DEV_SPEC_PROFILE_GLUCOSE.to_be_bytes().to_vec();
+    specialization.extend_from_slice(&1u16.to_be_bytes()); // version
+    let mut spec_list = 1u16.to_be_bytes().to_vec();
+    octet_string(&mut spec_list, &specialization);
+    attributes.push(MDC_ATTR_SYS_TYPE_SPEC_LIST, spec_list);
+    attributes
+}
+
+/// Build one config object from raw attribute values
+pub fn object(obj_class: u16, obj_handle: u16, attributes: &[(u16, Vec<u8>)]) -> ConfigObject {
+    let mut list = AttributeList::default();
+    for (id, value) in attributes {
+        list.push(*id, value.clone());
+    }
+
+    ConfigObject {
+        obj_class,
+        obj_handle,
+        attributes: list,
+    }
+}
+
+fn octet_string(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    buf.extend_from_slice(data);
+}
@@ -1,8 +1,48 @@
+pub mod apdu;
+mod association;
+mod capture;
+mod clock;
+mod config;
+mod context;
 mod device;
+mod framing;
+mod hotplug;
+mod invoke;
+mod mds;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+mod progress;
 mod protocol;
+mod segment;
+mod state;
+mod status;
+mod sync;
+mod transport;
 
+pub use association::{AssociationInfo, AssociationResult, KnownConfiguration, MANAGER_SYSTEM_ID};
+pub use capture::{CaptureRecord, RecordingTransport, ReplayTransport, TransferDirection};
+pub use clock::{ClockCheck, SampleTime, TimePolicy, TimeResolution};
+pub use config::{
+    AttributeValueMapEntry, DeviceConfiguration, EnumerationObject, MetricType, NumericObject,
+    PmStoreObject,
+};
+pub use context::{HealthState, MealRelation, SampleContext, Tester};
 pub use device::{find_devices, load_config, AccuChekDevice};
-pub use protocol::download_samples;
+pub use framing::DEFAULT_MAX_APDU_SIZE;
+pub use hotplug::{DeviceWatcher, HotplugEvent};
+pub use mds::{DeviceIdentity, RegulatoryCertification};
+pub use progress::{CancelToken, Progress, ProgressObserver};
+pub use protocol::{
+    clear_segments, download, download_samples, list_segments, set_device_time, ProtocolHandler,
+};
+pub use segment::{
+    BaseOffsetTime, EntryElement, EntryValue, PmSegment, PmSegmentEntryMap, SegmentEntry,
+    SegmentEntryElement,
+};
+pub use state::AssociationState;
+pub use status::{SampleStatus, StatusFlag};
+pub use sync::{EntryPosition, SyncCursor};
+pub use transport::{RetryPolicy, Timeouts, Transport, UsbTransport};
 
 use thiserror::Error;
 
@@ -20,9 +60,42 @@ pub enum UsbError {
     #[error("Protocol error: {0}")]
     Protocol(String),
 
+    #[error("Association rejected: {0}")]
+    AssociationRejected(String),
+
+    #[error("Not supported by the meter: {0}")]
+    Unsupported(String),
+
+    #[error("Refusing to clear the meter: {0}")]
+    ClearRefused(String),
+
+    #[error("Association aborted by the meter (reason {0})")]
+    Aborted(u16),
+
+    #[error("Meter answered with error {0}")]
+    RemoteError(u16),
+
+    #[error("Meter rejected the request (problem {0})")]
+    RemoteReject(u16),
+
+    #[error("Response with invoke-id {received}, expected {expected:?}")]
+    InvokeIdMismatch {
+        expected: Option<u16>,
+        received: u16,
+    },
+
+    #[error("Response to invoke-id {received} arrived before the one to {expected}")]
+    OutOfOrderResponse { expected: u16, received: u16 },
+
+    #[error("APDU of {size} bytes exceeds the {max} byte limit")]
+    ApduTooLarge { size: usize, max: usize },
+
     #[error("Parse error: {0}")]
     Parse(String),
 
     #[error("Timeout")]
     Timeout,
+
+    #[error("Cancelled")]
+    Cancelled,
 }
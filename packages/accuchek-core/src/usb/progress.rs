@@ -0,0 +1,52 @@
+//! Progress reporting and cancellation of a running session
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Callback receiving the [`Progress`] of a session
+pub type ProgressObserver = Box<dyn FnMut(&Progress) + Send>;
+
+/// Step reached by a session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Progress {
+    /// A transfer of the given phase starts
+    Phase { phase: usize, name: String },
+    /// The segments to transfer are known
+    SegmentsListed {
+        segments: usize,
+        /// Entries they hold, when every segment reports its usage count
+        expected_entries: Option<u32>,
+    },
+    /// A segment data event was decoded and acknowledged
+    EntriesReceived {
+        segment: u16,
+        /// Entries received so far, over all segments
+        received_entries: u32,
+        /// Samples decoded so far, over all segments
+        decoded_samples: usize,
+        expected_entries: Option<u32>,
+    },
+}
+
+/// Shared flag asking a running session to stop
+///
+/// The protocol handler checks it before each transfer and aborts the
+/// association once it is set.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
@@ -1,192 +1,623 @@
-use super::{AccuChekDevice, UsbError};
+use super::apdu::float::{MderFloat, NumericValue};
+use super::apdu::mder::Reader;
+use super::apdu::nomenclature::*;
+use super::apdu::{
+    AbortReason, Action, Apdu, AssociationResponse, ConfigReport, ConfigReportResponse, DataApdu,
+    DataMessage, DataProto, EventReport, GetArgument, RejectResult, ReleaseReason,
+    SegmentDataDescriptor, SegmentDataEvent, SegmentInfo, SegmentSelection,
+    TriggerTransferResponse,
+};
+use super::association::{
+    self, AssociationDecision, AssociationInfo, KnownConfiguration, MANAGER_SYSTEM_ID,
+};
+use super::clock::{ClockCheck, SampleTime, TimePolicy};
+use super::context::SampleContext;
+use super::framing::{ApduFramer, DEFAULT_MAX_APDU_SIZE};
+use super::invoke::{InvokeTracker, ResponseMatch};
+use super::mds::{encode_absolute_time, DeviceIdentity};
+use super::progress::{CancelToken, Progress, ProgressObserver};
+use super::segment::{EntryElement, PmSegment, PmSegmentEntryMap, SegmentEntry};
+use super::state::AssociationState;
+use super::status::{SampleStatus, StatusFlag};
+use super::sync::{EntryFilter, EntryPosition};
+use super::{AccuChekDevice, DeviceConfiguration, Transport, UsbError, UsbTransport};
+use crate::{
+    ClearOptions, ClearResult, DownloadOptions, DownloadResult, GlucoseSample, SetTimeOptions,
+    SetTimeResult,
+};
 use anyhow::Result;
-use chrono::{NaiveDateTime, TimeZone};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use log::{debug, info, warn};
-use rusb::{Direction, TransferType};
-use serde::Serialize;
-use std::time::Duration;
-
-const TIMEOUT: Duration = Duration::from_secs(5);
-const BUFFER_SIZE: usize = 1024;
-
-// Protocol constants from Continua Health Alliance (ISO/IEEE 11073)
-const APDU_TYPE_ASSOCIATION_RESPONSE: u16 = 0xE300;
-const APDU_TYPE_ASSOCIATION_RELEASE_REQUEST: u16 = 0xE400;
-const APDU_TYPE_PRESENTATION_APDU: u16 = 0xE700;
-
-const DATA_APDU_INVOKE_GET: u16 = 0x0103;
-const DATA_APDU_INVOKE_CONFIRMED_ACTION: u16 = 0x0107;
-const DATA_APDU_RESPONSE_CONFIRMED_EVENT_REPORT: u16 = 0x0201;
-
-const EVENT_TYPE_MDC_NOTI_CONFIG: u16 = 0x0D1C;
-const EVENT_TYPE_MDC_NOTI_SEGMENT_DATA: u16 = 0x0D21;
-
-const ACTION_TYPE_MDC_ACT_SEG_GET_INFO: u16 = 0x0C0D;
-const ACTION_TYPE_MDC_ACT_SEG_TRIG_XFER: u16 = 0x0C1C;
-
-const MDC_MOC_VMO_PMSTORE: u16 = 61;
-
-#[derive(Debug, Serialize, Clone)]
-pub struct GlucoseSample {
-    pub id: usize,
-    pub epoch: i64,
-    pub timestamp: String,
-    #[serde(rename = "mg/dL")]
-    pub mg_dl: u16,
-    #[serde(rename = "mmol/L")]
-    pub mmol_l: f64,
-}
+
+/// Meter clock drift worth a warning
+const CLOCK_DRIFT_WARNING_SECONDS: i64 = 5 * 60;
+
+/// Accuracy sent with Set-Time: one second, as a FLOAT-Type
+const SET_TIME_ACCURACY: u32 = 0x0000_0001;
 
 /// Download all glucose samples from the device
 pub fn download_samples(device_info: &AccuChekDevice) -> Result<Vec<GlucoseSample>> {
-    info!("Opening device...");
-
-    // Find the USB device
-    let devices = rusb::devices()?;
-    let device = devices
-        .iter()
-        .find(|d| {
-            d.bus_number() == device_info.bus && d.address() == device_info.address
+    download(device_info, DownloadOptions::default()).map(|result| result.samples)
+}
+
+/// Download every reading from the device, then erase it
+pub fn clear_segments(device_info: &AccuChekDevice, options: ClearOptions) -> Result<ClearResult> {
+    let transport = UsbTransport::open(device_info)?;
+    ProtocolHandler::new(transport).clear_segments(&options)
+}
+
+/// Describe the PM-segments stored on the device
+pub fn list_segments(device_info: &AccuChekDevice) -> Result<Vec<PmSegment>> {
+    let transport = UsbTransport::open(device_info)?;
+    ProtocolHandler::new(transport).list_segments()
+}
+
+/// Set the clock of the device to the host time
+pub fn set_device_time(
+    device_info: &AccuChekDevice,
+    options: SetTimeOptions,
+) -> Result<SetTimeResult> {
+    let transport = UsbTransport::open(device_info)?;
+    ProtocolHandler::new(transport).set_device_time(&options)
+}
+
+/// Download the samples along with what the meter told about itself
+pub fn download(device_info: &AccuChekDevice, options: DownloadOptions) -> Result<DownloadResult> {
+    let transport = UsbTransport::open(device_info)?;
+
+    // The interface is released when the handler (and its transport) is dropped
+    let mut protocol = ProtocolHandler::new(transport).with_options(options);
+    protocol.download()
+}
+
+/// Manager side of the IEEE 11073-20601 exchange, driven over any [`Transport`]
+pub struct ProtocolHandler<T: Transport> {
+    transport: T,
+    framer: ApduFramer,
+    invokes: InvokeTracker,
+    /// Last answer sent to a meter invocation, repeated if the meter does
+    last_answer: Option<Apdu>,
+    phase: usize,
+    manager_system_id: [u8; 8],
+    known_configurations: Vec<KnownConfiguration>,
+    options: DownloadOptions,
+    host_time: Option<DateTime<Utc>>,
+    state: AssociationState,
+    observer: Option<ProgressObserver>,
+    cancel: CancelToken,
+    tally: TransferTally,
+    /// Largest APDU the meter's specializations allow, once its MDS is read
+    meter_max_apdu_size: Option<usize>,
+}
+
+impl<T: Transport> ProtocolHandler<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            framer: ApduFramer::default(),
+            invokes: InvokeTracker::default(),
+            last_answer: None,
+            phase: 1,
+            manager_system_id: MANAGER_SYSTEM_ID,
+            known_configurations: Vec::new(),
+            options: DownloadOptions::default(),
+            host_time: None,
+            state: AssociationState::Disconnected,
+            observer: None,
+            cancel: CancelToken::default(),
+            tally: TransferTally::default(),
+            meter_max_apdu_size: None,
+        }
+    }
+
+    /// Announce a different manager system-id in the AARE
+    pub fn with_manager_system_id(mut self, system_id: [u8; 8]) -> Self {
+        self.manager_system_id = system_id;
+        self
+    }
+
+    /// Accept this configuration without a config report when the meter announces it
+    pub fn with_known_configuration(mut self, configuration: KnownConfiguration) -> Self {
+        self.known_configurations.push(configuration);
+        self
+    }
+
+    /// Apply these download options to every operation of the handler
+    pub fn with_options(mut self, options: DownloadOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Compare the meter clock against this time instead of the system clock
+    pub fn with_host_time(mut self, host_time: DateTime<Utc>) -> Self {
+        self.host_time = Some(host_time);
+        self
+    }
+
+    /// Report the progress of each session to `observer`
+    pub fn with_progress(mut self, observer: impl FnMut(&Progress) + Send + 'static) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Stop the session, aborting the association, once `token` is cancelled
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Give back the underlying transport
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Run the full 13-phase download and return the decoded samples
+    pub fn execute(&mut self) -> Result<Vec<GlucoseSample>> {
+        self.download().map(|result| result.samples)
+    }
+
+    /// Run the full 13-phase download
+    pub fn download(&mut self) -> Result<DownloadResult> {
+        self.guarded(|handler| {
+            let options = handler.options.clone();
+            let (result, _) = handler.transfer(&options)?;
+
+            // Phase 13: Disconnect cleanly, unless a failed transfer already
+            // ended the association
+            if result.interrupted.is_none() {
+                handler.disconnect()?;
+            }
+
+            Ok(result)
         })
-        .ok_or(UsbError::DeviceNotFound)?;
+    }
+
+    /// Download every reading, then erase the transferred segments
+    ///
+    /// The meter is only cleared when every segment transferred completely and
+    /// the number of entries matches [`ClearOptions::confirm_count`]; the
+    /// readings of that last download are returned with the result.
+    pub fn clear_segments(&mut self, options: &ClearOptions) -> Result<ClearResult> {
+        self.guarded(|handler| handler.clear(options))
+    }
+
+    fn clear(&mut self, options: &ClearOptions) -> Result<ClearResult> {
+        let download_options = DownloadOptions {
+            since: None,
+            ..options.download.clone()
+        };
+        let (download, failed) = self.transfer(&download_options)?;
+        let pm_store = download.configuration.pm_store()?.clone();
+
+        let refusal = if !pm_store.supports(PMSC_CLEAR_SEGM_BY_LIST_SUP)
+            && !pm_store.supports(PMSC_CLEAR_SEGM_ALL_SUP)
+        {
+            Some(UsbError::Unsupported(
+                "the PM-store cannot clear its segments".to_string(),
+            ))
+        } else if !failed.is_empty() {
+            Some(UsbError::ClearRefused(format!(
+                "transfer of segments {:?} failed",
+                failed
+            )))
+        } else if download.entry_count != options.confirm_count {
+            Some(UsbError::ClearRefused(format!(
+                "downloaded {} entries but {} were confirmed",
+                download.entry_count, options.confirm_count
+            )))
+        } else {
+            None
+        };
+        if let Some(error) = refusal {
+            if download.interrupted.is_none() {
+                self.disconnect()?;
+            }
+            return Err(error.into());
+        }
+
+        let cleared: Vec<u16> = download
+            .segments
+            .iter()
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| segment.instance)
+            .collect();
+
+        if !cleared.is_empty() {
+            let selection = if pm_store.supports(PMSC_CLEAR_SEGM_BY_LIST_SUP) {
+                SegmentSelection::Segments(cleared.clone())
+            } else {
+                SegmentSelection::AllSegments
+            };
 
-    let mut handle = device.open()?;
+            // Phase 13: Send the Clear-Segments action
+            self.send_clear_segments(pm_store.handle, &selection)?;
 
-    info!("Device opened successfully");
+            // Phase 14: Receive its confirmation
+            self.receive_action_result("clear segments response", MDC_ACT_SEG_CLR)?;
+            info!("Cleared segments {:?}", cleared);
+        }
+
+        self.disconnect()?;
+
+        Ok(ClearResult { download, cleared })
+    }
+
+    /// Phases 1 to 12: transfer the segments holding readings, returning the
+    /// download and the segments whose transfer failed
+    fn transfer(&mut self, options: &DownloadOptions) -> Result<(DownloadResult, Vec<u16>)> {
+        let Session {
+            association,
+            configuration,
+            device,
+            mut clock,
+        } = self.connect(options.time_policy)?;
+        if let Some(check) = &mut clock {
+            check.corrected = options.correct_clock;
+        }
+        let pm_store_handle = configuration.pm_store()?.handle;
+        info!("PM Store handle: {}", pm_store_handle);
+
+        // Phases 8 and 9: Learn which segments the PM-store holds
+        let segments = self.read_segments(pm_store_handle, &configuration)?;
+
+        if options.time_policy == TimePolicy::Meter && device.utc_offset_minutes.is_none() {
+            warn!("Meter declares no UTC offset, using the host time zone");
+        }
+        let mut cursor = options.since.clone().unwrap_or_default();
+        let mut samples = Vec::new();
+        let mut sample_id = 0;
+        let mut entry_count = 0;
+        let mut failed = Vec::new();
+        let mut interrupted = None;
+
+        let planned: Vec<(&PmSegment, EntryFilter)> = segments
+            .iter()
+            .filter(|segment| {
+                if segment.is_empty() {
+                    info!("Segment {} is empty", segment.instance);
+                }
+                !segment.is_empty()
+            })
+            .filter_map(|segment| match &options.since {
+                Some(since) => since.filter(segment).map(|filter| (segment, filter)),
+                None => Some((segment, EntryFilter::default())),
+            })
+            .collect();
+
+        self.tally = TransferTally {
+            expected_entries: planned.iter().map(|(segment, _)| segment.entry_count).sum(),
+            ..Default::default()
+        };
+        self.report(Progress::SegmentsListed {
+            segments: planned.len(),
+            expected_entries: self.tally.expected_entries,
+        });
+
+        for (segment, filter) in planned {
+            let decoder = SampleDecoder {
+                configuration: &configuration,
+                entry_map: &segment.entry_map,
+                options,
+                meter_offset_minutes: device.utc_offset_minutes,
+                clock: clock.as_ref(),
+                filter,
+            };
+            let mut transfer = SegmentTransfer::default();
+            let outcome = self.transfer_segment(
+                pm_store_handle,
+                segment.instance,
+                &decoder,
+                &mut sample_id,
+                &mut transfer,
+            );
+
+            match outcome {
+                Ok(TSXR_SUCCESSFUL) => {
+                    if segment
+                        .entry_count
+                        .is_some_and(|count| count != transfer.entry_count)
+                    {
+                        warn!(
+                            "Segment {} announced {:?} entries but sent {}",
+                            segment.instance, segment.entry_count, transfer.entry_count
+                        );
+                        failed.push(segment.instance);
+                    }
+                }
+                Ok(TSXR_FAIL_SEGM_EMPTY) => info!("Segment {} is empty", segment.instance),
+                Ok(result) => {
+                    warn!(
+                        "Meter refused to transfer segment {} (result {})",
+                        segment.instance, result
+                    );
+                    failed.push(segment.instance);
+                }
+                Err(error) if options.keep_partial => {
+                    warn!(
+                        "Transfer of segment {} failed after {} entries: {}",
+                        segment.instance, transfer.entry_count, error
+                    );
+                    self.recover(&error);
+                    failed.push(segment.instance);
+                    interrupted = Some(error.to_string());
+                }
+                Err(error) => return Err(error),
+            }
 
-    // On Linux, detach kernel driver if attached
-    #[cfg(target_os = "linux")]
-    {
-        match handle.kernel_driver_active(0) {
-            Ok(true) => {
-                info!("Detaching kernel driver...");
-                handle.detach_kernel_driver(0)?;
+            entry_count += transfer.entry_count;
+            cursor.last_entry = transfer.last_entry.or(cursor.last_entry);
+            cursor.last_time = transfer
+                .samples
+                .iter()
+                .map(|sample| sample.time.local)
+                .chain(cursor.last_time)
+                .max();
+            samples.extend(transfer.samples);
+
+            if interrupted.is_some() {
+                break;
             }
-            _ => {}
         }
+
+        let result = DownloadResult {
+            association,
+            device,
+            configuration,
+            clock,
+            segments,
+            samples,
+            entry_count,
+            cursor,
+            interrupted,
+        };
+        Ok((result, failed))
     }
 
-    // Set configuration
-    info!("Setting configuration...");
-    handle.set_active_configuration(1)?;
+    /// Describe the PM-segments stored on the meter without transferring them
+    pub fn list_segments(&mut self) -> Result<Vec<PmSegment>> {
+        self.guarded(|handler| {
+            let session = handler.connect(handler.options.time_policy)?;
+            let pm_store_handle = session.configuration.pm_store()?.handle;
 
-    // Claim interface 0
-    info!("Claiming interface 0...");
-    handle.claim_interface(0)?;
+            let segments = handler.read_segments(pm_store_handle, &session.configuration)?;
 
-    // Set alternate setting
-    handle.set_alternate_setting(0, 0)?;
+            handler.disconnect()?;
+            Ok(segments)
+        })
+    }
 
-    // AccuChek uses standard bulk endpoints:
-    // 0x01 = EP 1 OUT (host to device)
-    // 0x81 = EP 1 IN (device to host)
-    let bulk_out_endpoint = 0x01;
-    let bulk_in_endpoint = 0x81;
+    /// Set the meter clock to the current host time
+    ///
+    /// Fails without touching the clock if the meter does not declare the
+    /// set-clock capability in its Mds-Time-Info.
+    pub fn set_device_time(&mut self, options: &SetTimeOptions) -> Result<SetTimeResult> {
+        self.guarded(|handler| handler.set_time(options))
+    }
 
-    info!("Bulk OUT endpoint: {:02x}", bulk_out_endpoint);
-    info!("Bulk IN endpoint: {:02x}", bulk_in_endpoint);
+    fn set_time(&mut self, options: &SetTimeOptions) -> Result<SetTimeResult> {
+        let session = self.connect(options.time_policy)?;
+        let device = session.device;
 
-    let mut protocol = ProtocolHandler {
-        handle,
-        bulk_out: bulk_out_endpoint,
-        bulk_in: bulk_in_endpoint,
-        buffer: vec![0u8; BUFFER_SIZE],
-        invoke_id: 0,
-        phase: 1,
-    };
+        if !device.can_set_clock() {
+            self.disconnect()?;
+            return Err(UsbError::Unsupported(
+                "the meter does not allow setting its clock".to_string(),
+            )
+            .into());
+        }
 
-    let result = protocol.execute();
+        let host_time = self.host_time.unwrap_or_else(Utc::now);
+        let new_time = options
+            .time_policy
+            .local_time(host_time, device.utc_offset_minutes);
 
-    // Release interface
-    protocol.handle.release_interface(0)?;
+        let applied = if options.dry_run {
+            info!("Dry run, the meter clock would be set to {}", new_time);
+            false
+        } else {
+            // Phase 8: Send the Set-Time action
+            self.send_set_time(&new_time)?;
 
-    result
-}
+            // Phase 9: Receive its confirmation
+            self.receive_action_result("set time response", MDC_ACT_SET_TIME)?;
+            info!("Meter clock set to {}", new_time);
+            true
+        };
 
-struct ProtocolHandler {
-    handle: rusb::DeviceHandle<rusb::GlobalContext>,
-    bulk_out: u8,
-    bulk_in: u8,
-    buffer: Vec<u8>,
-    invoke_id: u16,
-    phase: usize,
-}
+        self.disconnect()?;
+
+        Ok(SetTimeResult {
+            device,
+            clock: session.clock,
+            new_time,
+            applied,
+        })
+    }
+
+    /// Current state of the association with the meter
+    pub fn state(&self) -> AssociationState {
+        self.state
+    }
+
+    /// Run `operation`, leaving no association behind if it fails
+    fn guarded<R>(&mut self, operation: impl FnOnce(&mut Self) -> Result<R>) -> Result<R> {
+        let result = operation(self);
+        if let Err(error) = &result {
+            self.recover(error);
+        }
+        result
+    }
+
+    /// End the association after a failure: abort it on a protocol violation,
+    /// release it otherwise
+    fn recover(&mut self, error: &anyhow::Error) {
+        if !self.state.is_associated() {
+            return;
+        }
+
+        let violation = matches!(
+            error.downcast_ref::<UsbError>(),
+            Some(
+                UsbError::Protocol(_)
+                    | UsbError::Parse(_)
+                    | UsbError::InvokeIdMismatch { .. }
+                    | UsbError::OutOfOrderResponse { .. }
+                    | UsbError::ApduTooLarge { .. }
+            )
+        );
+        let cancelled = matches!(error.downcast_ref(), Some(UsbError::Cancelled));
+        if violation || cancelled || self.state == AssociationState::Disassociating {
+            self.abort();
+        } else if let Err(release_error) = self.disconnect() {
+            warn!("Release after failure did not complete: {}", release_error);
+            self.abort();
+        }
+    }
+
+    fn abort(&mut self) {
+        warn!("Aborting the association");
+        if let Err(error) = self.send("abort", &Apdu::Abort(AbortReason::UNDEFINED)) {
+            warn!("Failed to send abort: {}", error);
+        }
+        self.state = AssociationState::Disconnected;
+    }
+
+    /// Phases 1 to 7: associate, learn the configuration and read the MDS
+    /// attributes
+    fn connect(&mut self, time_policy: TimePolicy) -> Result<Session> {
+        self.state = AssociationState::Disconnected;
+        self.invokes.reset();
+        self.last_answer = None;
+        self.framer.reset();
+        self.meter_max_apdu_size = None;
 
-impl ProtocolHandler {
-    fn execute(&mut self) -> Result<Vec<GlucoseSample>> {
         // Phase 1: Initial control transfer
+        self.transport
+            .set_timeout(self.options.timeouts.association);
         self.control_transfer_in()?;
 
         // Phase 2: Wait for pairing request
-        self.bulk_in("pairing request", 64)?;
+        let request = match self.receive("pairing request")? {
+            Apdu::AssociationRequest(request) => request,
+            other => return Err(unexpected("association request", &other)),
+        };
+        self.state = AssociationState::Associating;
+        self.transport.set_timeout(self.options.timeouts.response);
+        let decision = association::evaluate(&request, &self.known_configurations)?;
 
         // Phase 3: Send pairing confirmation
-        self.send_pairing_confirmation()?;
+        self.send_pairing_confirmation(&decision)?;
+
+        let association = match decision.info {
+            Some(info) if decision.result.is_accepted() => info,
+            _ => {
+                self.state = AssociationState::Disconnected;
+                return Err(UsbError::AssociationRejected(format!(
+                    "{:?} (code {})",
+                    decision.result,
+                    decision.result.code()
+                ))
+                .into());
+            }
+        };
+        info!(
+            "Associated with agent {} (dev-config-id 0x{:04X})",
+            association.system_id, association.dev_config_id
+        );
+
+        let configuration = match decision.known_configuration {
+            Some(known) => {
+                info!("Known configuration, skipping config report");
+                self.state = AssociationState::Operating;
+                known.configuration
+            }
+            None => {
+                self.state = AssociationState::Configuring;
 
-        // Phase 4: Receive config info
-        let bytes_read = self.bulk_in("config info", BUFFER_SIZE)?;
-        self.update_invoke_id(6)?;
+                // Phase 4: Receive config info
+                let (invoke_id, report) = self.receive_config_report()?;
+                let configuration = DeviceConfiguration::from_report(&report)?;
 
-        // Parse config to get PM store handle
-        let pm_store_handle = self.parse_pm_store_handle(bytes_read)?;
-        info!("PM Store handle: {}", pm_store_handle);
+                // Nothing to download from a meter without a PM-store
+                configuration.pm_store()?;
 
-        // Phase 5: Send config received confirmation
-        self.send_config_confirmation()?;
+                // Phase 5: Send config received confirmation
+                self.send_config_confirmation(invoke_id, report.config_report_id)?;
+                self.state = AssociationState::Operating;
+                configuration
+            }
+        };
 
         // Phase 6: Request MDS attributes
         self.request_mds_attributes()?;
 
         // Phase 7: Receive MDS response
-        self.bulk_in("MDS attribute answer", BUFFER_SIZE)?;
-        self.update_invoke_id(6)?;
-
-        // Phase 8: Send action request for segment info
-        self.send_segment_info_request(pm_store_handle)?;
-
-        // Phase 9: Receive action response
-        self.bulk_in("action request response", BUFFER_SIZE)?;
-        self.update_invoke_id(6)?;
-
-        // Phase 10: Request data segments
-        self.request_data_segments(pm_store_handle)?;
-
-        // Phase 11: Receive segment headers
-        self.bulk_in("segment headers", BUFFER_SIZE)?;
-        self.update_invoke_id(6)?;
-
-        // Phase 12: Read all data segments
-        let samples = self.read_data_segments(pm_store_handle)?;
-
-        // Phase 13: Disconnect cleanly
-        self.disconnect()?;
+        let device = match self.receive_response("MDS attribute answer")? {
+            DataMessage::GetResult(result) => DeviceIdentity::from_attributes(&result.attributes)?,
+            other => return Err(unexpected_message("MDS attributes", &other)),
+        };
+        self.meter_max_apdu_size = device.max_apdu_size();
+        info!(
+            "Meter {} {} (serial {})",
+            device.manufacturer.as_deref().unwrap_or("?"),
+            device.model.as_deref().unwrap_or("?"),
+            device.serial_number.as_deref().unwrap_or("?")
+        );
+
+        let clock = device.clock.map(|meter_clock| {
+            let meter_time = time_policy.resolve(meter_clock, device.utc_offset_minutes);
+            ClockCheck::measure(&meter_time, self.host_time.unwrap_or_else(Utc::now))
+        });
+        if let Some(check) = &clock {
+            if check.reset_detected {
+                warn!("Meter clock was reset (reads {})", check.meter_time);
+            } else if check.drift_seconds.abs() > CLOCK_DRIFT_WARNING_SECONDS {
+                warn!("Meter clock is off by {} seconds", check.drift_seconds);
+            } else {
+                info!("Meter clock is off by {} seconds", check.drift_seconds);
+            }
+        }
 
-        Ok(samples)
+        Ok(Session {
+            association,
+            configuration,
+            device,
+            clock,
+        })
     }
 
     fn control_transfer_in(&mut self) -> Result<()> {
         info!("Phase {}: Initial control transfer", self.phase);
+        self.begin_phase("initial control transfer");
 
         let mut buf = [0u8; 2];
-        let result = self.handle.read_control(
-            rusb::request_type(Direction::In, rusb::RequestType::Standard, rusb::Recipient::Device),
-            rusb::constants::LIBUSB_REQUEST_GET_STATUS,
-            0,
-            0,
-            &mut buf,
-            TIMEOUT,
-        )?;
+        let result = self.transport.control_in(&mut buf)?;
 
         debug!("Control transfer received {} bytes", result);
         self.phase += 1;
         Ok(())
     }
 
+    fn begin_phase(&mut self, name: &str) {
+        self.transport.begin_phase(self.phase, name);
+        self.report(Progress::Phase {
+            phase: self.phase,
+            name: name.to_string(),
+        });
+    }
+
+    fn report(&mut self, progress: Progress) {
+        if let Some(observer) = &mut self.observer {
+            observer(&progress);
+        }
+    }
+
     fn bulk_out(&mut self, name: &str, data: &[u8]) -> Result<()> {
         info!("Phase {}: Sending {}", self.phase, name);
+        self.begin_phase(name);
         debug_hex_dump(name, data);
 
-        let written = self.handle.write_bulk(self.bulk_out, data, TIMEOUT)?;
+        let written = self.transport.write(data)?;
 
         if written != data.len() {
             return Err(UsbError::Transfer(format!(
@@ -201,319 +632,706 @@ impl ProtocolHandler {
         Ok(())
     }
 
-    fn bulk_in(&mut self, name: &str, max_len: usize) -> Result<usize> {
+    fn bulk_in(&mut self, name: &str) -> Result<Vec<u8>> {
+        if self.cancel.is_cancelled() {
+            return Err(UsbError::Cancelled.into());
+        }
         info!("Phase {}: Receiving {}", self.phase, name);
+        self.begin_phase(name);
+
+        let max_apdu_size = self.max_apdu_size();
+        let retry = self.options.retry;
+        let transport = &mut self.transport;
+        let data = self.framer.read_apdu(
+            |buffer| retry.run("Bulk read", || transport.read(buffer)),
+            max_apdu_size,
+        )?;
 
-        self.buffer.resize(max_len, 0);
-        let bytes_read = self.handle.read_bulk(self.bulk_in, &mut self.buffer[..max_len], TIMEOUT)?;
-
-        debug!("Read {} bytes", bytes_read);
-        debug_hex_dump(name, &self.buffer[..bytes_read]);
+        debug!("Read {} bytes", data.len());
+        debug_hex_dump(name, &data);
 
         self.phase += 1;
-        Ok(bytes_read)
+        Ok(data)
     }
 
-    fn update_invoke_id(&mut self, offset: usize) -> Result<()> {
-        if self.buffer.len() < offset + 2 {
-            return Err(UsbError::Parse("Buffer too small for invoke_id".to_string()).into());
-        }
+    /// Limit set by the options, lowered to the meter's own once it is known
+    fn max_apdu_size(&self) -> usize {
+        let max_apdu_size = self.options.max_apdu_size.unwrap_or(DEFAULT_MAX_APDU_SIZE);
+        self.meter_max_apdu_size
+            .map_or(max_apdu_size, |meter| meter.min(max_apdu_size))
+    }
 
-        self.invoke_id = u16::from_be_bytes([self.buffer[offset], self.buffer[offset + 1]]);
-        debug!("Updated invoke_id to: {}", self.invoke_id);
-        Ok(())
+    fn send(&mut self, name: &str, apdu: &Apdu) -> Result<()> {
+        debug!("Sending {}", apdu.name());
+        self.bulk_out(name, &apdu.encode())
     }
 
-    fn send_pairing_confirmation(&mut self) -> Result<()> {
-        let mut msg = Vec::new();
-        write_be16(&mut msg, APDU_TYPE_ASSOCIATION_RESPONSE);
-        write_be16(&mut msg, 44); // length
-        write_be16(&mut msg, 0x0003); // accepted-unknown-config
-        write_be16(&mut msg, 20601); // data-proto-id
-        write_be16(&mut msg, 38); // data-proto-info length
-        write_be32(&mut msg, 0x80000002); // protocolVersion
-        write_be16(&mut msg, 0x8000); // encoding-rules = MDER
-        write_be32(&mut msg, 0x80000000); // nomenclatureVersion
-        write_be32(&mut msg, 0); // functionalUnits
-        write_be32(&mut msg, 0x80000000); // systemType
-        write_be16(&mut msg, 8); // system-id length
-        write_be32(&mut msg, 0x12345678); // system-id high
-        write_be32(&mut msg, 0); // padding
-        write_be32(&mut msg, 0); // padding
-        write_be32(&mut msg, 0); // padding
-        write_be16(&mut msg, 0); // padding
+    /// Receive an APDU the meter may send in the current state
+    fn receive(&mut self, name: &str) -> Result<Apdu> {
+        let data = self.bulk_in(name)?;
+        let apdu = Apdu::decode(&data)?;
+        debug!("Received {}", apdu.name());
 
-        self.bulk_out("pairing confirmation", &msg)
-    }
+        if let Apdu::Abort(reason) = apdu {
+            self.state = AssociationState::Disconnected;
+            return Err(UsbError::Aborted(reason.0).into());
+        }
+        if !self.state.accepts(&apdu) {
+            return Err(UsbError::Protocol(format!(
+                "Received {} while {:?}",
+                apdu.name(),
+                self.state
+            ))
+            .into());
+        }
+        if let (
+            Apdu::ReleaseRequest(_),
+            AssociationState::Configuring | AssociationState::Operating,
+        ) = (&apdu, self.state)
+        {
+            self.send(
+                "release response",
+                &Apdu::ReleaseResponse(ReleaseReason::NORMAL),
+            )?;
+            self.state = AssociationState::Disconnected;
+            return Err(UsbError::Protocol(format!(
+                "Meter released the association during {}",
+                name
+            ))
+            .into());
+        }
 
-    fn parse_pm_store_handle(&self, bytes_read: usize) -> Result<u16> {
-        // Look for PM Store object in config
-        let mut offset = 24;
+        Ok(apdu)
+    }
 
-        if bytes_read < offset + 4 {
-            return Err(UsbError::Parse("Config response too small".to_string()).into());
+    fn receive_data(&mut self, name: &str) -> Result<DataApdu> {
+        match self.receive(name)? {
+            Apdu::Presentation(data) => Ok(data),
+            other => Err(unexpected(name, &other)),
         }
+    }
 
-        let count = u16::from_be_bytes([self.buffer[offset], self.buffer[offset + 1]]);
-        offset += 4; // skip count and dummy
+    fn send_data(&mut self, name: &str, invoke_id: u16, message: DataMessage) -> Result<()> {
+        self.send(name, &Apdu::Presentation(DataApdu { invoke_id, message }))
+    }
 
-        debug!("Config has {} objects", count);
+    /// Send a request under a fresh invoke-id
+    fn send_request(&mut self, name: &str, message: DataMessage) -> Result<()> {
+        let invoke_id = self.invokes.start();
+        self.send_data(name, invoke_id, message)
+    }
 
-        for i in 0..count {
-            if offset + 8 > bytes_read {
-                break;
+    /// Answer the meter's invocation `invoke_id`
+    fn send_answer(&mut self, name: &str, invoke_id: u16, message: DataMessage) -> Result<()> {
+        let apdu = Apdu::Presentation(DataApdu { invoke_id, message });
+        self.send(name, &apdu)?;
+        self.last_answer = Some(apdu);
+        Ok(())
+    }
+
+    /// Receive the response to the oldest outstanding request
+    ///
+    /// Errors and rejects the meter answers with become [`UsbError::RemoteError`]
+    /// and [`UsbError::RemoteReject`].
+    fn receive_response(&mut self, name: &str) -> Result<DataMessage> {
+        loop {
+            let data = self.receive_data(name)?;
+            if is_invocation(&data.message) {
+                self.handle_stray_invocation(data)?;
+                continue;
             }
 
-            let obj_class = u16::from_be_bytes([self.buffer[offset], self.buffer[offset + 1]]);
-            let obj_handle = u16::from_be_bytes([self.buffer[offset + 2], self.buffer[offset + 3]]);
-            let _attr_count = u16::from_be_bytes([self.buffer[offset + 4], self.buffer[offset + 5]]);
-            let obj_size = u16::from_be_bytes([self.buffer[offset + 6], self.buffer[offset + 7]]);
+            if self.invokes.complete(data.invoke_id)? == ResponseMatch::Duplicate {
+                warn!(
+                    "Ignoring duplicate response to invoke-id {}",
+                    data.invoke_id
+                );
+                continue;
+            }
 
-            debug!(
-                "Object {}: class={}, handle={}, size={}",
-                i, obj_class, obj_handle, obj_size
-            );
+            return match data.message {
+                DataMessage::Error(error) => Err(UsbError::RemoteError(error.error_value).into()),
+                DataMessage::Reject(reject) => Err(UsbError::RemoteReject(reject.problem).into()),
+                message => Ok(message),
+            };
+        }
+    }
 
-            if obj_class == MDC_MOC_VMO_PMSTORE {
-                return Ok(obj_handle);
+    /// Receive a confirmed event report of `event_type`, returning it with the
+    /// invoke-id to answer
+    fn receive_event_report(&mut self, name: &str, event_type: u16) -> Result<(u16, EventReport)> {
+        loop {
+            let data = self.receive_data(name)?;
+            match data.message {
+                DataMessage::ConfirmedEventReport(report) if report.event_type == event_type => {
+                    if self.invokes.agent_invoke(data.invoke_id) {
+                        self.repeat_answer(data.invoke_id)?;
+                        continue;
+                    }
+                    return Ok((data.invoke_id, report));
+                }
+                message if is_invocation(&message) => {
+                    self.handle_stray_invocation(DataApdu {
+                        invoke_id: data.invoke_id,
+                        message,
+                    })?;
+                }
+                message => {
+                    if self.invokes.complete(data.invoke_id)? == ResponseMatch::Duplicate {
+                        warn!(
+                            "Ignoring duplicate response to invoke-id {}",
+                            data.invoke_id
+                        );
+                        continue;
+                    }
+                    return Err(unexpected_message(name, &message));
+                }
             }
+        }
+    }
 
-            offset += 8 + obj_size as usize;
+    /// Deal with an invocation of the meter nobody waits for: acknowledge
+    /// event reports, reject anything else that needs an answer
+    fn handle_stray_invocation(&mut self, data: DataApdu) -> Result<()> {
+        match data.message {
+            DataMessage::EventReport(report) => {
+                info!("Ignoring event report 0x{:04X}", report.event_type);
+                Ok(())
+            }
+            DataMessage::Action(action) => {
+                info!("Ignoring action 0x{:04X}", action.action_type);
+                Ok(())
+            }
+            DataMessage::ConfirmedEventReport(_) if self.invokes.agent_invoke(data.invoke_id) => {
+                self.repeat_answer(data.invoke_id)
+            }
+            DataMessage::ConfirmedEventReport(report) => {
+                warn!(
+                    "Acknowledging unexpected event report 0x{:04X}",
+                    report.event_type
+                );
+                let message = DataMessage::EventReportResult(EventReport {
+                    info: Vec::new(),
+                    ..report
+                });
+                self.send_answer("event report ACK", data.invoke_id, message)
+            }
+            other => {
+                warn!("Rejecting {} from the meter", other.name());
+                let message = DataMessage::Reject(RejectResult {
+                    problem: RORJ_UNRECOGNIZED_OPERATION,
+                });
+                self.send_data("reject", data.invoke_id, message)
+            }
         }
+    }
 
-        Err(UsbError::Parse("PM Store not found in config".to_string()).into())
+    /// Send the last answer again for an invocation the meter repeated
+    fn repeat_answer(&mut self, invoke_id: u16) -> Result<()> {
+        warn!("Meter repeated invoke-id {}", invoke_id);
+        match self.last_answer.clone() {
+            Some(answer) => self.send("repeated answer", &answer),
+            None => Ok(()),
+        }
     }
 
-    fn send_config_confirmation(&mut self) -> Result<()> {
-        let mut msg = Vec::new();
-        write_be16(&mut msg, APDU_TYPE_PRESENTATION_APDU);
-        write_be16(&mut msg, 22);
-        write_be16(&mut msg, 20);
-        write_be16(&mut msg, self.invoke_id);
-        write_be16(&mut msg, DATA_APDU_RESPONSE_CONFIRMED_EVENT_REPORT);
-        write_be16(&mut msg, 14);
-        write_be16(&mut msg, 0); // obj-handle
-        write_be32(&mut msg, 0); // currentTime
-        write_be16(&mut msg, EVENT_TYPE_MDC_NOTI_CONFIG);
-        write_be16(&mut msg, 4);
-        write_be16(&mut msg, 0x4000); // config-report-id
-        write_be16(&mut msg, 0); // config-result = accepted
+    fn receive_action_result(&mut self, name: &str, action_type: u16) -> Result<Action> {
+        match self.receive_response(name)? {
+            DataMessage::ActionResult(action) if action.action_type == action_type => Ok(action),
+            other => Err(unexpected_message(name, &other)),
+        }
+    }
 
-        self.bulk_out("config confirmation", &msg)
+    fn send_pairing_confirmation(&mut self, decision: &AssociationDecision) -> Result<()> {
+        // A rejection carries an empty data protocol
+        let selected_data_proto = if decision.result.is_accepted() {
+            let info = association::manager_information(
+                decision.protocol_version,
+                &self.manager_system_id,
+            );
+            DataProto {
+                id: DATA_PROTO_ID_20601,
+                info: info.to_bytes(),
+            }
+        } else {
+            DataProto {
+                id: 0,
+                info: Vec::new(),
+            }
+        };
+
+        let response = Apdu::AssociationResponse(AssociationResponse {
+            result: decision.result.code(),
+            selected_data_proto,
+        });
+
+        self.send("pairing confirmation", &response)
+    }
+
+    fn receive_config_report(&mut self) -> Result<(u16, ConfigReport)> {
+        let (invoke_id, report) = self.receive_event_report("config info", MDC_NOTI_CONFIG)?;
+        Ok((invoke_id, ConfigReport::from_bytes(&report.info)?))
+    }
+
+    fn send_config_confirmation(&mut self, invoke_id: u16, config_report_id: u16) -> Result<()> {
+        let response = ConfigReportResponse {
+            config_report_id,
+            config_result: CONFIG_RESULT_ACCEPTED,
+        };
+
+        let message = DataMessage::EventReportResult(EventReport {
+            obj_handle: 0,
+            event_time: 0,
+            event_type: MDC_NOTI_CONFIG,
+            info: response.to_bytes(),
+        });
+
+        self.send_answer("config confirmation", invoke_id, message)
     }
 
     fn request_mds_attributes(&mut self) -> Result<()> {
-        let mut msg = Vec::new();
-        write_be16(&mut msg, APDU_TYPE_PRESENTATION_APDU);
-        write_be16(&mut msg, 14);
-        write_be16(&mut msg, 12);
-        write_be16(&mut msg, self.invoke_id + 1);
-        write_be16(&mut msg, DATA_APDU_INVOKE_GET);
-        write_be16(&mut msg, 6);
-        write_be16(&mut msg, 0); // obj-handle
-        write_be32(&mut msg, 0); // currentTime
+        let message = DataMessage::Get(GetArgument {
+            obj_handle: 0,
+            attribute_ids: Vec::new(),
+        });
 
-        self.bulk_out("MDS attribute request", &msg)
+        self.send_request("MDS attribute request", message)
     }
 
     fn send_segment_info_request(&mut self, pm_store_handle: u16) -> Result<()> {
-        let mut msg = Vec::new();
-        write_be16(&mut msg, APDU_TYPE_PRESENTATION_APDU);
-        write_be16(&mut msg, 20);
-        write_be16(&mut msg, 18);
-        write_be16(&mut msg, self.invoke_id + 1);
-        write_be16(&mut msg, DATA_APDU_INVOKE_CONFIRMED_ACTION);
-        write_be16(&mut msg, 12);
-        write_be16(&mut msg, pm_store_handle);
-        write_be16(&mut msg, ACTION_TYPE_MDC_ACT_SEG_GET_INFO);
-        write_be16(&mut msg, 6);
-        write_be16(&mut msg, 1); // all segments
-        write_be16(&mut msg, 2);
-        write_be16(&mut msg, 0);
-
-        self.bulk_out("action request", &msg)
-    }
-
-    fn request_data_segments(&mut self, pm_store_handle: u16) -> Result<()> {
-        let mut msg = Vec::new();
-        write_be16(&mut msg, APDU_TYPE_PRESENTATION_APDU);
-        write_be16(&mut msg, 16);
-        write_be16(&mut msg, 14);
-        write_be16(&mut msg, self.invoke_id + 1);
-        write_be16(&mut msg, DATA_APDU_INVOKE_CONFIRMED_ACTION);
-        write_be16(&mut msg, 8);
-        write_be16(&mut msg, pm_store_handle);
-        write_be16(&mut msg, ACTION_TYPE_MDC_ACT_SEG_TRIG_XFER);
-        write_be16(&mut msg, 2);
-        write_be16(&mut msg, 0); // segment
-
-        self.bulk_out("request segments", &msg)
-    }
-
-    fn read_data_segments(&mut self, pm_store_handle: u16) -> Result<Vec<GlucoseSample>> {
-        let mut samples = Vec::new();
-        let mut sample_id = 0;
+        let message = DataMessage::ConfirmedAction(Action {
+            obj_handle: pm_store_handle,
+            action_type: MDC_ACT_SEG_GET_INFO,
+            info: SegmentSelection::AllSegments.to_bytes(),
+        });
 
-        loop {
-            // Read segment data
-            let bytes_read = self.bulk_in("data segment", BUFFER_SIZE)?;
+        self.send_request("action request", message)
+    }
 
-            if bytes_read < 33 {
-                warn!("Segment too small: {} bytes", bytes_read);
-                break;
-            }
+    fn read_segments(
+        &mut self,
+        pm_store_handle: u16,
+        configuration: &DeviceConfiguration,
+    ) -> Result<Vec<PmSegment>> {
+        // Phase 8: Send action request for segment info
+        self.send_segment_info_request(pm_store_handle)?;
+
+        // Phase 9: Receive action response
+        let action = self.receive_action_result("action request response", MDC_ACT_SEG_GET_INFO)?;
+        let segments =
+            PmSegment::list(&SegmentInfo::list_from_bytes(&action.info)?, configuration)?;
+        info!("PM-store holds {} segments", segments.len());
+
+        Ok(segments)
+    }
+
+    fn send_clear_segments(
+        &mut self,
+        pm_store_handle: u16,
+        selection: &SegmentSelection,
+    ) -> Result<()> {
+        let message = DataMessage::ConfirmedAction(Action {
+            obj_handle: pm_store_handle,
+            action_type: MDC_ACT_SEG_CLR,
+            info: selection.to_bytes(),
+        });
+
+        self.send_request("clear segments", message)
+    }
 
-            let status = self.buffer[32];
-            self.update_invoke_id(6)?;
-
-            // Extract data for ACK
-            let u0 = u32::from_be_bytes([
-                self.buffer[22],
-                self.buffer[23],
-                self.buffer[24],
-                self.buffer[25],
-            ]);
-            let u1 = u32::from_be_bytes([
-                self.buffer[26],
-                self.buffer[27],
-                self.buffer[28],
-                self.buffer[29],
-            ]);
-            let u2 = u16::from_be_bytes([self.buffer[30], self.buffer[31]]);
+    fn send_set_time(&mut self, time: &NaiveDateTime) -> Result<()> {
+        let mut info = encode_absolute_time(time).to_vec();
+        info.extend_from_slice(&SET_TIME_ACCURACY.to_be_bytes());
+
+        let message = DataMessage::ConfirmedAction(Action {
+            obj_handle: 0,
+            action_type: MDC_ACT_SET_TIME,
+            info,
+        });
+
+        self.send_request("set time", message)
+    }
+
+    fn request_data_segments(&mut self, pm_store_handle: u16, instance: u16) -> Result<()> {
+        let message = DataMessage::ConfirmedAction(Action {
+            obj_handle: pm_store_handle,
+            action_type: MDC_ACT_SEG_TRIG_XFER,
+            info: instance.to_be_bytes().to_vec(),
+        });
+
+        self.send_request("request segments", message)
+    }
+
+    /// Phases 10 to 12: transfer one segment into `transfer`, returning the
+    /// meter's answer to the trigger
+    ///
+    /// On failure `transfer` keeps the entries acknowledged so far.
+    fn transfer_segment(
+        &mut self,
+        pm_store_handle: u16,
+        instance: u16,
+        decoder: &SampleDecoder,
+        sample_id: &mut usize,
+        transfer: &mut SegmentTransfer,
+    ) -> Result<u16> {
+        // Phase 10: Request the segment data
+        self.request_data_segments(pm_store_handle, instance)?;
+
+        // Phase 11: Receive segment headers
+        let action = self.receive_action_result("segment headers", MDC_ACT_SEG_TRIG_XFER)?;
+        let response = TriggerTransferResponse::from_bytes(&action.info)?;
+        if response.result != TSXR_SUCCESSFUL {
+            return Ok(response.result);
+        }
+
+        // Phase 12: Read all data of the segment
+        self.transport
+            .set_timeout(self.options.timeouts.segment_data);
+        self.read_data_segments(pm_store_handle, decoder, sample_id, transfer)?;
+        self.transport.set_timeout(self.options.timeouts.response);
+
+        Ok(TSXR_SUCCESSFUL)
+    }
+
+    fn read_data_segments(
+        &mut self,
+        pm_store_handle: u16,
+        decoder: &SampleDecoder,
+        sample_id: &mut usize,
+        transfer: &mut SegmentTransfer,
+    ) -> Result<()> {
+        loop {
+            // Read segment data
+            let (invoke_id, report) =
+                self.receive_event_report("data segment", MDC_NOTI_SEGMENT_DATA)?;
+            let event = SegmentDataEvent::from_bytes(&report.info)?;
 
             // Parse samples from segment
-            let segment_samples = self.parse_segment_samples(&mut sample_id, bytes_read)?;
-            samples.extend(segment_samples);
+            let samples = decoder.decode(sample_id, &event)?;
 
             // Send ACK
-            self.send_segment_ack(pm_store_handle, u0, u1, u2)?;
+            self.send_segment_ack(pm_store_handle, invoke_id, &event.descriptor)?;
+
+            // Only count entries once the meter knows they arrived
+            let descriptor = &event.descriptor;
+            self.tally.received_entries = self
+                .tally
+                .received_entries
+                .saturating_add(descriptor.entry_count);
+            self.tally.decoded_samples = self.tally.decoded_samples.saturating_add(samples.len());
+            self.report(Progress::EntriesReceived {
+                segment: descriptor.instance,
+                received_entries: self.tally.received_entries,
+                decoded_samples: self.tally.decoded_samples,
+                expected_entries: self.tally.expected_entries,
+            });
+            transfer.samples.extend(samples);
+            transfer.entry_count = transfer.entry_count.saturating_add(descriptor.entry_count);
+            if let Some(last) = descriptor.entry_count.checked_sub(1) {
+                let index = descriptor.entry_index.checked_add(last).ok_or_else(|| {
+                    UsbError::Parse(format!(
+                        "Entry index {} overflows the segment",
+                        descriptor.entry_index
+                    ))
+                })?;
+                transfer.last_entry = Some(EntryPosition {
+                    segment: descriptor.instance,
+                    index,
+                });
+            }
 
             // Check if this was the last segment
-            if status & 0x40 != 0 {
+            if descriptor.status & SEGM_EVT_STATUS_LAST_ENTRY != 0 {
                 info!("Last segment received");
                 break;
             }
         }
 
-        Ok(samples)
+        Ok(())
+    }
+
+    fn send_segment_ack(
+        &mut self,
+        pm_store_handle: u16,
+        invoke_id: u16,
+        descriptor: &SegmentDataDescriptor,
+    ) -> Result<()> {
+        let result = SegmentDataDescriptor {
+            status: SEGM_EVT_STATUS_MANAGER_CONFIRM,
+            ..*descriptor
+        };
+
+        let message = DataMessage::EventReportResult(EventReport {
+            obj_handle: pm_store_handle,
+            event_time: 0xFFFFFFFF, // relative time
+            event_type: MDC_NOTI_SEGMENT_DATA,
+            info: result.to_bytes(),
+        });
+
+        self.send_answer("segment ACK", invoke_id, message)
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        self.transport.set_timeout(self.options.timeouts.release);
+        self.send(
+            "release request",
+            &Apdu::ReleaseRequest(ReleaseReason::NORMAL),
+        )?;
+        self.state = AssociationState::Disassociating;
+
+        loop {
+            match self.receive("release confirmation")? {
+                Apdu::ReleaseResponse(_) => break,
+                Apdu::ReleaseRequest(_) => {
+                    // Both sides released at once
+                    self.send(
+                        "release response",
+                        &Apdu::ReleaseResponse(ReleaseReason::NORMAL),
+                    )?;
+                    break;
+                }
+                other => debug!("Ignoring {} while releasing", other.name()),
+            }
+        }
+        self.state = AssociationState::Disconnected;
+
+        info!("Disconnected cleanly");
+        Ok(())
     }
+}
+
+/// What the first phases of a session learned about the meter
+struct Session {
+    association: AssociationInfo,
+    configuration: DeviceConfiguration,
+    device: DeviceIdentity,
+    clock: Option<ClockCheck>,
+}
+
+/// What the data events of one segment carried
+#[derive(Default)]
+struct SegmentTransfer {
+    samples: Vec<GlucoseSample>,
+    /// Entries received, including those not turned into samples
+    entry_count: u32,
+    last_entry: Option<EntryPosition>,
+}
+
+/// Running totals of a transfer, for progress reports
+#[derive(Default)]
+struct TransferTally {
+    expected_entries: Option<u32>,
+    received_entries: u32,
+    decoded_samples: usize,
+}
+
+/// Turns the entries of segment data events into samples
+struct SampleDecoder<'a> {
+    configuration: &'a DeviceConfiguration,
+    entry_map: &'a PmSegmentEntryMap,
+    options: &'a DownloadOptions,
+    /// UTC offset declared in the MDS attributes
+    meter_offset_minutes: Option<i16>,
+    clock: Option<&'a ClockCheck>,
+    /// Entries to keep in an incremental download
+    filter: EntryFilter,
+}
 
-    fn parse_segment_samples(
+impl SampleDecoder<'_> {
+    fn decode(
         &self,
         sample_id: &mut usize,
-        bytes_read: usize,
+        event: &SegmentDataEvent,
     ) -> Result<Vec<GlucoseSample>> {
         let mut samples = Vec::new();
 
-        if bytes_read < 32 {
-            return Ok(samples);
-        }
-
-        let nb_entries = u16::from_be_bytes([self.buffer[30], self.buffer[31]]) as usize;
+        let nb_entries = event.descriptor.entry_count;
         info!("Segment has {} entries", nb_entries);
+        if event
+            .descriptor
+            .entry_index
+            .checked_add(nb_entries)
+            .is_none()
+        {
+            return Err(UsbError::Parse(format!(
+                "{} entries from index {} overflow the segment",
+                nb_entries, event.descriptor.entry_index
+            ))
+            .into());
+        }
 
-        let mut offset = 30;
+        let entries = self.entry_map.decode_entries(&event.entries, nb_entries)?;
+        for (index, entry) in (event.descriptor.entry_index..).zip(entries) {
+            let Some(glucose) = glucose_element(&entry) else {
+                warn!("Segment entry without a numeric observation");
+                continue;
+            };
+            let Some(value) = observed_value(glucose)? else {
+                warn!("Segment entry without a glucose value");
+                continue;
+            };
+            let Some(mut time) = self.time(&entry) else {
+                warn!("Segment entry without a valid time");
+                continue;
+            };
+            if !self.filter.accepts(index, time.local) {
+                debug!("Skipping entry {} already downloaded", index);
+                continue;
+            }
 
-        for _ in 0..nb_entries {
-            if offset + 18 > bytes_read {
-                break;
+            let value = NumericValue {
+                value,
+                unit_code: self
+                    .configuration
+                    .numeric(glucose.handle)
+                    .and_then(|numeric| numeric.unit_code)
+                    .unwrap_or(MDC_DIM_MILLI_G_PER_DL),
+            };
+            if value.value.is_finite() && value.mg_dl().is_none() {
+                warn!(
+                    "Skipping sample at {} in unknown unit {}",
+                    time.local, value.unit_code
+                );
+                continue;
             }
 
-            // Decode BCD-encoded datetime
-            let cc = bcd_decode(self.buffer[offset + 6]);
-            let yy = bcd_decode(self.buffer[offset + 7]);
-            let mm = bcd_decode(self.buffer[offset + 8]);
-            let dd = bcd_decode(self.buffer[offset + 9]);
-            let hh = bcd_decode(self.buffer[offset + 10]);
-            let mn = bcd_decode(self.buffer[offset + 11]);
+            let mut status = SampleStatus::decode(
+                u16_value(glucose, MDC_ATTR_MSMT_STAT)?.unwrap_or(0),
+                device_status(&entry)?,
+                value.value,
+                glucose.metric_type.code == MDC_CONC_GLU_CONTROL,
+            );
 
-            // Read glucose value and status
-            let vv = u16::from_be_bytes([self.buffer[offset + 14], self.buffer[offset + 15]]);
-            let ss = u16::from_be_bytes([self.buffer[offset + 16], self.buffer[offset + 17]]);
+            if let Some(clock) = self.clock {
+                if clock.predates_reset(&time) {
+                    status.push(StatusFlag::BeforeClockReset);
+                } else if clock.corrected {
+                    time = time.corrected(clock.drift_seconds);
+                }
+            }
 
-            offset += 12;
+            let context = SampleContext::from_entry(&entry)?;
 
             debug!(
-                "Sample: {:02}{:02}/{:02}/{:02} {:02}:{:02} => mg/dL={}, status=0x{:02x}",
-                cc, yy, mm, dd, hh, mn, vv, ss
+                "Sample: {} => {:?}, status={:?}, context={:?}",
+                time.local, value, status, context
             );
 
-            // Only include valid samples (status == 0)
-            if ss == 0 {
-                let year = cc * 100 + yy;
-                let timestamp = format!("{:02}{:02}/{:02}/{:02} {:02}:{:02}", cc, yy, mm, dd, hh, mn);
-
-                // Create naive datetime and convert to epoch
-                let naive_dt = NaiveDateTime::parse_from_str(
-                    &format!("{}-{:02}-{:02} {:02}:{:02}:00", year, mm, dd, hh, mn),
-                    "%Y-%m-%d %H:%M:%S",
-                )?;
-
-                let epoch = chrono::Local.from_local_datetime(&naive_dt).unwrap().timestamp();
-
-                samples.push(GlucoseSample {
-                    id: *sample_id,
-                    epoch,
-                    timestamp,
-                    mg_dl: vv,
-                    mmol_l: vv as f64 / 18.0,
-                });
+            if status.is_flagged() && !self.options.include_flagged {
+                debug!("Dropping flagged sample at {}", time.local);
+                continue;
+            }
 
-                *sample_id += 1;
+            if time.is_ambiguous() {
+                warn!(
+                    "Sample at {} falls in a DST change ({:?}), assuming UTC{}",
+                    time.local,
+                    time.resolution,
+                    time.offset()
+                );
             }
+
+            samples.push(GlucoseSample {
+                id: *sample_id,
+                epoch: time.utc.timestamp(),
+                timestamp: time.local.format("%Y/%m/%d %H:%M").to_string(),
+                time,
+                mg_dl: value.mg_dl().map(|mg_dl| mg_dl.round() as u16),
+                mmol_l: value.mmol_l(),
+                status,
+                meal: context.meal,
+                tester: context.tester,
+                health: context.health,
+                carbs_g: context.carbs_g,
+            });
+
+            *sample_id += 1;
         }
 
         Ok(samples)
     }
 
-    fn send_segment_ack(&mut self, pm_store_handle: u16, u0: u32, u1: u32, u2: u16) -> Result<()> {
-        let mut msg = Vec::new();
-        write_be16(&mut msg, APDU_TYPE_PRESENTATION_APDU);
-        write_be16(&mut msg, 30);
-        write_be16(&mut msg, 28);
-        write_be16(&mut msg, self.invoke_id);
-        write_be16(&mut msg, DATA_APDU_RESPONSE_CONFIRMED_EVENT_REPORT);
-        write_be16(&mut msg, 22);
-        write_be16(&mut msg, pm_store_handle);
-        write_be32(&mut msg, 0xFFFFFFFF); // relative time
-        write_be16(&mut msg, EVENT_TYPE_MDC_NOTI_SEGMENT_DATA);
-        write_be16(&mut msg, 12);
-        write_be32(&mut msg, u0);
-        write_be32(&mut msg, u1);
-        write_be16(&mut msg, u2);
-        write_be16(&mut msg, 0x0080);
-
-        self.bulk_out("segment ACK", &msg)
+    /// Time of an entry; a declared Base-Offset-Time wins under
+    /// [`TimePolicy::Meter`] or when the entry has no absolute time
+    fn time(&self, entry: &SegmentEntry) -> Option<SampleTime> {
+        let policy = self.options.time_policy;
+        match (entry.absolute_time, entry.base_offset_time) {
+            (_, Some(time)) if policy == TimePolicy::Meter => SampleTime::from_base_offset(time),
+            (Some(local), _) => Some(policy.resolve(local, self.meter_offset_minutes)),
+            (None, Some(time)) => SampleTime::from_base_offset(time),
+            (None, None) => None,
+        }
     }
+}
 
-    fn disconnect(&mut self) -> Result<()> {
-        let mut msg = Vec::new();
-        write_be16(&mut msg, APDU_TYPE_ASSOCIATION_RELEASE_REQUEST);
-        write_be16(&mut msg, 2);
-        write_be16(&mut msg, 0); // normal release
+/// The glucose observation of an entry, skipping numeric context objects
+/// such as carbohydrates
+fn glucose_element(entry: &SegmentEntry) -> Option<&EntryElement> {
+    entry.elements.iter().find(|element| {
+        element.class_id == MDC_MOC_VMO_METRIC_NU
+            && element.metric_type.partition != MDC_PART_PHD_DM
+    })
+}
 
-        self.bulk_out("release request", &msg)?;
-        self.bulk_in("release confirmation", BUFFER_SIZE)?;
+/// Device and sensor annunciation reported alongside the reading
+fn device_status(entry: &SegmentEntry) -> Result<u16> {
+    let annunciation = entry.elements.iter().find(|element| {
+        element.class_id == MDC_MOC_VMO_METRIC_ENUM
+            && element.metric_type.code == MDC_GLU_METER_DEV_STATUS
+    });
 
-        info!("Disconnected cleanly");
-        Ok(())
+    match annunciation {
+        Some(element) => Ok(u16_value(element, MDC_ATTR_ENUM_OBS_VAL_BASIC_BIT_STR)?.unwrap_or(0)),
+        None => Ok(0),
+    }
+}
+
+fn u16_value(element: &EntryElement, attribute_id: u16) -> Result<Option<u16>> {
+    element
+        .get(attribute_id)
+        .map(|value| Reader::new(value).u16())
+        .transpose()
+}
+
+/// Observed value of a numeric entry element, SFLOAT or FLOAT encoded
+fn observed_value(element: &EntryElement) -> Result<Option<MderFloat>> {
+    if let Some(value) = element.get(MDC_ATTR_NU_VAL_OBS_BASIC) {
+        return Ok(Some(MderFloat::from_sfloat(Reader::new(value).u16()?)));
+    }
+    if let Some(value) = element.get(MDC_ATTR_NU_VAL_OBS_SIMP) {
+        return Ok(Some(MderFloat::from_float(Reader::new(value).u32()?)));
     }
+    Ok(None)
 }
 
-// Helper functions for writing big-endian values
-fn write_be16(buf: &mut Vec<u8>, val: u16) {
-    buf.extend_from_slice(&val.to_be_bytes());
+/// Whether the meter expects or may expect an answer to `message`
+fn is_invocation(message: &DataMessage) -> bool {
+    matches!(
+        message,
+        DataMessage::EventReport(_)
+            | DataMessage::ConfirmedEventReport(_)
+            | DataMessage::Get(_)
+            | DataMessage::Action(_)
+            | DataMessage::ConfirmedAction(_)
+    )
 }
 
-fn write_be32(buf: &mut Vec<u8>, val: u32) {
-    buf.extend_from_slice(&val.to_be_bytes());
+fn unexpected(expected: &str, apdu: &Apdu) -> anyhow::Error {
+    UsbError::Protocol(format!(
+        "Expected {} but received {}",
+        expected,
+        apdu.name()
+    ))
+    .into()
 }
 
-// Decode BCD (Binary-Coded Decimal)
-fn bcd_decode(val: u8) -> i32 {
-    let high = (val >> 4) & 0x0F;
-    let low = val & 0x0F;
-    (high * 10 + low) as i32
+fn unexpected_message(expected: &str, message: &DataMessage) -> anyhow::Error {
+    UsbError::Protocol(format!(
+        "Expected {} but received {}",
+        expected,
+        message.name()
+    ))
+    .into()
 }
 
 fn debug_hex_dump(name: &str, data: &[u8]) {
@@ -0,0 +1,289 @@
+//! PM-segment entry layout and decoding
+
+use super::apdu::mder::{Reader, Writer};
+use super::apdu::nomenclature::*;
+use super::apdu::SegmentInfo;
+use super::config::{AttributeValueMapEntry, DeviceConfiguration, MetricType};
+use super::mds::{absolute_time, text};
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+/// Handle of the glucose numeric when the configuration declares none
+const DEFAULT_GLUCOSE_HANDLE: u16 = 1;
+
+/// One PM-segment of the PM-store, as described by its Segment-Info
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PmSegment {
+    pub instance: u16,
+    /// PM-Segment-Label-String
+    pub label: Option<String>,
+    /// Segment-Usage-Count: number of entries currently stored
+    pub entry_count: Option<u32>,
+    /// Time of the first entry
+    pub start_time: Option<NaiveDateTime>,
+    /// Time of the last entry
+    pub end_time: Option<NaiveDateTime>,
+    pub entry_map: PmSegmentEntryMap,
+}
+
+impl PmSegment {
+    /// Decode a Segment-Info, falling back to [`PmSegmentEntryMap::legacy`]
+    /// when it carries no entry map
+    pub fn from_info(info: &SegmentInfo, configuration: &DeviceConfiguration) -> Result<Self> {
+        let attributes = &info.attributes;
+        let time = |attribute_id| -> Result<Option<NaiveDateTime>> {
+            Ok(attributes
+                .get(attribute_id)
+                .map(|value| Reader::new(value).bytes(8))
+                .transpose()?
+                .and_then(absolute_time))
+        };
+
+        let entry_map = match attributes.get(MDC_ATTR_PM_SEG_MAP) {
+            Some(map) => PmSegmentEntryMap::from_bytes(map)?,
+            None => {
+                info!(
+                    "Segment {} has no entry map, assuming the legacy layout",
+                    info.instance
+                );
+                PmSegmentEntryMap::legacy(configuration)
+            }
+        };
+
+        Ok(Self {
+            instance: info.instance,
+            label: attributes
+                .get(MDC_ATTR_PM_SEG_LABEL_STRING)
+                .map(|value| Reader::new(value).octet_string().map(text))
+                .transpose()?,
+            entry_count: attributes
+                .get(MDC_ATTR_SEG_USAGE_CNT)
+                .map(|value| Reader::new(value).u32())
+                .transpose()?,
+            start_time: time(MDC_ATTR_TIME_START_SEG)?,
+            end_time: time(MDC_ATTR_TIME_END_SEG)?,
+            entry_map,
+        })
+    }
+
+    /// Decode every Segment-Info of a MDC_ACT_SEG_GET_INFO result
+    pub fn list(infos: &[SegmentInfo], configuration: &DeviceConfiguration) -> Result<Vec<Self>> {
+        infos
+            .iter()
+            .map(|info| Self::from_info(info, configuration))
+            .collect()
+    }
+
+    /// Whether the meter reported the segment holds no entry
+    pub fn is_empty(&self) -> bool {
+        self.entry_count == Some(0)
+    }
+}
+
+/// Layout of the entries of a PM-segment (PM-Segment-Entry-Map attribute)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PmSegmentEntryMap {
+    /// Header fields present in each entry (`SEG_ELEM_HDR_*`)
+    pub header: u16,
+    pub elements: Vec<SegmentEntryElement>,
+}
+
+/// One observation carried by each entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentEntryElement {
+    pub class_id: u16,
+    pub metric_type: MetricType,
+    pub handle: u16,
+    pub attribute_value_map: Vec<AttributeValueMapEntry>,
+}
+
+impl PmSegmentEntryMap {
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut r = Reader::new(data);
+        Ok(Self {
+            header: r.u16()?,
+            elements: r.list(|r| {
+                Ok(SegmentEntryElement {
+                    class_id: r.u16()?,
+                    metric_type: MetricType {
+                        partition: r.u16()?,
+                        code: r.u16()?,
+                    },
+                    handle: r.u16()?,
+                    attribute_value_map: r.list(|r| {
+                        Ok(AttributeValueMapEntry {
+                            attribute_id: r.u16()?,
+                            attribute_len: r.u16()?,
+                        })
+                    })?,
+                })
+            })?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u16(self.header);
+        w.list(&self.elements, |w, element| {
+            w.u16(element.class_id)
+                .u16(element.metric_type.partition)
+                .u16(element.metric_type.code)
+                .u16(element.handle)
+                .list(&element.attribute_value_map, |w, entry| {
+                    w.u16(entry.attribute_id).u16(entry.attribute_len);
+                });
+        });
+        w.into_bytes()
+    }
+
+    /// Layout of the Accu-Chek meters that do not report their entry map:
+    /// absolute time followed by the glucose value and its status
+    pub fn legacy(configuration: &DeviceConfiguration) -> Self {
+        let glucose = configuration.numerics.first();
+        let metric_type = glucose
+            .and_then(|numeric| numeric.metric_type)
+            .unwrap_or(MetricType {
+                partition: MDC_PART_SCADA,
+                code: MDC_CONC_GLU_CAPILLARY_WHOLEBLOOD,
+            });
+
+        Self {
+            header: SEG_ELEM_HDR_ABSOLUTE_TIME,
+            elements: vec![SegmentEntryElement {
+                class_id: MDC_MOC_VMO_METRIC_NU,
+                metric_type,
+                handle: glucose.map_or(DEFAULT_GLUCOSE_HANDLE, |numeric| numeric.handle),
+                attribute_value_map: vec![
+                    AttributeValueMapEntry {
+                        attribute_id: MDC_ATTR_NU_VAL_OBS_BASIC,
+                        attribute_len: 2,
+                    },
+                    AttributeValueMapEntry {
+                        attribute_id: MDC_ATTR_MSMT_STAT,
+                        attribute_len: 2,
+                    },
+                ],
+            }],
+        }
+    }
+
+    /// Decode `count` entries laid out according to this map
+    pub fn decode_entries(&self, data: &[u8], count: u32) -> Result<Vec<SegmentEntry>> {
+        let mut r = Reader::new(data);
+        let mut entries = Vec::new();
+
+        for _ in 0..count {
+            entries.push(self.decode_entry(&mut r)?);
+        }
+
+        if !r.is_empty() {
+            warn!("{} bytes left after the segment entries", r.remaining());
+        }
+
+        Ok(entries)
+    }
+
+    fn decode_entry(&self, r: &mut Reader) -> Result<SegmentEntry> {
+        let mut entry = SegmentEntry::default();
+
+        if self.header & SEG_ELEM_HDR_ABSOLUTE_TIME != 0 {
+            let time = r.bytes(8)?;
+            entry.absolute_time = absolute_time(time);
+            if entry.absolute_time.is_none() {
+                warn!("Invalid absolute time in segment entry: {:02x?}", time);
+            }
+        }
+        if self.header & SEG_ELEM_HDR_RELATIVE_TIME != 0 {
+            entry.relative_time = Some(r.u32()?);
+        }
+        if self.header & SEG_ELEM_HDR_HIRES_RELATIVE_TIME != 0 {
+            let high = r.u32()? as u64;
+            let low = r.u32()? as u64;
+            entry.hires_relative_time = Some(high << 32 | low);
+        }
+        if self.header & SEG_ELEM_HDR_BO_TIME != 0 {
+            entry.base_offset_time = Some(BaseOffsetTime {
+                seconds: r.u32()?,
+                fraction: r.u16()?,
+                offset_minutes: r.i16()?,
+            });
+        }
+
+        for element in &self.elements {
+            let mut values = Vec::with_capacity(element.attribute_value_map.len());
+            for mapped in &element.attribute_value_map {
+                values.push(EntryValue {
+                    attribute_id: mapped.attribute_id,
+                    value: r.bytes(mapped.attribute_len as usize)?.to_vec(),
+                });
+            }
+
+            entry.elements.push(EntryElement {
+                class_id: element.class_id,
+                metric_type: element.metric_type,
+                handle: element.handle,
+                values,
+            });
+        }
+
+        Ok(entry)
+    }
+}
+
+/// Base-Offset-Time of an entry (IEEE 11073-20601:2019)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BaseOffsetTime {
+    /// Seconds since 1970-01-01 UTC
+    pub seconds: u32,
+    /// 1/65536 second
+    pub fraction: u16,
+    /// Local time offset from UTC
+    pub offset_minutes: i16,
+}
+
+/// One decoded PM-segment entry
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SegmentEntry {
+    pub absolute_time: Option<NaiveDateTime>,
+    /// 1/8 ms ticks
+    pub relative_time: Option<u32>,
+    /// Microseconds
+    pub hires_relative_time: Option<u64>,
+    pub base_offset_time: Option<BaseOffsetTime>,
+    pub elements: Vec<EntryElement>,
+}
+
+impl SegmentEntry {
+    /// First element of the given object class
+    pub fn element(&self, class_id: u16) -> Option<&EntryElement> {
+        self.elements
+            .iter()
+            .find(|element| element.class_id == class_id)
+    }
+}
+
+/// Attribute values of one element of an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryElement {
+    pub class_id: u16,
+    pub metric_type: MetricType,
+    pub handle: u16,
+    pub values: Vec<EntryValue>,
+}
+
+impl EntryElement {
+    pub fn get(&self, attribute_id: u16) -> Option<&[u8]> {
+        self.values
+            .iter()
+            .find(|value| value.attribute_id == attribute_id)
+            .map(|value| value.value.as_slice())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryValue {
+    pub attribute_id: u16,
+    pub value: Vec<u8>,
+}
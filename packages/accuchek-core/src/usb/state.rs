@@ -0,0 +1,55 @@
+//! Manager-side association state machine (IEEE 11073-20601)
+//!
+//! Every APDU received is checked against the current state; anything the
+//! standard does not allow there is a protocol violation, answered with ABRT.
+
+use super::apdu::Apdu;
+use serde::{Deserialize, Serialize};
+
+/// State of the association with the meter
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AssociationState {
+    /// No association; the meter opens one with an AARQ
+    #[default]
+    Disconnected,
+    /// AARQ received, AARE not sent yet
+    Associating,
+    /// Association accepted, waiting for the configuration report
+    Configuring,
+    /// Configuration known, data can be exchanged
+    Operating,
+    /// RLRQ sent, waiting for the RLRE
+    Disassociating,
+}
+
+impl AssociationState {
+    /// Whether the meter may send `apdu` in this state
+    pub fn accepts(self, apdu: &Apdu) -> bool {
+        use AssociationState::*;
+
+        matches!(
+            (self, apdu),
+            (_, Apdu::Abort(_))
+                | (Disconnected, Apdu::AssociationRequest(_))
+                | (
+                    Configuring | Operating,
+                    Apdu::Presentation(_) | Apdu::ReleaseRequest(_)
+                )
+                | (
+                    Disassociating,
+                    Apdu::Presentation(_) | Apdu::ReleaseRequest(_) | Apdu::ReleaseResponse(_)
+                )
+        )
+    }
+
+    /// Whether an association exists that has to be released or aborted
+    pub fn is_associated(self) -> bool {
+        matches!(
+            self,
+            AssociationState::Configuring
+                | AssociationState::Operating
+                | AssociationState::Disassociating
+        )
+    }
+}
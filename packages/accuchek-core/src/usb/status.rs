@@ -0,0 +1,161 @@
+//! Per-reading status decoding
+
+use super::apdu::float::MderFloat;
+use super::apdu::nomenclature::*;
+use serde::{Deserialize, Serialize};
+
+/// Measurement-Status bits and the flag each one raises
+const MEASUREMENT_FLAGS: [(u16, StatusFlag); 8] = [
+    (MSMT_STATUS_INVALID, StatusFlag::Invalid),
+    (MSMT_STATUS_QUESTIONABLE, StatusFlag::Questionable),
+    (MSMT_STATUS_NOT_AVAILABLE, StatusFlag::NotAvailable),
+    (
+        MSMT_STATUS_CALIBRATION_ONGOING,
+        StatusFlag::CalibrationOngoing,
+    ),
+    (MSMT_STATUS_TEST_DATA, StatusFlag::TestData),
+    (MSMT_STATUS_DEMO_DATA, StatusFlag::DemoData),
+    (MSMT_STATUS_EARLY_INDICATION, StatusFlag::EarlyIndication),
+    (MSMT_STATUS_MSMT_ONGOING, StatusFlag::MeasurementOngoing),
+];
+
+/// Device and sensor annunciation bits and the flag each one raises
+const DEVICE_FLAGS: [(u16, StatusFlag); 11] = [
+    (GLU_DEV_STATUS_BATTERY_LOW, StatusFlag::BatteryLow),
+    (
+        GLU_DEV_STATUS_SENSOR_MALFUNCTION,
+        StatusFlag::SensorMalfunction,
+    ),
+    (
+        GLU_DEV_STATUS_SAMPLE_SIZE_INSUFFICIENT,
+        StatusFlag::SampleSizeInsufficient,
+    ),
+    (
+        GLU_DEV_STATUS_STRIP_INSERTION,
+        StatusFlag::StripInsertionError,
+    ),
+    (
+        GLU_DEV_STATUS_STRIP_TYPE_INCORRECT,
+        StatusFlag::StripTypeIncorrect,
+    ),
+    (GLU_DEV_STATUS_RESULT_TOO_HIGH, StatusFlag::ResultTooHigh),
+    (GLU_DEV_STATUS_RESULT_TOO_LOW, StatusFlag::ResultTooLow),
+    (GLU_DEV_STATUS_TEMP_TOO_HIGH, StatusFlag::TemperatureTooHigh),
+    (GLU_DEV_STATUS_TEMP_TOO_LOW, StatusFlag::TemperatureTooLow),
+    (GLU_DEV_STATUS_READ_INTERRUPT, StatusFlag::ReadInterrupted),
+    (GLU_DEV_STATUS_GENERAL_FAULT, StatusFlag::DeviceFault),
+];
+
+/// Condition reported for one reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StatusFlag {
+    Invalid,
+    Questionable,
+    NotAvailable,
+    CalibrationOngoing,
+    TestData,
+    DemoData,
+    EarlyIndication,
+    MeasurementOngoing,
+    BatteryLow,
+    SensorMalfunction,
+    SampleSizeInsufficient,
+    StripInsertionError,
+    StripTypeIncorrect,
+    /// "HI" reading, above the meter's measuring range
+    ResultTooHigh,
+    /// "LO" reading, below the meter's measuring range
+    ResultTooLow,
+    TemperatureTooHigh,
+    TemperatureTooLow,
+    ReadInterrupted,
+    DeviceFault,
+    /// Control solution test rather than a blood sample
+    ControlSolution,
+    /// Recorded before the meter clock was reset; its time may be off
+    BeforeClockReset,
+    /// Status bits this library does not know about
+    Other,
+}
+
+/// Decoded status of one reading
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SampleStatus {
+    /// Raw Measurement-Status bits
+    pub measurement_status: u16,
+    /// Raw device and sensor annunciation bits, when the meter reports them
+    pub device_status: u16,
+    pub flags: Vec<StatusFlag>,
+}
+
+impl SampleStatus {
+    pub fn decode(
+        measurement_status: u16,
+        device_status: u16,
+        value: MderFloat,
+        control_solution: bool,
+    ) -> Self {
+        let mut status = SampleStatus {
+            measurement_status,
+            device_status,
+            flags: Vec::new(),
+        };
+
+        let mut known = MSMT_STATUS_VALIDATED_DATA;
+        for (bit, flag) in MEASUREMENT_FLAGS {
+            known |= bit;
+            if measurement_status & bit != 0 {
+                status.push(flag);
+            }
+        }
+        let mut unknown = measurement_status & !known;
+
+        let mut known = 0;
+        for (bit, flag) in DEVICE_FLAGS {
+            known |= bit;
+            if device_status & bit != 0 {
+                status.push(flag);
+            }
+        }
+        unknown |= device_status & !known;
+
+        match value {
+            MderFloat::Finite { .. } => {}
+            MderFloat::PositiveInfinity => status.push(StatusFlag::ResultTooHigh),
+            MderFloat::NegativeInfinity => status.push(StatusFlag::ResultTooLow),
+            MderFloat::NaN | MderFloat::NRes | MderFloat::Reserved => {
+                status.push(StatusFlag::NotAvailable)
+            }
+        }
+
+        if control_solution {
+            status.push(StatusFlag::ControlSolution);
+        }
+        if unknown != 0 {
+            status.push(StatusFlag::Other);
+        }
+
+        status
+    }
+
+    /// Whether anything but a plain, valid blood glucose reading was reported
+    ///
+    /// A low battery or a clock reset says nothing about the value itself and
+    /// is ignored.
+    pub fn is_flagged(&self) -> bool {
+        self.flags
+            .iter()
+            .any(|flag| !matches!(flag, StatusFlag::BatteryLow | StatusFlag::BeforeClockReset))
+    }
+
+    pub fn has(&self, flag: StatusFlag) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    pub(crate) fn push(&mut self, flag: StatusFlag) {
+        if !self.has(flag) {
+            self.flags.push(flag);
+        }
+    }
+}
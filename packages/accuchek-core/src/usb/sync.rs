@@ -0,0 +1,100 @@
+//! Incremental downloads
+//!
+//! A [`SyncCursor`] remembers the newest reading already downloaded. Segments
+//! whose Segment-Usage-Count or Time-End-Seg show nothing newer are not
+//! transferred at all; the entries of the others are filtered on the host,
+//! since a PM-segment can only be transferred as a whole.
+
+use super::segment::PmSegment;
+use chrono::NaiveDateTime;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Newest reading of a previous download
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncCursor {
+    /// Meter-local time of the reading
+    pub last_time: Option<NaiveDateTime>,
+    /// Where the reading is stored in the PM-store
+    pub last_entry: Option<EntryPosition>,
+}
+
+impl FromStr for SyncCursor {
+    type Err = anyhow::Error;
+
+    /// Parse the JSON form a download reports its cursor in
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(serde_json::from_str(s)?)
+    }
+}
+
+/// Position of an entry in the PM-store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryPosition {
+    /// PM-segment instance
+    pub segment: u16,
+    pub index: u32,
+}
+
+/// Which entries of a segment are newer than a cursor
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct EntryFilter {
+    after_index: Option<u32>,
+    after_time: Option<NaiveDateTime>,
+}
+
+impl EntryFilter {
+    pub(crate) fn accepts(&self, index: u32, time: NaiveDateTime) -> bool {
+        self.after_index.is_none_or(|after| index > after)
+            && self.after_time.is_none_or(|after| time > after)
+    }
+}
+
+impl SyncCursor {
+    /// Filter for the entries of `segment`, `None` if it holds nothing newer
+    pub(crate) fn filter(&self, segment: &PmSegment) -> Option<EntryFilter> {
+        let last_index = self
+            .last_entry
+            .filter(|position| position.segment == segment.instance)
+            .map(|position| position.index);
+
+        // Entry indices only hold while the segment has not been cleared
+        let by_index = |index| {
+            Some(EntryFilter {
+                after_index: Some(index),
+                after_time: None,
+            })
+        };
+        match (last_index, segment.entry_count) {
+            (Some(index), Some(count)) if index.checked_add(1) == Some(count) => {
+                info!("Segment {} has no new entries", segment.instance);
+                return None;
+            }
+            (Some(index), Some(count)) if count > index => return by_index(index),
+            (Some(_), Some(count)) => warn!(
+                "Segment {} holds {} entries, fewer than the cursor expects",
+                segment.instance, count
+            ),
+            (Some(index), None) => return by_index(index),
+            (None, _) => {}
+        }
+
+        let Some(last_time) = self.last_time else {
+            return Some(EntryFilter::default());
+        };
+
+        if segment.end_time.is_some_and(|end| end <= last_time) {
+            info!(
+                "Segment {} ends before {}, nothing new",
+                segment.instance, last_time
+            );
+            return None;
+        }
+
+        Some(EntryFilter {
+            after_index: None,
+            after_time: Some(last_time),
+        })
+    }
+}
@@ -0,0 +1,234 @@
+use super::device::open_device;
+use super::{AccuChekDevice, UsbError};
+use anyhow::Result;
+use log::{info, warn};
+use rusb::{Direction, Recipient, RequestType};
+use std::time::Duration;
+
+/// Default wait for each transfer
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default pause before a failed read is attempted again
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// AccuChek uses standard bulk endpoints:
+// 0x01 = EP 1 OUT (host to device)
+// 0x81 = EP 1 IN (device to host)
+const BULK_OUT_ENDPOINT: u8 = 0x01;
+const BULK_IN_ENDPOINT: u8 = 0x81;
+
+/// Byte pipe between the protocol handler and a meter
+///
+/// The protocol handler only needs to push and pull raw APDU bytes, so any
+/// link able to do that (USB bulk endpoints, a mock, a recorded session, a
+/// serial bridge) can drive a full download.
+pub trait Transport {
+    /// Write data to the device, returning the number of bytes written
+    fn write(&mut self, data: &[u8]) -> Result<usize>;
+
+    /// Read data from the device, returning the number of bytes read
+    ///
+    /// A read may return part of an APDU; the protocol handler keeps reading
+    /// until the length in the APDU header is reached.
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize>;
+
+    /// Initial control transfer (USB-specific, no-op by default)
+    fn control_in(&mut self, _buffer: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Called by the protocol handler before each transfer with the phase
+    /// number and a short name of what is exchanged
+    fn begin_phase(&mut self, _phase: usize, _name: &str) {}
+
+    /// How long the following transfers may wait for the device (no-op by
+    /// default)
+    fn set_timeout(&mut self, _timeout: Duration) {}
+}
+
+/// How long to wait for the meter in each part of a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Association request, sent once the meter enters transfer mode
+    pub association: Duration,
+    /// Configuration report and answers to the manager's requests
+    pub response: Duration,
+    /// Each segment data event of a transfer
+    pub segment_data: Duration,
+    /// Release response at the end of the session
+    pub release: Duration,
+}
+
+impl Timeouts {
+    /// The same timeout for every phase
+    pub fn uniform(timeout: Duration) -> Self {
+        Self {
+            association: timeout,
+            response: timeout,
+            segment_data: timeout,
+            release: timeout,
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::uniform(TIMEOUT)
+    }
+}
+
+/// How reads failing with a transient USB error are retried
+///
+/// Only reads are retried: a write that timed out may already have reached
+/// the meter in part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first failed one
+    pub retries: u32,
+    /// Pause before each new attempt
+    pub delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Fail on the first error
+    pub const NONE: Self = Self {
+        retries: 0,
+        delay: Duration::ZERO,
+    };
+
+    /// Run `operation` until it succeeds, fails for good or runs out of retries
+    pub(crate) fn run<R>(&self, what: &str, mut operation: impl FnMut() -> Result<R>) -> Result<R> {
+        let mut attempt = 0;
+        loop {
+            match operation() {
+                Err(error) if attempt < self.retries && is_transient(&error) => {
+                    attempt += 1;
+                    warn!(
+                        "{} failed ({}), retrying ({}/{})",
+                        what, error, attempt, self.retries
+                    );
+                    std::thread::sleep(self.delay);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            delay: RETRY_DELAY,
+        }
+    }
+}
+
+/// Whether an error may go away when the transfer is attempted again
+fn is_transient(error: &anyhow::Error) -> bool {
+    let usb_error =
+        error
+            .downcast_ref::<rusb::Error>()
+            .or(match error.downcast_ref::<UsbError>() {
+                Some(UsbError::Usb(usb_error)) => Some(usb_error),
+                _ => None,
+            });
+
+    matches!(
+        usb_error,
+        Some(rusb::Error::Timeout | rusb::Error::Busy | rusb::Error::Interrupted)
+    ) || matches!(error.downcast_ref::<UsbError>(), Some(UsbError::Timeout))
+}
+
+/// Transport over the bulk endpoints of a claimed USB interface
+///
+/// The interface is released when the transport is dropped.
+pub struct UsbTransport {
+    handle: rusb::DeviceHandle<rusb::GlobalContext>,
+    bulk_in: u8,
+    bulk_out: u8,
+    timeout: Duration,
+}
+
+impl UsbTransport {
+    /// Wrap an already configured handle whose interface 0 is claimed
+    pub fn new(handle: rusb::DeviceHandle<rusb::GlobalContext>, bulk_in: u8, bulk_out: u8) -> Self {
+        Self {
+            handle,
+            bulk_in,
+            bulk_out,
+            timeout: TIMEOUT,
+        }
+    }
+
+    /// Open the device, claim its interface and set up the bulk endpoints
+    pub fn open(device_info: &AccuChekDevice) -> Result<Self> {
+        info!("Opening device...");
+
+        // Find the USB device, which may have been re-enumerated since the scan
+        let handle = open_device(device_info)?;
+
+        info!("Device opened successfully");
+
+        // On Linux, detach kernel driver if attached
+        #[cfg(target_os = "linux")]
+        {
+            if let Ok(true) = handle.kernel_driver_active(0) {
+                info!("Detaching kernel driver...");
+                handle.detach_kernel_driver(0)?;
+            }
+        }
+
+        // Set configuration
+        info!("Setting configuration...");
+        handle.set_active_configuration(1)?;
+
+        // Claim interface 0
+        info!("Claiming interface 0...");
+        handle.claim_interface(0)?;
+
+        // Set alternate setting
+        handle.set_alternate_setting(0, 0)?;
+
+        info!("Bulk OUT endpoint: {:02x}", BULK_OUT_ENDPOINT);
+        info!("Bulk IN endpoint: {:02x}", BULK_IN_ENDPOINT);
+
+        Ok(Self::new(handle, BULK_IN_ENDPOINT, BULK_OUT_ENDPOINT))
+    }
+}
+
+impl Transport for UsbTransport {
+    fn write(&mut self, data: &[u8]) -> Result<usize> {
+        let written = self.handle.write_bulk(self.bulk_out, data, self.timeout)?;
+        Ok(written)
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let read = self.handle.read_bulk(self.bulk_in, buffer, self.timeout)?;
+        Ok(read)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn control_in(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let read = self.handle.read_control(
+            rusb::request_type(Direction::In, RequestType::Standard, Recipient::Device),
+            rusb::constants::LIBUSB_REQUEST_GET_STATUS,
+            0,
+            0,
+            buffer,
+            self.timeout,
+        )?;
+        Ok(read)
+    }
+}
+
+impl Drop for UsbTransport {
+    fn drop(&mut self) {
+        if let Err(e) = self.handle.release_interface(0) {
+            warn!("Failed to release interface 0: {}", e);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use accuchek_core::usb::mock::{MeterScript, MockEntry, MockMeter};
+use accuchek_core::usb::{
+    CaptureRecord, ProtocolHandler, RecordingTransport, ReplayTransport, TransferDirection,
+};
+use chrono::NaiveDate;
+
+fn entries() -> Vec<MockEntry> {
+    (0..4)
+        .map(|i| {
+            let time = NaiveDate::from_ymd_opt(2024, 11, 14)
+                .unwrap()
+                .and_hms_opt(8 + i, 15, 0)
+                .unwrap();
+            MockEntry::new(time, 90 + 10 * i as u16)
+        })
+        .collect()
+}
+
+fn record_session() -> Vec<u8> {
+    let meter = MockMeter::new(MeterScript::standard(&entries(), 2));
+    let mut handler = ProtocolHandler::new(RecordingTransport::new(meter, Vec::new()));
+    handler.execute().unwrap();
+
+    let (_, capture) = handler.into_transport().into_inner();
+    capture
+}
+
+#[test]
+fn records_every_transfer_with_its_phase() {
+    let capture = record_session();
+    let records: Vec<CaptureRecord> = String::from_utf8(capture)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    // control + AARQ/AARE + config + MDS + info + trigger + 2 x (data + ACK) + release,
+    // the config report and MDS attributes each arriving in two bulk reads
+    assert_eq!(records.len(), 1 + 2 + 3 + 3 + 2 + 2 + 4 + 2);
+
+    assert_eq!(records[0].direction, TransferDirection::ControlIn);
+    assert_eq!(records[0].phase, 1);
+    assert_eq!(records[1].direction, TransferDirection::BulkIn);
+    assert_eq!(records[1].name, "pairing request");
+    assert_eq!(records[1].bytes().unwrap()[..2], [0xE2, 0x00]);
+    assert_eq!(records[2].direction, TransferDirection::BulkOut);
+    assert_eq!(records[2].phase, 3);
+    assert_eq!(records[2].name, "pairing confirmation");
+
+    let last = records.last().unwrap();
+    assert_eq!(last.name, "release confirmation");
+    assert!(records.windows(2).all(|w| w[0].phase <= w[1].phase));
+    assert!(records.iter().all(|r| r.error.is_none()));
+}
+
+#[test]
+fn replays_a_recorded_session() {
+    let capture = record_session();
+
+    let replay = ReplayTransport::from_reader(capture.as_slice()).unwrap();
+    let mut handler = ProtocolHandler::new(replay);
+    let samples = handler.execute().unwrap();
+
+    assert_eq!(samples.len(), 4);
+    assert_eq!(samples[3].mg_dl, Some(120));
+    assert_eq!(samples[3].timestamp, "2024/11/14 11:15");
+    assert_eq!(handler.into_transport().mismatches(), 0);
+}
+
+#[test]
+fn replays_recorded_errors() {
+    // The meter stops answering after the association request
+    let meter = MockMeter::new(MeterScript::new().association_request());
+    let mut handler = ProtocolHandler::new(RecordingTransport::new(meter, Vec::new()));
+    assert!(handler.execute().is_err());
+    let (_, capture) = handler.into_transport().into_inner();
+
+    let replay = ReplayTransport::from_reader(capture.as_slice()).unwrap();
+    let error = ProtocolHandler::new(replay).execute().unwrap_err();
+    assert_eq!(error.to_string(), "Timeout");
+}
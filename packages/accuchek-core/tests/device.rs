@@ -0,0 +1,31 @@
+use accuchek_core::usb::AccuChekDevice;
+
+fn meter(serial_number: Option<&str>) -> AccuChekDevice {
+    AccuChekDevice {
+        vendor_id: 0x173A,
+        product_id: 0x21D5,
+        name: "Accu-Chek Guide".to_string(),
+        bus: 1,
+        address: 9,
+        port_path: "1-2.3".to_string(),
+        serial_number: serial_number.map(str::to_string),
+    }
+}
+
+#[test]
+fn device_id_prefers_the_serial_number_over_the_port() {
+    assert_eq!(meter(Some("92345678")).device_id(), "92345678");
+    assert_eq!(meter(None).device_id(), "1-2.3");
+}
+
+#[test]
+fn selects_meters_by_serial_number_or_port_path() {
+    let device = meter(Some("92345678"));
+    assert!(device.matches("92345678"));
+    assert!(device.matches("1-2.3"));
+    assert!(!device.matches("1-2"));
+    assert!(!device.matches("9"));
+
+    // The address of the enumeration is not an identity
+    assert!(!meter(None).matches("92345678"));
+}
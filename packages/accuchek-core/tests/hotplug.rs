@@ -0,0 +1,85 @@
+use accuchek_core::usb::{AccuChekDevice, DeviceWatcher, HotplugEvent};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+fn meter(address: u8) -> AccuChekDevice {
+    AccuChekDevice {
+        vendor_id: 0x173A,
+        product_id: 0x21D5,
+        name: "Accu-Chek Guide".to_string(),
+        bus: 1,
+        address,
+        port_path: format!("1-{}", address),
+        serial_number: None,
+    }
+}
+
+#[test]
+fn reports_meters_plugged_in_and_removed_between_scans() {
+    let mut scans = VecDeque::from([
+        vec![],
+        vec![meter(4)],
+        vec![meter(4), meter(7)],
+        vec![meter(7)],
+    ]);
+    let watcher = DeviceWatcher::with_scanner(Duration::from_millis(1), move || {
+        Ok(scans.pop_front().unwrap_or_default())
+    });
+
+    let events: Vec<_> = (0..4)
+        .map_while(|_| watcher.recv_timeout(Duration::from_secs(5)))
+        .collect();
+
+    assert_eq!(
+        events,
+        [
+            HotplugEvent::Arrived(meter(4)),
+            HotplugEvent::Arrived(meter(7)),
+            HotplugEvent::Left(meter(4)),
+            HotplugEvent::Left(meter(7)),
+        ]
+    );
+}
+
+#[test]
+fn keeps_watching_after_a_failed_scan() {
+    let mut scans = VecDeque::from([Err(anyhow::anyhow!("bus busy")), Ok(vec![meter(4)])]);
+    let watcher = DeviceWatcher::with_scanner(Duration::from_millis(1), move || {
+        scans.pop_front().unwrap_or_else(|| Ok(vec![meter(4)]))
+    });
+
+    assert_eq!(
+        watcher.recv_timeout(Duration::from_secs(5)),
+        Some(HotplugEvent::Arrived(meter(4)))
+    );
+}
+
+#[test]
+fn ignores_a_serial_number_missing_from_one_scan() {
+    let with_serial = AccuChekDevice {
+        serial_number: Some("92345678".to_string()),
+        ..meter(4)
+    };
+    let mut scans = VecDeque::from([
+        vec![with_serial.clone()],
+        vec![meter(4)],
+        vec![with_serial.clone()],
+        vec![],
+    ]);
+    let watcher = DeviceWatcher::with_scanner(Duration::from_millis(1), move || {
+        Ok(scans.pop_front().unwrap_or_default())
+    });
+
+    let events: Vec<_> = (0..2)
+        .map_while(|_| watcher.recv_timeout(Duration::from_secs(5)))
+        .collect();
+
+    assert_eq!(
+        events,
+        [
+            HotplugEvent::Arrived(with_serial.clone()),
+            HotplugEvent::Left(with_serial),
+        ]
+    );
+    assert_eq!(watcher.recv_timeout(Duration::from_millis(50)), None);
+}
@@ -0,0 +1,55 @@
+#![cfg(feature = "async")]
+
+use accuchek_core::nonblocking::run_blocking;
+use accuchek_core::usb::mock::{MeterScript, MockEntry, MockMeter};
+use accuchek_core::ProtocolHandler;
+use chrono::NaiveDate;
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+/// Minimal executor: park the test thread until the future wakes it
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn downloads_on_a_worker_thread() {
+    let time = NaiveDate::from_ymd_opt(2024, 11, 14)
+        .unwrap()
+        .and_hms_opt(7, 5, 0)
+        .unwrap();
+    let script = MeterScript::standard(&[MockEntry::new(time, 105)], 1);
+
+    let caller = thread::current().id();
+    let (samples, worker) = block_on(run_blocking(move || {
+        let samples = ProtocolHandler::new(MockMeter::new(script)).execute();
+        (samples, thread::current().id())
+    }));
+
+    assert_eq!(samples.unwrap()[0].mg_dl, Some(105));
+    assert_ne!(worker, caller);
+}
+
+#[test]
+#[should_panic(expected = "meter unplugged")]
+fn resumes_a_panic_of_the_worker() {
+    block_on(run_blocking(|| panic!("meter unplugged")));
+}
@@ -0,0 +1,2056 @@
+use accuchek_core::usb::apdu::{
+    Apdu, AssociationRequest, DataMessage, DataProto, PhdAssociationInformation,
+};
+use accuchek_core::usb::mock::{
+    self, MeterScript, MeterStep, MockEntry, MockMeter, MOCK_CONFIG_REPORT_ID,
+    MOCK_FIRMWARE_REVISION, MOCK_PM_STORE_HANDLE, MOCK_SERIAL_NUMBER, MOCK_SYSTEM_ID,
+};
+use accuchek_core::usb::{
+    DeviceConfiguration, KnownConfiguration, MetricType, PmStoreObject, ProtocolHandler,
+    StatusFlag, MANAGER_SYSTEM_ID,
+};
+use accuchek_core::DownloadOptions;
+use chrono::NaiveDate;
+
+fn entry(day: u32, hour: u32, minute: u32, value: u16) -> MockEntry {
+    let time = NaiveDate::from_ymd_opt(2024, 11, day)
+        .unwrap()
+        .and_hms_opt(hour, minute, 0)
+        .unwrap();
+    MockEntry::new(time, value)
+}
+
+fn be16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn run(script: MeterScript) -> (anyhow::Result<Vec<accuchek_core::GlucoseSample>>, MockMeter) {
+    let mut handler = ProtocolHandler::new(MockMeter::new(script));
+    let result = handler.execute();
+    (result, handler.into_transport())
+}
+
+/// AARE sent by the host, as (result, selected data protocol)
+fn association_response(meter: &MockMeter) -> (u16, DataProto) {
+    match &meter.sent_apdus().unwrap()[0] {
+        Apdu::AssociationResponse(response) => {
+            (response.result, response.selected_data_proto.clone())
+        }
+        other => panic!("expected AARE, got {}", other.name()),
+    }
+}
+
+fn mock_system_id() -> String {
+    MOCK_SYSTEM_ID
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[test]
+fn downloads_samples_from_scripted_meter() {
+    let entries = [
+        entry(14, 7, 5, 105),
+        entry(14, 12, 30, 142),
+        entry(15, 22, 45, 98),
+    ];
+    let (result, meter) = run(MeterScript::standard(&entries, 10));
+    let samples = result.unwrap();
+
+    assert_eq!(samples.len(), 3);
+    assert_eq!(meter.remaining(), 0);
+
+    assert_eq!(samples[0].id, 0);
+    assert_eq!(samples[0].timestamp, "2024/11/14 07:05");
+    assert_eq!(samples[0].mg_dl, Some(105));
+    assert_eq!(samples[1].timestamp, "2024/11/14 12:30");
+    assert_eq!(samples[1].mg_dl, Some(142));
+    assert_eq!(samples[2].id, 2);
+    assert_eq!(samples[2].timestamp, "2024/11/15 22:45");
+    assert_eq!(samples[2].mg_dl, Some(98));
+    assert!((samples[2].mmol_l.unwrap() - 98.0 / 18.016).abs() < 1e-9);
+    assert!(samples[1].epoch - samples[0].epoch == 5 * 3600 + 25 * 60);
+}
+
+#[test]
+fn host_sends_expected_apdus() {
+    let entries = [entry(14, 7, 5, 105)];
+    let (result, meter) = run(MeterScript::standard(&entries, 10));
+    result.unwrap();
+
+    let sent = meter.sent();
+    assert_eq!(sent.len(), 7);
+
+    // AARE: accepted-unknown-config for protocol 20601
+    assert_eq!(be16(&sent[0], 0), 0xE300);
+    assert_eq!(be16(&sent[0], 2) as usize, sent[0].len() - 4);
+    assert_eq!(be16(&sent[0], 4), 0x0003);
+    assert_eq!(be16(&sent[0], 6), 20601);
+
+    // Config report response echoes the meter's invoke-id
+    assert_eq!(be16(&sent[1], 0), 0xE700);
+    assert_eq!(be16(&sent[1], 6), 1);
+    assert_eq!(be16(&sent[1], 8), 0x0201);
+    assert_eq!(be16(&sent[1], 18), 0x0D1C);
+    assert_eq!(be16(&sent[1], 22), MOCK_CONFIG_REPORT_ID);
+    assert_eq!(be16(&sent[1], 24), 0);
+
+    // GET of all MDS attributes
+    assert_eq!(be16(&sent[2], 8), 0x0103);
+    assert_eq!(be16(&sent[2], 12), 0);
+
+    // Segment info and transfer trigger target the PM-store
+    assert_eq!(be16(&sent[3], 8), 0x0107);
+    assert_eq!(be16(&sent[3], 12), MOCK_PM_STORE_HANDLE);
+    assert_eq!(be16(&sent[3], 14), 0x0C0D);
+    assert_eq!(be16(&sent[4], 8), 0x0107);
+    assert_eq!(be16(&sent[4], 12), MOCK_PM_STORE_HANDLE);
+    assert_eq!(be16(&sent[4], 14), 0x0C1C);
+    assert_eq!(be16(&sent[4], 18), 0);
+
+    // Segment data ACK carries manager-confirm
+    assert_eq!(be16(&sent[5], 8), 0x0201);
+    assert_eq!(be16(&sent[5], 12), MOCK_PM_STORE_HANDLE);
+    assert_eq!(be16(&sent[5], 18), 0x0D21);
+    assert_eq!(be16(&sent[5], 32), 0x0080);
+
+    // Release request
+    assert_eq!(sent[6], vec![0xE4, 0x00, 0x00, 0x02, 0x00, 0x00]);
+
+    // All lengths are consistent with the payload
+    for msg in &sent[1..6] {
+        assert_eq!(be16(msg, 2) as usize, msg.len() - 4);
+        assert_eq!(be16(msg, 4) as usize, msg.len() - 6);
+        assert_eq!(be16(msg, 10) as usize, msg.len() - 12);
+    }
+}
+
+#[test]
+fn acknowledges_every_segment_data_event() {
+    let entries: Vec<_> = (0..7)
+        .map(|i| entry(14, 8 + i, 0, 100 + i as u16))
+        .collect();
+    let (result, meter) = run(MeterScript::standard(&entries, 3));
+    let samples = result.unwrap();
+
+    assert_eq!(samples.len(), 7);
+    assert_eq!(
+        samples.iter().map(|s| s.id).collect::<Vec<_>>(),
+        (0..7).collect::<Vec<_>>()
+    );
+    assert_eq!(samples[6].mg_dl, Some(106));
+
+    let acks: Vec<_> = meter
+        .sent()
+        .iter()
+        .filter(|msg| msg.len() > 20 && be16(msg, 8) == 0x0201 && be16(msg, 18) == 0x0D21)
+        .collect();
+    assert_eq!(acks.len(), 3);
+
+    let ranges: Vec<_> = acks
+        .iter()
+        .map(|ack| (be32(ack, 24), be32(ack, 28)))
+        .collect();
+    assert_eq!(ranges, vec![(0, 3), (3, 3), (6, 1)]);
+
+    // Each ACK echoes the invoke-id of the event it confirms
+    let invoke_ids: Vec<_> = acks.iter().map(|ack| be16(ack, 6)).collect();
+    assert_eq!(invoke_ids, vec![2, 3, 4]);
+}
+
+#[test]
+fn drops_entries_with_non_zero_status() {
+    let entries = [
+        entry(14, 7, 5, 105),
+        entry(14, 8, 0, 600).with_status(0x0400),
+        entry(14, 9, 0, 110),
+    ];
+    let (result, _) = run(MeterScript::standard(&entries, 10));
+    let samples = result.unwrap();
+
+    assert_eq!(
+        samples.iter().map(|s| s.mg_dl.unwrap()).collect::<Vec<_>>(),
+        vec![105, 110]
+    );
+    assert_eq!(samples[1].id, 1);
+}
+
+#[test]
+fn finds_pm_store_after_other_objects() {
+    let pm_store_handle = 0x0042;
+    let numeric = mock::object(6, 1, &[(0x0996, vec![0x08, 0x52])]);
+    let enumeration = mock::object(5, 2, &[]);
+    let pm_store = mock::object(61, pm_store_handle, &[(0x0951, vec![0x00, 0x01])]);
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report_with(vec![numeric, enumeration, pm_store])
+        .mds_attributes()
+        .segment_info(pm_store_handle)
+        .trigger_transfer(pm_store_handle)
+        .segment_data(pm_store_handle, 0, &[entry(14, 7, 5, 105)], true)
+        .release_response();
+
+    let (result, meter) = run(script);
+    assert_eq!(result.unwrap().len(), 1);
+    assert_eq!(be16(&meter.sent()[3], 12), pm_store_handle);
+    assert_eq!(be16(&meter.sent()[4], 12), pm_store_handle);
+}
+
+#[test]
+fn fails_without_pm_store() {
+    let script = MeterScript::new()
+        .association_request()
+        .config_report_with(vec![mock::object(6, 1, &[])]);
+
+    let (result, meter) = run(script);
+    assert!(result.is_err());
+
+    // The association is aborted rather than left open
+    assert_eq!(meter.sent().len(), 2);
+    assert!(matches!(meter.sent_apdus().unwrap()[1], Apdu::Abort(_)));
+}
+
+#[test]
+fn rejects_segment_data_shorter_than_its_entry_count() {
+    use accuchek_core::usb::apdu::nomenclature::MDC_NOTI_SEGMENT_DATA;
+    use accuchek_core::usb::apdu::{SegmentDataDescriptor, SegmentDataEvent};
+
+    let event = SegmentDataEvent {
+        descriptor: SegmentDataDescriptor {
+            instance: 0,
+            entry_index: 0,
+            entry_count: 3,
+            status: 0xC000,
+        },
+        entries: vec![
+            0x20, 0x24, 0x11, 0x14, 0x07, 0x05, 0x00, 0x00, 0x00, 0x69, 0x00, 0x00,
+        ],
+    };
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info(MOCK_PM_STORE_HANDLE)
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .event_report(
+            MOCK_PM_STORE_HANDLE,
+            MDC_NOTI_SEGMENT_DATA,
+            event.to_bytes(),
+        );
+
+    let (result, _) = run(script);
+    assert!(result.unwrap_err().to_string().contains("Truncated"));
+}
+
+#[test]
+fn exposes_the_association_request() {
+    let entries = [entry(14, 7, 5, 105)];
+    let mut handler = ProtocolHandler::new(MockMeter::new(MeterScript::standard(&entries, 10)));
+    let result = handler.download().unwrap();
+
+    assert_eq!(result.association.protocol_version, 0x80000000);
+    assert_eq!(result.association.encoding_rules, 0xA000);
+    assert_eq!(result.association.nomenclature_version, 0x80000000);
+    assert_eq!(result.association.system_id, mock_system_id());
+    assert_eq!(result.association.dev_config_id, MOCK_CONFIG_REPORT_ID);
+    assert_eq!(
+        result.configuration.pm_store().unwrap().handle,
+        MOCK_PM_STORE_HANDLE
+    );
+    assert_eq!(result.samples.len(), 1);
+
+    // The AARE answers with the manager's own system-id on protocol version 1
+    let (code, proto) = association_response(&handler.into_transport());
+    assert_eq!(code, 3);
+    let info = PhdAssociationInformation::from_bytes(&proto.info).unwrap();
+    assert_eq!(info.protocol_version, 0x80000000);
+    assert_eq!(info.encoding_rules, 0x8000);
+    assert_eq!(info.system_id, MANAGER_SYSTEM_ID.to_vec());
+}
+
+#[test]
+fn rejects_unsupported_protocol_version() {
+    let info = PhdAssociationInformation {
+        protocol_version: 0x20000000,
+        ..mock::mock_association_information()
+    };
+    let script = MeterScript::new().association_request_with(info);
+
+    let (result, meter) = run(script);
+    assert!(result.unwrap_err().to_string().contains("rejected"));
+    assert_eq!(meter.sent().len(), 1);
+
+    let (code, proto) = association_response(&meter);
+    assert_eq!(code, 5);
+    assert_eq!(proto.id, 0);
+    assert!(proto.info.is_empty());
+}
+
+#[test]
+fn rejects_unknown_data_protocol() {
+    let script = MeterScript::new().send(Apdu::AssociationRequest(AssociationRequest {
+        assoc_version: 0x80000000,
+        data_protos: vec![DataProto {
+            id: 20602,
+            info: Vec::new(),
+        }],
+    }));
+
+    let (result, meter) = run(script);
+    assert!(result.is_err());
+    assert_eq!(association_response(&meter).0, 4);
+}
+
+#[test]
+fn accepts_known_configuration_without_config_report() {
+    let pm_store_handle = 0x0042;
+    let script = MeterScript::new()
+        .association_request()
+        .mds_attributes()
+        .segment_info(pm_store_handle)
+        .trigger_transfer(pm_store_handle)
+        .segment_data(pm_store_handle, 0, &[entry(14, 7, 5, 105)], true)
+        .release_response();
+
+    let known = KnownConfiguration {
+        system_id: mock_system_id(),
+        dev_config_id: MOCK_CONFIG_REPORT_ID,
+        configuration: DeviceConfiguration {
+            config_report_id: MOCK_CONFIG_REPORT_ID,
+            pm_stores: vec![PmStoreObject {
+                handle: pm_store_handle,
+                capabilities: 0,
+                number_of_segments: Some(1),
+            }],
+            ..Default::default()
+        },
+    };
+    let mut handler = ProtocolHandler::new(MockMeter::new(script)).with_known_configuration(known);
+    let result = handler.download().unwrap();
+    assert_eq!(result.samples.len(), 1);
+
+    let meter = handler.into_transport();
+    assert_eq!(association_response(&meter).0, 0);
+    assert_eq!(meter.remaining(), 0);
+
+    // No config report response: straight from AARE to the MDS GET
+    let sent = meter.sent();
+    assert_eq!(sent.len(), 6);
+    assert_eq!(be16(&sent[1], 8), 0x0103);
+    assert_eq!(be16(&sent[2], 12), pm_store_handle);
+}
+
+#[test]
+fn decodes_device_identity_from_mds_attributes() {
+    let entries = [entry(14, 7, 5, 105)];
+    let mut handler = ProtocolHandler::new(MockMeter::new(MeterScript::standard(&entries, 10)));
+    let device = handler.download().unwrap().device;
+
+    assert_eq!(device.manufacturer.as_deref(), Some("Roche"));
+    assert_eq!(device.model.as_deref(), Some("Guide"));
+    assert_eq!(device.system_id, Some(mock_system_id()));
+    assert_eq!(device.serial_number.as_deref(), Some(MOCK_SERIAL_NUMBER));
+    assert_eq!(device.hardware_revision.as_deref(), Some("1.0"));
+    assert_eq!(
+        device.firmware_revision.as_deref(),
+        Some(MOCK_FIRMWARE_REVISION)
+    );
+    assert_eq!(device.software_revision, None);
+    assert_eq!(
+        device.clock,
+        NaiveDate::from_ymd_opt(2024, 11, 14)
+            .unwrap()
+            .and_hms_opt(10, 30, 0)
+    );
+    assert_eq!(device.certifications.len(), 1);
+    assert_eq!(device.certifications[0].auth_body, 2);
+    assert!(device.is_on_battery());
+    assert_eq!(device.battery_level, Some(80));
+    assert_eq!(device.specializations, [4113]);
+    assert_eq!(device.max_apdu_size(), Some(63 * 1024));
+}
+
+#[test]
+fn tolerates_missing_mds_attributes() {
+    use accuchek_core::usb::apdu::AttributeList;
+
+    let mut attributes = AttributeList::default();
+    attributes.push(0x0987, vec![0x00; 8]); // clock never set
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes_with(attributes)
+        .segment_info(MOCK_PM_STORE_HANDLE)
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data(MOCK_PM_STORE_HANDLE, 0, &[entry(14, 7, 5, 105)], true)
+        .release_response();
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script));
+    let device = handler.download().unwrap().device;
+
+    assert_eq!(device.model, None);
+    assert_eq!(device.serial_number, None);
+    assert_eq!(device.clock, None);
+    assert!(!device.is_on_battery());
+}
+
+#[test]
+fn decodes_the_configuration_report() {
+    let entries = [entry(14, 7, 5, 105)];
+    let mut handler = ProtocolHandler::new(MockMeter::new(MeterScript::standard(&entries, 10)));
+    let config = handler.download().unwrap().configuration;
+
+    assert_eq!(config.config_report_id, MOCK_CONFIG_REPORT_ID);
+    assert_eq!(config.numerics.len(), 1);
+    assert!(config.enumerations.is_empty());
+
+    let glucose = config.numeric(1).unwrap();
+    assert_eq!(
+        glucose.metric_type,
+        Some(MetricType {
+            partition: 2,
+            code: 29112
+        })
+    );
+    assert_eq!(glucose.unit_code, Some(2130));
+    assert_eq!(glucose.metric_spec_small, Some(0xF040));
+    assert_eq!(
+        glucose
+            .attribute_value_map
+            .iter()
+            .map(|entry| (entry.attribute_id, entry.attribute_len))
+            .collect::<Vec<_>>(),
+        vec![(0x0A4C, 2), (0x0990, 8)]
+    );
+
+    let pm_store = config.pm_store().unwrap();
+    assert_eq!(pm_store.handle, MOCK_PM_STORE_HANDLE);
+    assert_eq!(pm_store.number_of_segments, Some(1));
+    assert!(pm_store.supports(0x0800));
+}
+
+#[test]
+fn rejects_malformed_attribute_value_map() {
+    let numeric = mock::object(6, 1, &[(0x0A55, vec![0x00, 0x02, 0x00, 0x04, 0x0A, 0x4C])]);
+    let pm_store = mock::object(61, MOCK_PM_STORE_HANDLE, &[]);
+    let script = MeterScript::new()
+        .association_request()
+        .config_report_with(vec![numeric, pm_store]);
+
+    let (result, _) = run(script);
+    assert!(result.unwrap_err().to_string().contains("Truncated"));
+}
+
+#[test]
+fn decodes_entries_according_to_the_segment_entry_map() {
+    use accuchek_core::usb::apdu::AttributeList;
+    use accuchek_core::usb::{AttributeValueMapEntry, PmSegmentEntryMap, SegmentEntryElement};
+
+    let mapped = |attribute_id, attribute_len| AttributeValueMapEntry {
+        attribute_id,
+        attribute_len,
+    };
+
+    // Absolute + base-offset time, a meal context enumeration, then glucose
+    let map = PmSegmentEntryMap {
+        header: 0x8000 | 0x1000,
+        elements: vec![
+            SegmentEntryElement {
+                class_id: 5,
+                metric_type: MetricType {
+                    partition: 128,
+                    code: 29256,
+                },
+                handle: 2,
+                attribute_value_map: vec![mapped(0x0A49, 2)],
+            },
+            SegmentEntryElement {
+                class_id: 6,
+                metric_type: MetricType {
+                    partition: 2,
+                    code: 29112,
+                },
+                handle: 1,
+                attribute_value_map: vec![mapped(0x0A4C, 2), mapped(0x0947, 2)],
+            },
+        ],
+    };
+    let mut attributes = AttributeList::default();
+    attributes.push(0x0A4E, map.to_bytes());
+
+    let mut data = Vec::new();
+    for (hour, value) in [(7u8, 105u16), (8, 131)] {
+        data.extend_from_slice(&[0x20, 0x24, 0x11, 0x14, hour, 0x05, 0x00, 0x00]);
+        data.extend_from_slice(&[0x67, 0x35, 0x9A, 0x00, 0x00, 0x00, 0x00, 0x3C]);
+        data.extend_from_slice(&29260u16.to_be_bytes()); // preprandial
+        data.extend_from_slice(&value.to_be_bytes());
+        data.extend_from_slice(&0u16.to_be_bytes());
+    }
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_with(MOCK_PM_STORE_HANDLE, attributes)
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data_bytes(MOCK_PM_STORE_HANDLE, 0, 2, data, true)
+        .release_response();
+
+    let (result, meter) = run(script);
+    let samples = result.unwrap();
+    assert_eq!(meter.remaining(), 0);
+
+    assert_eq!(
+        samples
+            .iter()
+            .map(|s| (s.timestamp.as_str(), s.mg_dl.unwrap()))
+            .collect::<Vec<_>>(),
+        vec![("2024/11/14 07:05", 105), ("2024/11/14 08:05", 131)]
+    );
+}
+
+#[test]
+fn falls_back_to_legacy_layout_without_entry_map() {
+    use accuchek_core::usb::apdu::AttributeList;
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_with(MOCK_PM_STORE_HANDLE, AttributeList::default())
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data(
+            MOCK_PM_STORE_HANDLE,
+            0,
+            &[entry(14, 7, 5, 105), entry(14, 9, 0, 110)],
+            true,
+        )
+        .release_response();
+
+    let (result, _) = run(script);
+    assert_eq!(
+        result
+            .unwrap()
+            .iter()
+            .map(|s| s.mg_dl.unwrap())
+            .collect::<Vec<_>>(),
+        vec![105, 110]
+    );
+}
+
+#[test]
+fn converts_values_reported_in_mmol_per_litre() {
+    let glucose = mock::object(
+        6,
+        1,
+        &[
+            (0x092F, vec![0x00, 0x02, 0x71, 0xB8]),
+            (0x0996, 4722u16.to_be_bytes().to_vec()), // mmol/L
+        ],
+    );
+    let pm_store = mock::object(61, MOCK_PM_STORE_HANDLE, &[]);
+
+    let entries = [
+        entry(14, 7, 5, 0xF03A),  // 5.8 mmol/L
+        entry(14, 8, 0, 0xE3E8),  // 10.00 mmol/L
+        entry(14, 9, 0, 0x07FF),  // NaN
+        entry(14, 10, 0, 0x0800), // NRes
+    ];
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report_with(vec![glucose, pm_store])
+        .mds_attributes()
+        .segment_info(MOCK_PM_STORE_HANDLE)
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data(MOCK_PM_STORE_HANDLE, 0, &entries, true)
+        .release_response();
+
+    let (result, _) = run(script);
+    let samples = result.unwrap();
+
+    assert_eq!(samples.len(), 2);
+    assert!((samples[0].mmol_l.unwrap() - 5.8).abs() < 1e-9);
+    assert_eq!(samples[0].mg_dl, Some(104));
+    assert!((samples[1].mmol_l.unwrap() - 10.0).abs() < 1e-9);
+    assert_eq!(samples[1].mg_dl, Some(180));
+}
+
+#[test]
+fn keeps_flagged_readings_when_asked() {
+    let entries = [
+        entry(14, 7, 5, 105),
+        entry(14, 8, 0, 0x07FE),                   // HI
+        entry(14, 9, 0, 0x0802),                   // LO
+        entry(14, 10, 0, 600).with_status(0x8000), // invalid
+        entry(14, 11, 0, 98).with_status(0x0080),  // validated data only
+        entry(14, 12, 0, 120).with_status(0x0001), // unknown bit
+    ];
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(MeterScript::standard(&entries, 10)))
+        .with_options(DownloadOptions {
+            include_flagged: true,
+            ..Default::default()
+        });
+    let samples = handler.download().unwrap().samples;
+    assert_eq!(samples.len(), 6);
+
+    assert!(!samples[0].status.is_flagged());
+    assert_eq!(samples[1].mg_dl, None);
+    assert_eq!(samples[1].mmol_l, None);
+    assert_eq!(samples[1].status.flags, vec![StatusFlag::ResultTooHigh]);
+    assert_eq!(samples[2].status.flags, vec![StatusFlag::ResultTooLow]);
+    assert_eq!(samples[3].mg_dl, Some(600));
+    assert_eq!(samples[3].status.flags, vec![StatusFlag::Invalid]);
+    assert_eq!(samples[3].status.measurement_status, 0x8000);
+    assert!(!samples[4].status.is_flagged());
+    assert_eq!(samples[5].status.flags, vec![StatusFlag::Other]);
+
+    // Without the option only the plain readings are kept
+    let (result, _) = run(MeterScript::standard(&entries, 10));
+    assert_eq!(
+        result
+            .unwrap()
+            .iter()
+            .map(|s| s.mg_dl.unwrap())
+            .collect::<Vec<_>>(),
+        vec![105, 98]
+    );
+}
+
+#[test]
+fn decodes_device_annunciation_and_control_solution() {
+    use accuchek_core::usb::apdu::AttributeList;
+    use accuchek_core::usb::{AttributeValueMapEntry, PmSegmentEntryMap, SegmentEntryElement};
+
+    let glucose = |code| SegmentEntryElement {
+        class_id: 6,
+        metric_type: MetricType { partition: 2, code },
+        handle: 1,
+        attribute_value_map: vec![AttributeValueMapEntry {
+            attribute_id: 0x0A4C,
+            attribute_len: 2,
+        }],
+    };
+    let annunciation = SegmentEntryElement {
+        class_id: 5,
+        metric_type: MetricType {
+            partition: 2,
+            code: 29144,
+        },
+        handle: 3,
+        attribute_value_map: vec![AttributeValueMapEntry {
+            attribute_id: 0x0A66,
+            attribute_len: 2,
+        }],
+    };
+
+    let map = PmSegmentEntryMap {
+        header: 0x8000,
+        elements: vec![glucose(29136), annunciation],
+    };
+    let mut attributes = AttributeList::default();
+    attributes.push(0x0A4E, map.to_bytes());
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&[0x20, 0x24, 0x11, 0x14, 0x07, 0x05, 0x00, 0x00]);
+    data.extend_from_slice(&112u16.to_be_bytes());
+    data.extend_from_slice(&0x2000u16.to_be_bytes()); // sample size insufficient
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_with(MOCK_PM_STORE_HANDLE, attributes)
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data_bytes(MOCK_PM_STORE_HANDLE, 0, 1, data, true)
+        .release_response();
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script)).with_options(DownloadOptions {
+        include_flagged: true,
+        ..Default::default()
+    });
+    let samples = handler.download().unwrap().samples;
+
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].status.device_status, 0x2000);
+    assert_eq!(
+        samples[0].status.flags,
+        vec![
+            StatusFlag::SampleSizeInsufficient,
+            StatusFlag::ControlSolution
+        ]
+    );
+}
+
+#[test]
+fn decodes_meal_and_carbohydrate_context() {
+    use accuchek_core::usb::apdu::AttributeList;
+    use accuchek_core::usb::{
+        AttributeValueMapEntry, MealRelation, PmSegmentEntryMap, SegmentEntryElement, Tester,
+    };
+
+    let element = |class_id, partition, code, handle, attribute_id| SegmentEntryElement {
+        class_id,
+        metric_type: MetricType { partition, code },
+        handle,
+        attribute_value_map: vec![AttributeValueMapEntry {
+            attribute_id,
+            attribute_len: 2,
+        }],
+    };
+
+    // Carbohydrates come first so the glucose value is not mistaken for them
+    let map = PmSegmentEntryMap {
+        header: 0x8000,
+        elements: vec![
+            element(6, 128, 29156, 10, 0x0A4C),
+            element(6, 2, 29112, 1, 0x0A4C),
+            element(5, 128, 29256, 11, 0x0A49),
+            element(5, 128, 29276, 12, 0x0A49),
+        ],
+    };
+    let mut attributes = AttributeList::default();
+    attributes.push(0x0A4E, map.to_bytes());
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&[0x20, 0x24, 0x11, 0x14, 0x12, 0x30, 0x00, 0x00]);
+    data.extend_from_slice(&45u16.to_be_bytes());
+    data.extend_from_slice(&142u16.to_be_bytes());
+    data.extend_from_slice(&29264u16.to_be_bytes()); // after meal
+    data.extend_from_slice(&29280u16.to_be_bytes()); // self test
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_with(MOCK_PM_STORE_HANDLE, attributes)
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data_bytes(MOCK_PM_STORE_HANDLE, 0, 1, data, true)
+        .release_response();
+
+    let samples = run(script).0.unwrap();
+
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].mg_dl, Some(142));
+    assert_eq!(samples[0].meal, Some(MealRelation::AfterMeal));
+    assert_eq!(samples[0].tester, Some(Tester::SelfTest));
+    assert_eq!(samples[0].health, None);
+    assert_eq!(samples[0].carbs_g, Some(45.0));
+}
+
+#[test]
+fn decodes_every_meal_and_health_context_value() {
+    use accuchek_core::usb::apdu::AttributeList;
+    use accuchek_core::usb::{
+        AttributeValueMapEntry, HealthState, MealRelation, PmSegmentEntryMap, SegmentEntryElement,
+    };
+
+    let element = |class_id, partition, code, handle, attribute_id| SegmentEntryElement {
+        class_id,
+        metric_type: MetricType { partition, code },
+        handle,
+        attribute_value_map: vec![AttributeValueMapEntry {
+            attribute_id,
+            attribute_len: 2,
+        }],
+    };
+    let map = PmSegmentEntryMap {
+        header: 0x8000,
+        elements: vec![
+            element(6, 2, 29112, 1, 0x0A4C),
+            element(5, 128, 29256, 11, 0x0A49),
+            element(5, 128, 29212, 12, 0x0A49),
+        ],
+    };
+    let mut attributes = AttributeList::default();
+    attributes.push(0x0A4E, map.to_bytes());
+
+    // Codes outside an enumeration are left empty
+    let meals = [
+        (29260u16, Some(MealRelation::BeforeMeal)),
+        (29264, Some(MealRelation::AfterMeal)),
+        (29268, Some(MealRelation::Fasting)),
+        (29272, Some(MealRelation::Other)),
+        (29300, Some(MealRelation::Bedtime)),
+        (29276, None),
+    ];
+    let health_states = [
+        (29216u16, Some(HealthState::MinorIssues)),
+        (29220, Some(HealthState::MajorIssues)),
+        (29224, Some(HealthState::Menses)),
+        (29228, Some(HealthState::Stress)),
+        (29232, Some(HealthState::NoIssues)),
+        (29256, None),
+    ];
+
+    let mut data = Vec::new();
+    for (minute, ((meal, _), (health, _))) in meals.iter().zip(&health_states).enumerate() {
+        data.extend_from_slice(&[0x20, 0x24, 0x11, 0x14, 0x12, minute as u8, 0x00, 0x00]);
+        data.extend_from_slice(&120u16.to_be_bytes());
+        data.extend_from_slice(&meal.to_be_bytes());
+        data.extend_from_slice(&health.to_be_bytes());
+    }
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_with(MOCK_PM_STORE_HANDLE, attributes)
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data_bytes(MOCK_PM_STORE_HANDLE, 0, meals.len() as u32, data, true)
+        .release_response();
+
+    let samples = run(script).0.unwrap();
+
+    assert_eq!(samples.len(), meals.len());
+    for ((sample, (_, meal)), (_, health)) in samples.iter().zip(meals).zip(health_states) {
+        assert_eq!(sample.meal, meal);
+        assert_eq!(sample.health, health);
+    }
+}
+
+#[test]
+fn resolves_local_times_in_the_selected_zone() {
+    use accuchek_core::usb::{TimePolicy, TimeResolution};
+
+    let at = |month, day, hour, minute| {
+        NaiveDate::from_ymd_opt(2024, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    };
+    let entries = [
+        MockEntry::new(at(3, 31, 2, 30), 101), // skipped by the spring change
+        MockEntry::new(at(10, 27, 2, 30), 102), // repeated by the autumn change
+        MockEntry::new(at(11, 14, 7, 5), 103),
+    ];
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(MeterScript::standard(&entries, 10)))
+        .with_options(DownloadOptions {
+            time_policy: "Europe/Paris".parse().unwrap(),
+            ..Default::default()
+        });
+    let samples = handler.download().unwrap().samples;
+
+    assert_eq!(samples.len(), 3);
+
+    assert_eq!(samples[0].time.resolution, TimeResolution::Nonexistent);
+    assert_eq!(samples[0].time.offset_minutes, 60);
+    assert_eq!(
+        samples[0].time.utc.to_rfc3339(),
+        "2024-03-31T01:30:00+00:00"
+    );
+
+    assert_eq!(samples[1].time.resolution, TimeResolution::Ambiguous);
+    assert_eq!(samples[1].time.offset_minutes, 120);
+    assert_eq!(
+        samples[1].time.utc.to_rfc3339(),
+        "2024-10-27T00:30:00+00:00"
+    );
+
+    assert_eq!(samples[2].time.resolution, TimeResolution::Unique);
+    assert_eq!(samples[2].time.local, at(11, 14, 7, 5));
+    assert_eq!(samples[2].timestamp, "2024/11/14 07:05");
+    assert_eq!(samples[2].epoch, samples[2].time.utc.timestamp());
+    assert!(samples[1].time.is_ambiguous());
+    assert!(!samples[2].time.is_ambiguous());
+
+    assert_eq!("host".parse::<TimePolicy>().unwrap(), TimePolicy::Host);
+    assert_eq!("meter".parse::<TimePolicy>().unwrap(), TimePolicy::Meter);
+    assert!(matches!(
+        "+05:30".parse::<TimePolicy>().unwrap(),
+        TimePolicy::Fixed(offset) if offset.local_minus_utc() == 5 * 3600 + 30 * 60
+    ));
+    assert!("Mars/Olympus_Mons".parse::<TimePolicy>().is_err());
+}
+
+#[test]
+fn uses_the_base_offset_time_declared_by_the_meter() {
+    use accuchek_core::usb::apdu::AttributeList;
+    use accuchek_core::usb::{
+        AttributeValueMapEntry, PmSegmentEntryMap, SegmentEntryElement, TimePolicy, TimeResolution,
+    };
+
+    let map = PmSegmentEntryMap {
+        header: 0x8000 | 0x1000,
+        elements: vec![SegmentEntryElement {
+            class_id: 6,
+            metric_type: MetricType {
+                partition: 2,
+                code: 29112,
+            },
+            handle: 1,
+            attribute_value_map: vec![AttributeValueMapEntry {
+                attribute_id: 0x0A4C,
+                attribute_len: 2,
+            }],
+        }],
+    };
+    let mut attributes = AttributeList::default();
+    attributes.push(0x0A4E, map.to_bytes());
+
+    // 2024-11-14 07:05 at UTC-05:00
+    let mut data = Vec::new();
+    data.extend_from_slice(&[0x20, 0x24, 0x11, 0x14, 0x07, 0x05, 0x00, 0x00]);
+    data.extend_from_slice(&1_731_585_900u32.to_be_bytes());
+    data.extend_from_slice(&0u16.to_be_bytes());
+    data.extend_from_slice(&(-300i16).to_be_bytes());
+    data.extend_from_slice(&105u16.to_be_bytes());
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_with(MOCK_PM_STORE_HANDLE, attributes)
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data_bytes(MOCK_PM_STORE_HANDLE, 0, 1, data, true)
+        .release_response();
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script)).with_options(DownloadOptions {
+        time_policy: TimePolicy::Meter,
+        ..Default::default()
+    });
+    let samples = handler.download().unwrap().samples;
+
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].time.resolution, TimeResolution::Declared);
+    assert_eq!(samples[0].time.offset_minutes, -300);
+    assert_eq!(
+        samples[0].time.utc.to_rfc3339(),
+        "2024-11-14T12:05:00+00:00"
+    );
+    assert_eq!(samples[0].timestamp, "2024/11/14 07:05");
+    assert_eq!(samples[0].epoch, 1_731_585_900);
+}
+
+#[test]
+fn measures_and_corrects_meter_clock_drift() {
+    use accuchek_core::usb::TimePolicy;
+    use chrono::{TimeZone, Utc};
+
+    // The scripted meter reads 2024-11-14 10:30, seven minutes ahead
+    let host_time = Utc.with_ymd_and_hms(2024, 11, 14, 10, 23, 0).unwrap();
+    let entries = [entry(14, 7, 5, 105)];
+    let download = |correct_clock| {
+        ProtocolHandler::new(MockMeter::new(MeterScript::standard(&entries, 10)))
+            .with_host_time(host_time)
+            .with_options(DownloadOptions {
+                time_policy: TimePolicy::Fixed(chrono::FixedOffset::east_opt(0).unwrap()),
+                correct_clock,
+                ..Default::default()
+            })
+            .download()
+            .unwrap()
+    };
+
+    let result = download(false);
+    let clock = result.clock.unwrap();
+    assert_eq!(clock.drift_seconds, 7 * 60);
+    assert!(!clock.reset_detected);
+    assert!(!clock.corrected);
+    assert_eq!(
+        result.samples[0].epoch,
+        Utc.with_ymd_and_hms(2024, 11, 14, 7, 5, 0)
+            .unwrap()
+            .timestamp()
+    );
+
+    let result = download(true);
+    assert!(result.clock.unwrap().corrected);
+    assert_eq!(result.samples[0].time.clock_correction_seconds, 7 * 60);
+    assert_eq!(result.samples[0].timestamp, "2024/11/14 07:05");
+    assert_eq!(
+        result.samples[0].epoch,
+        Utc.with_ymd_and_hms(2024, 11, 14, 6, 58, 0)
+            .unwrap()
+            .timestamp()
+    );
+}
+
+#[test]
+fn flags_samples_recorded_before_a_clock_reset() {
+    use accuchek_core::usb::apdu::AttributeList;
+    use chrono::{TimeZone, Utc};
+
+    let mut attributes = AttributeList::default();
+    attributes.push(0x0987, vec![0x20, 0x00, 0x01, 0x03, 0x09, 0x00, 0x00, 0x00]);
+
+    let reset = NaiveDate::from_ymd_opt(2000, 1, 3)
+        .unwrap()
+        .and_hms_opt(8, 15, 0)
+        .unwrap();
+    let entries = [entry(14, 7, 5, 105), MockEntry::new(reset, 98)];
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes_with(attributes)
+        .segment_info(MOCK_PM_STORE_HANDLE)
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data(MOCK_PM_STORE_HANDLE, 0, &entries, true)
+        .release_response();
+
+    let result = ProtocolHandler::new(MockMeter::new(script))
+        .with_host_time(Utc.with_ymd_and_hms(2024, 11, 20, 9, 0, 0).unwrap())
+        .download()
+        .unwrap();
+
+    assert!(result.clock.unwrap().reset_detected);
+    // Neither reading is dropped: the values themselves are fine
+    assert_eq!(result.samples.len(), 2);
+    assert_eq!(
+        result.samples[0].status.flags,
+        vec![StatusFlag::BeforeClockReset]
+    );
+    assert!(!result.samples[0].status.is_flagged());
+    assert!(result.samples[1].status.flags.is_empty());
+}
+
+fn set_time_script() -> MeterScript {
+    MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .set_time_response()
+        .release_response()
+}
+
+#[test]
+fn sets_the_meter_clock() {
+    use accuchek_core::usb::TimePolicy;
+    use accuchek_core::SetTimeOptions;
+    use chrono::{FixedOffset, TimeZone, Utc};
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(set_time_script()))
+        .with_host_time(Utc.with_ymd_and_hms(2024, 11, 14, 9, 45, 12).unwrap());
+    let result = handler
+        .set_device_time(&SetTimeOptions {
+            time_policy: TimePolicy::Fixed(FixedOffset::east_opt(3600).unwrap()),
+            dry_run: false,
+        })
+        .unwrap();
+
+    // The meter reads 10:30 local, 15 minutes 12 seconds behind
+    assert_eq!(result.clock.unwrap().drift_seconds, -(15 * 60 + 12));
+    assert_eq!(
+        result.new_time,
+        NaiveDate::from_ymd_opt(2024, 11, 14)
+            .unwrap()
+            .and_hms_opt(10, 45, 12)
+            .unwrap()
+    );
+    assert!(result.applied);
+
+    let meter = handler.into_transport();
+    assert_eq!(meter.remaining(), 0);
+    let apdus = meter.sent_apdus().unwrap();
+    let Apdu::Presentation(data) = &apdus[3] else {
+        panic!("expected a set time request, got {}", apdus[3].name());
+    };
+    let DataMessage::ConfirmedAction(action) = &data.message else {
+        panic!("expected a confirmed action, got {}", data.message.name());
+    };
+    assert_eq!(action.obj_handle, 0);
+    assert_eq!(action.action_type, 0x0C17);
+    assert_eq!(
+        &action.info[..8],
+        &[0x20, 0x24, 0x11, 0x14, 0x10, 0x45, 0x12, 0x00]
+    );
+}
+
+#[test]
+fn dry_run_leaves_the_meter_clock_alone() {
+    use accuchek_core::SetTimeOptions;
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .release_response();
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script));
+    let result = handler
+        .set_device_time(&SetTimeOptions {
+            dry_run: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+    assert!(!result.applied);
+    assert!(result.clock.is_some());
+
+    let apdus = handler.into_transport().sent_apdus().unwrap();
+    assert_eq!(apdus.len(), 4);
+    assert!(matches!(apdus[3], Apdu::ReleaseRequest(_)));
+}
+
+#[test]
+fn refuses_to_set_the_clock_without_capability() {
+    use accuchek_core::usb::apdu::AttributeList;
+    use accuchek_core::SetTimeOptions;
+
+    // Real-time clock only, no set-clock bit
+    let mut attributes = AttributeList::default();
+    attributes.push(0x0A45, [&[0x80, 0x00][..], &[0x00; 14]].concat());
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes_with(attributes)
+        .release_response();
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script));
+    let err = handler
+        .set_device_time(&SetTimeOptions::default())
+        .unwrap_err();
+
+    assert!(err.to_string().contains("setting its clock"), "{}", err);
+    assert_eq!(handler.into_transport().remaining(), 0);
+}
+
+/// Segment info attributes with the legacy entry map and a Segment-Usage-Count
+fn segment_attributes(usage_count: u32) -> accuchek_core::usb::apdu::AttributeList {
+    use accuchek_core::usb::PmSegmentEntryMap;
+
+    let mut attributes = accuchek_core::usb::apdu::AttributeList::default();
+    attributes.push(
+        0x0A4E,
+        PmSegmentEntryMap::legacy(&DeviceConfiguration::default()).to_bytes(),
+    );
+    attributes.push(0x097B, usage_count.to_be_bytes().to_vec());
+    attributes
+}
+
+#[test]
+fn downloads_only_entries_after_the_cursor() {
+    use accuchek_core::usb::{EntryPosition, SyncCursor};
+
+    let entries = [
+        entry(14, 7, 5, 105),
+        entry(14, 12, 30, 142),
+        entry(15, 22, 45, 98),
+    ];
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_with(MOCK_PM_STORE_HANDLE, segment_attributes(3))
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data(MOCK_PM_STORE_HANDLE, 0, &entries, true)
+        .release_response();
+
+    let since = SyncCursor {
+        last_time: None,
+        last_entry: Some(EntryPosition {
+            segment: 0,
+            index: 1,
+        }),
+    };
+    let mut handler = ProtocolHandler::new(MockMeter::new(script)).with_options(DownloadOptions {
+        since: Some(since),
+        ..Default::default()
+    });
+    let result = handler.download().unwrap();
+
+    assert_eq!(result.samples.len(), 1);
+    assert_eq!(result.samples[0].mg_dl, Some(98));
+    assert_eq!(
+        result.cursor.last_entry,
+        Some(EntryPosition {
+            segment: 0,
+            index: 2
+        })
+    );
+    assert_eq!(result.cursor.last_time, Some(entries[2].time));
+
+    // A time cursor filters the same way
+    let script = MeterScript::standard(&entries, 10);
+    let mut handler = ProtocolHandler::new(MockMeter::new(script)).with_options(DownloadOptions {
+        since: Some(SyncCursor {
+            last_time: Some(entries[0].time),
+            last_entry: None,
+        }),
+        ..Default::default()
+    });
+    let samples = handler.download().unwrap().samples;
+    assert_eq!(samples.len(), 2);
+    assert_eq!(samples[0].mg_dl, Some(142));
+}
+
+#[test]
+fn downloads_everything_for_a_cursor_at_the_last_possible_index() {
+    use accuchek_core::usb::{EntryPosition, SyncCursor};
+
+    let entries = [entry(14, 7, 5, 105), entry(14, 12, 30, 142)];
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_with(MOCK_PM_STORE_HANDLE, segment_attributes(2))
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data(MOCK_PM_STORE_HANDLE, 0, &entries, true)
+        .release_response();
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script)).with_options(DownloadOptions {
+        since: Some(SyncCursor {
+            last_time: None,
+            last_entry: Some(EntryPosition {
+                segment: 0,
+                index: u32::MAX,
+            }),
+        }),
+        ..Default::default()
+    });
+    assert_eq!(handler.download().unwrap().samples.len(), 2);
+}
+
+#[test]
+fn parses_a_cursor_from_its_json_form() {
+    use accuchek_core::usb::{EntryPosition, SyncCursor};
+
+    let cursor = SyncCursor {
+        last_time: Some(entry(14, 7, 5, 0).time),
+        last_entry: Some(EntryPosition {
+            segment: 1,
+            index: 41,
+        }),
+    };
+    let json = serde_json::to_string(&cursor).unwrap();
+
+    assert_eq!(json.parse::<SyncCursor>().unwrap(), cursor);
+    assert!("2024-11-14".parse::<SyncCursor>().is_err());
+}
+
+#[test]
+fn skips_the_transfer_when_nothing_is_new() {
+    use accuchek_core::usb::{EntryPosition, SyncCursor};
+
+    let by_entry = SyncCursor {
+        last_time: None,
+        last_entry: Some(EntryPosition {
+            segment: 0,
+            index: 2,
+        }),
+    };
+    let mut attributes = segment_attributes(3);
+    let mut by_time = SyncCursor {
+        last_time: Some(entry(15, 22, 45, 0).time),
+        last_entry: None,
+    };
+    // Time-End-Seg 2024-11-15 22:45
+    attributes.push(0x0A7A, vec![0x20, 0x24, 0x11, 0x15, 0x22, 0x45, 0x00, 0x00]);
+
+    for since in [by_entry.clone(), by_time.clone()] {
+        let script = MeterScript::new()
+            .association_request()
+            .config_report(MOCK_PM_STORE_HANDLE)
+            .mds_attributes()
+            .segment_info_with(MOCK_PM_STORE_HANDLE, attributes.clone())
+            .release_response();
+
+        let mut handler =
+            ProtocolHandler::new(MockMeter::new(script)).with_options(DownloadOptions {
+                since: Some(since.clone()),
+                ..Default::default()
+            });
+        let result = handler.download().unwrap();
+
+        assert!(result.samples.is_empty());
+        assert_eq!(result.cursor, since);
+
+        let meter = handler.into_transport();
+        assert_eq!(meter.remaining(), 0);
+        assert!(!meter
+            .sent()
+            .iter()
+            .any(|msg| msg.len() > 15 && be16(msg, 14) == 0x0C1C));
+    }
+
+    // A cleared segment holds fewer entries than the cursor: fall back to time
+    by_time.last_entry = Some(EntryPosition {
+        segment: 0,
+        index: 40,
+    });
+    let entries = [entry(15, 22, 45, 98), entry(16, 8, 0, 120)];
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_with(MOCK_PM_STORE_HANDLE, segment_attributes(2))
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data(MOCK_PM_STORE_HANDLE, 0, &entries, true)
+        .release_response();
+    let mut handler = ProtocolHandler::new(MockMeter::new(script)).with_options(DownloadOptions {
+        since: Some(by_time),
+        ..Default::default()
+    });
+    let result = handler.download().unwrap();
+
+    assert_eq!(result.samples.len(), 1);
+    assert_eq!(result.samples[0].mg_dl, Some(120));
+    assert_eq!(
+        result.cursor.last_entry,
+        Some(EntryPosition {
+            segment: 0,
+            index: 1
+        })
+    );
+}
+
+/// Three segments: two holding readings, one empty
+fn three_segments() -> Vec<accuchek_core::usb::apdu::SegmentInfo> {
+    use accuchek_core::usb::apdu::SegmentInfo;
+
+    let mut first = segment_attributes(2);
+    first.push(0x0A8B, [&[0x00, 0x05][..], b"Daily"].concat());
+    first.push(0x0A79, vec![0x20, 0x24, 0x11, 0x14, 0x07, 0x05, 0x00, 0x00]);
+    first.push(0x0A7A, vec![0x20, 0x24, 0x11, 0x14, 0x12, 0x30, 0x00, 0x00]);
+
+    vec![
+        SegmentInfo {
+            instance: 0,
+            attributes: first,
+        },
+        SegmentInfo {
+            instance: 3,
+            attributes: segment_attributes(0),
+        },
+        SegmentInfo {
+            instance: 7,
+            attributes: segment_attributes(1),
+        },
+    ]
+}
+
+#[test]
+fn transfers_every_segment_in_turn() {
+    use accuchek_core::usb::EntryPosition;
+
+    let entries = [
+        entry(14, 7, 5, 105),
+        entry(14, 12, 30, 142),
+        entry(15, 22, 45, 98),
+    ];
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_list(MOCK_PM_STORE_HANDLE, &three_segments())
+        .trigger_transfer_of(MOCK_PM_STORE_HANDLE, 0, 0)
+        .segment_data_of(MOCK_PM_STORE_HANDLE, 0, 0, &entries[..2], true)
+        .trigger_transfer_of(MOCK_PM_STORE_HANDLE, 7, 0)
+        .segment_data_of(MOCK_PM_STORE_HANDLE, 7, 0, &entries[2..], true)
+        .release_response();
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script));
+    let result = handler.download().unwrap();
+
+    assert_eq!(result.segments.len(), 3);
+    assert_eq!(result.samples.len(), 3);
+    let ids: Vec<_> = result.samples.iter().map(|sample| sample.id).collect();
+    assert_eq!(ids, [0, 1, 2]);
+    assert_eq!(result.samples[2].mg_dl, Some(98));
+    assert_eq!(
+        result.cursor.last_entry,
+        Some(EntryPosition {
+            segment: 7,
+            index: 0
+        })
+    );
+
+    // The empty segment is never triggered
+    let meter = handler.into_transport();
+    assert_eq!(meter.remaining(), 0);
+    let triggered: Vec<_> = meter
+        .sent_apdus()
+        .unwrap()
+        .into_iter()
+        .filter_map(|apdu| match apdu {
+            Apdu::Presentation(data) => match data.message {
+                DataMessage::ConfirmedAction(action) if action.action_type == 0x0C1C => {
+                    Some(be16(&action.info, 0))
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    assert_eq!(triggered, [0, 7]);
+}
+
+#[test]
+fn skips_segments_the_meter_refuses_to_transfer() {
+    let entries = [entry(15, 22, 45, 98)];
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_list(MOCK_PM_STORE_HANDLE, &three_segments())
+        .trigger_transfer_of(MOCK_PM_STORE_HANDLE, 0, 2) // try later
+        .trigger_transfer_of(MOCK_PM_STORE_HANDLE, 7, 0)
+        .segment_data_of(MOCK_PM_STORE_HANDLE, 7, 0, &entries, true)
+        .release_response();
+
+    let (result, meter) = run(script);
+    let samples = result.unwrap();
+
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].mg_dl, Some(98));
+    assert_eq!(meter.remaining(), 0);
+}
+
+#[test]
+fn lists_the_segments_of_the_pm_store() {
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_list(MOCK_PM_STORE_HANDLE, &three_segments())
+        .release_response();
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script));
+    let segments = handler.list_segments().unwrap();
+
+    assert_eq!(segments.len(), 3);
+    assert_eq!(segments[0].instance, 0);
+    assert_eq!(segments[0].label.as_deref(), Some("Daily"));
+    assert_eq!(segments[0].entry_count, Some(2));
+    assert_eq!(segments[0].start_time, Some(entry(14, 7, 5, 0).time));
+    assert_eq!(segments[0].end_time, Some(entry(14, 12, 30, 0).time));
+    assert!(segments[1].is_empty());
+    assert_eq!(segments[2].instance, 7);
+    assert_eq!(segments[2].label, None);
+    assert_eq!(segments[2].entry_map.header, 0x8000);
+
+    // Nothing but the segment info request goes out before the release
+    let meter = handler.into_transport();
+    assert_eq!(meter.remaining(), 0);
+    assert_eq!(meter.sent().len(), 5);
+}
+
+fn clear_options(confirm_count: u32) -> accuchek_core::ClearOptions {
+    accuchek_core::ClearOptions {
+        confirm_count,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn clears_segments_after_a_confirmed_download() {
+    use accuchek_core::usb::apdu::SegmentSelection;
+
+    let entries = [
+        entry(14, 7, 5, 105),
+        entry(14, 12, 30, 0x07FE), // HI, dropped but still counted
+        entry(15, 22, 45, 98),
+    ];
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_list(MOCK_PM_STORE_HANDLE, &three_segments())
+        .trigger_transfer_of(MOCK_PM_STORE_HANDLE, 0, 0)
+        .segment_data_of(MOCK_PM_STORE_HANDLE, 0, 0, &entries[..2], true)
+        .trigger_transfer_of(MOCK_PM_STORE_HANDLE, 7, 0)
+        .segment_data_of(MOCK_PM_STORE_HANDLE, 7, 0, &entries[2..], true)
+        .clear_segments_response(MOCK_PM_STORE_HANDLE)
+        .release_response();
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script));
+    let result = handler.clear_segments(&clear_options(3)).unwrap();
+
+    assert_eq!(result.download.samples.len(), 2);
+    assert_eq!(result.download.entry_count, 3);
+    assert_eq!(result.cleared, [0, 7]);
+
+    let meter = handler.into_transport();
+    assert_eq!(meter.remaining(), 0);
+    let apdus = meter.sent_apdus().unwrap();
+    match &apdus[apdus.len() - 2] {
+        Apdu::Presentation(data) => match &data.message {
+            DataMessage::ConfirmedAction(action) => {
+                assert_eq!(action.obj_handle, MOCK_PM_STORE_HANDLE);
+                assert_eq!(action.action_type, 0x0C0C);
+                assert_eq!(
+                    SegmentSelection::from_bytes(&action.info).unwrap(),
+                    SegmentSelection::Segments(vec![0, 7])
+                );
+            }
+            other => panic!("expected a clear action, got {}", other.name()),
+        },
+        other => panic!("expected a clear action, got {}", other.name()),
+    }
+}
+
+#[test]
+fn refuses_to_clear_without_a_matching_count() {
+    let entries = [entry(14, 7, 5, 105), entry(14, 12, 30, 142)];
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_with(MOCK_PM_STORE_HANDLE, segment_attributes(2))
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data(MOCK_PM_STORE_HANDLE, 0, &entries, true)
+        .release_response();
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script));
+    let err = handler.clear_segments(&clear_options(3)).unwrap_err();
+
+    assert!(err.to_string().contains("downloaded 2 entries"));
+    let meter = handler.into_transport();
+    assert_eq!(meter.remaining(), 0);
+    assert!(!meter
+        .sent()
+        .iter()
+        .any(|msg| msg.len() > 15 && be16(msg, 14) == 0x0C0C));
+}
+
+#[test]
+fn refuses_to_clear_after_a_failed_transfer() {
+    let entries = [entry(15, 22, 45, 98)];
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_list(MOCK_PM_STORE_HANDLE, &three_segments())
+        .trigger_transfer_of(MOCK_PM_STORE_HANDLE, 0, 512)
+        .trigger_transfer_of(MOCK_PM_STORE_HANDLE, 7, 0)
+        .segment_data_of(MOCK_PM_STORE_HANDLE, 7, 0, &entries, true)
+        .release_response();
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script));
+    let err = handler.clear_segments(&clear_options(1)).unwrap_err();
+
+    assert!(err.to_string().contains("segments [0]"));
+    let meter = handler.into_transport();
+    assert_eq!(meter.remaining(), 0);
+    assert!(!meter
+        .sent()
+        .iter()
+        .any(|msg| msg.len() > 15 && be16(msg, 14) == 0x0C0C));
+}
+
+#[test]
+fn stops_when_the_meter_aborts() {
+    use accuchek_core::usb::apdu::AbortReason;
+    use accuchek_core::usb::{AssociationState, UsbError};
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .send(Apdu::Abort(AbortReason::CONFIGURATION_TIMEOUT));
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script));
+    let err = handler.download().unwrap_err();
+
+    assert!(matches!(err.downcast_ref(), Some(UsbError::Aborted(3))));
+    assert_eq!(handler.state(), AssociationState::Disconnected);
+
+    // Nothing is sent once the meter has aborted
+    let meter = handler.into_transport();
+    assert_eq!(meter.sent().len(), 3);
+}
+
+#[test]
+fn releases_the_association_when_the_meter_returns_an_error() {
+    use accuchek_core::usb::apdu::ErrorResult;
+    use accuchek_core::usb::{AssociationState, UsbError};
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .respond(DataMessage::Error(ErrorResult {
+            error_value: 24, // not-allowed-in-current-state
+            parameter: Vec::new(),
+        }))
+        .release_response();
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script));
+    let err = handler.download().unwrap_err();
+
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(UsbError::RemoteError(24))
+    ));
+    assert_eq!(handler.state(), AssociationState::Disconnected);
+
+    let meter = handler.into_transport();
+    assert_eq!(meter.remaining(), 0);
+    let apdus = meter.sent_apdus().unwrap();
+    assert!(matches!(apdus.last(), Some(Apdu::ReleaseRequest(_))));
+}
+
+#[test]
+fn aborts_on_an_apdu_not_allowed_in_the_current_state() {
+    use accuchek_core::usb::AssociationState;
+
+    // A second AARQ in the middle of the session
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .association_request();
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script));
+    let err = handler.download().unwrap_err();
+
+    assert!(err.to_string().contains("AARQ"));
+    assert_eq!(handler.state(), AssociationState::Disconnected);
+
+    let meter = handler.into_transport();
+    let apdus = meter.sent_apdus().unwrap();
+    assert!(matches!(apdus.last(), Some(Apdu::Abort(_))));
+}
+
+fn presentation(invoke_id: u16, message: DataMessage) -> Apdu {
+    Apdu::Presentation(accuchek_core::usb::apdu::DataApdu { invoke_id, message })
+}
+
+#[test]
+fn acknowledges_stray_event_reports() {
+    use accuchek_core::usb::apdu::EventReport;
+
+    let entries = [entry(14, 7, 5, 105)];
+    let stray = DataMessage::ConfirmedEventReport(EventReport {
+        obj_handle: 0,
+        event_time: 0,
+        event_type: 0x0D1D, // fixed scan report
+        info: vec![0x00, 0x00],
+    });
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .send(presentation(0x4242, stray))
+        .mds_attributes()
+        .segment_info(MOCK_PM_STORE_HANDLE)
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data(MOCK_PM_STORE_HANDLE, 0, &entries, true)
+        .release_response();
+
+    let (result, meter) = run(script);
+    assert_eq!(result.unwrap().len(), 1);
+    assert_eq!(meter.remaining(), 0);
+
+    // The stray report is acknowledged under its own invoke-id
+    let ack = meter
+        .sent_apdus()
+        .unwrap()
+        .into_iter()
+        .find_map(|apdu| match apdu {
+            Apdu::Presentation(data) if data.invoke_id == 0x4242 => Some(data.message),
+            _ => None,
+        });
+    assert!(matches!(
+        ack,
+        Some(DataMessage::EventReportResult(report)) if report.event_type == 0x0D1D
+    ));
+}
+
+#[test]
+fn rejects_a_response_to_an_unknown_invoke_id() {
+    use accuchek_core::usb::apdu::GetResult;
+    use accuchek_core::usb::UsbError;
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .send(presentation(
+            0x0999,
+            DataMessage::GetResult(GetResult {
+                obj_handle: 0,
+                attributes: mock::mock_mds_attributes(),
+            }),
+        ));
+
+    let (result, meter) = run(script);
+    let err = result.unwrap_err();
+
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(UsbError::InvokeIdMismatch {
+            expected: Some(_),
+            received: 0x0999
+        })
+    ));
+    assert!(matches!(
+        meter.sent_apdus().unwrap().last(),
+        Some(Apdu::Abort(_))
+    ));
+}
+
+#[test]
+fn acknowledges_a_repeated_segment_event_once() {
+    use accuchek_core::usb::apdu::{EventReport, SegmentDataDescriptor, SegmentDataEvent};
+
+    // 2024-11-14 07:05, 105 mg/dL
+    let mut entries = vec![0x20, 0x24, 0x11, 0x14, 0x07, 0x05, 0x00, 0x00];
+    entries.extend_from_slice(&105u16.to_be_bytes());
+    entries.extend_from_slice(&0u16.to_be_bytes());
+    let event = SegmentDataEvent {
+        descriptor: SegmentDataDescriptor {
+            instance: 0,
+            entry_index: 0,
+            entry_count: 1,
+            status: 0x8000,
+        },
+        entries,
+    };
+    let report = DataMessage::ConfirmedEventReport(EventReport {
+        obj_handle: MOCK_PM_STORE_HANDLE,
+        event_time: 0xFFFFFFFF,
+        event_type: 0x0D21,
+        info: event.to_bytes(),
+    });
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info(MOCK_PM_STORE_HANDLE)
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .send(presentation(7, report.clone()))
+        .send(presentation(7, report)) // retransmitted event
+        .segment_data(MOCK_PM_STORE_HANDLE, 1, &[entry(14, 12, 30, 142)], true)
+        .release_response();
+
+    let (result, meter) = run(script);
+    let samples = result.unwrap();
+
+    assert_eq!(samples.len(), 2);
+    assert_eq!(samples[1].mg_dl, Some(142));
+    assert_eq!(meter.remaining(), 0);
+
+    // Both copies of the event are acknowledged
+    let acks = meter
+        .sent_apdus()
+        .unwrap()
+        .into_iter()
+        .filter(|apdu| matches!(apdu, Apdu::Presentation(data) if data.invoke_id == 7))
+        .count();
+    assert_eq!(acks, 2);
+}
+
+fn many_entries(count: u32) -> Vec<MockEntry> {
+    (0..count)
+        .map(|i| {
+            entry(
+                1 + i / 96,
+                (i / 4) % 24,
+                (i % 4) * 15,
+                80 + (i % 100) as u16,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn reassembles_segment_data_spanning_many_packets() {
+    // One segment data event of several kilobytes
+    let entries = many_entries(300);
+    let (result, meter) = run(MeterScript::standard(&entries, entries.len()));
+    let samples = result.unwrap();
+
+    assert_eq!(samples.len(), 300);
+    assert_eq!(samples[299].mg_dl, Some(179));
+    assert_eq!(meter.remaining(), 0);
+}
+
+#[test]
+fn rejects_entry_indexes_past_the_end_of_the_segment() {
+    use accuchek_core::usb::UsbError;
+
+    let entries = [entry(14, 7, 5, 105), entry(14, 8, 5, 131)];
+    for first_index in [u32::MAX, u32::MAX - 1] {
+        let script = MeterScript::new()
+            .association_request()
+            .config_report(MOCK_PM_STORE_HANDLE)
+            .mds_attributes()
+            .segment_info(MOCK_PM_STORE_HANDLE)
+            .trigger_transfer(MOCK_PM_STORE_HANDLE)
+            .segment_data(MOCK_PM_STORE_HANDLE, first_index, &entries, true);
+
+        let err = run(script).0.unwrap_err();
+        assert!(
+            matches!(err.downcast_ref(), Some(UsbError::Parse(_))),
+            "{}",
+            err
+        );
+    }
+}
+
+#[test]
+fn limits_apdus_to_the_size_of_the_meter_specialization() {
+    use accuchek_core::usb::{UsbError, DEFAULT_MAX_APDU_SIZE};
+
+    // A presentation APDU announcing more than a glucose meter may send
+    let mut oversized = vec![0xE7, 0x00];
+    oversized.extend_from_slice(&65000u16.to_be_bytes());
+    oversized.extend_from_slice(&[0x00; 60]);
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .step(MeterStep::Send(oversized));
+    let mut handler = ProtocolHandler::new(MockMeter::new(script)).with_options(DownloadOptions {
+        max_apdu_size: Some(100_000),
+        ..Default::default()
+    });
+    let err = handler.download().unwrap_err();
+
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(UsbError::ApduTooLarge { size: 65004, max }) if *max == DEFAULT_MAX_APDU_SIZE
+    ));
+}
+
+#[test]
+fn aborts_on_an_apdu_over_the_size_limit() {
+    use accuchek_core::usb::UsbError;
+
+    let entries = many_entries(300);
+    let mut handler = ProtocolHandler::new(MockMeter::new(MeterScript::standard(
+        &entries,
+        entries.len(),
+    )))
+    .with_options(DownloadOptions {
+        max_apdu_size: Some(1024),
+        ..Default::default()
+    });
+    let err = handler.download().unwrap_err();
+
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(UsbError::ApduTooLarge { max: 1024, .. })
+    ));
+    let meter = handler.into_transport();
+    assert!(matches!(
+        meter.sent_apdus().unwrap().last(),
+        Some(Apdu::Abort(_))
+    ));
+}
+
+#[test]
+fn retries_a_read_that_timed_out() {
+    use accuchek_core::usb::RetryPolicy;
+    use std::time::Duration;
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info(MOCK_PM_STORE_HANDLE)
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .silence()
+        .segment_data(MOCK_PM_STORE_HANDLE, 0, &[entry(14, 7, 5, 105)], true)
+        .release_response();
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script)).with_options(DownloadOptions {
+        retry: RetryPolicy {
+            retries: 1,
+            delay: Duration::ZERO,
+        },
+        ..Default::default()
+    });
+    let result = handler.download().unwrap();
+
+    assert_eq!(result.samples.len(), 1);
+    assert_eq!(handler.into_transport().remaining(), 0);
+}
+
+#[test]
+fn applies_the_timeout_of_each_phase() {
+    use accuchek_core::usb::Timeouts;
+    use std::time::Duration;
+
+    let timeouts = Timeouts {
+        association: Duration::from_secs(30),
+        response: Duration::from_secs(3),
+        segment_data: Duration::from_secs(10),
+        release: Duration::from_secs(1),
+    };
+    let script = MeterScript::standard(&[entry(14, 7, 5, 105)], 1);
+    let mut handler = ProtocolHandler::new(MockMeter::new(script)).with_options(DownloadOptions {
+        timeouts,
+        ..Default::default()
+    });
+    handler.download().unwrap();
+
+    assert_eq!(
+        handler.into_transport().timeouts(),
+        [
+            timeouts.association,
+            timeouts.response,
+            timeouts.segment_data,
+            timeouts.response,
+            timeouts.release
+        ]
+    );
+}
+
+#[test]
+fn keeps_acknowledged_entries_when_a_transfer_fails() {
+    use accuchek_core::usb::{EntryPosition, RetryPolicy};
+
+    // The meter stops answering after the first data event of the segment
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info(MOCK_PM_STORE_HANDLE)
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data(
+            MOCK_PM_STORE_HANDLE,
+            0,
+            &[entry(14, 7, 5, 105), entry(14, 12, 30, 142)],
+            false,
+        );
+
+    let options = DownloadOptions {
+        retry: RetryPolicy::NONE,
+        keep_partial: true,
+        ..Default::default()
+    };
+    let mut handler = ProtocolHandler::new(MockMeter::new(script)).with_options(options);
+    let result = handler.download().unwrap();
+
+    assert_eq!(result.interrupted.as_deref(), Some("Timeout"));
+    assert_eq!(result.samples.len(), 2);
+    assert_eq!(result.entry_count, 2);
+    assert_eq!(
+        result.cursor.last_entry,
+        Some(EntryPosition {
+            segment: 0,
+            index: 1
+        })
+    );
+    assert_eq!(
+        result.cursor.last_time,
+        NaiveDate::from_ymd_opt(2024, 11, 14)
+            .unwrap()
+            .and_hms_opt(12, 30, 0)
+    );
+
+    // The release goes unanswered too, so the association is aborted
+    let sent = handler.into_transport().sent_apdus().unwrap();
+    assert!(matches!(sent[sent.len() - 2], Apdu::ReleaseRequest(_)));
+    assert!(matches!(sent.last(), Some(Apdu::Abort(_))));
+}
+
+#[test]
+fn fails_on_an_interrupted_transfer_by_default() {
+    use accuchek_core::usb::RetryPolicy;
+
+    let script = MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info(MOCK_PM_STORE_HANDLE)
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data(MOCK_PM_STORE_HANDLE, 0, &[entry(14, 7, 5, 105)], false);
+
+    let mut handler = ProtocolHandler::new(MockMeter::new(script)).with_options(DownloadOptions {
+        retry: RetryPolicy::NONE,
+        ..Default::default()
+    });
+    let err = handler.download().unwrap_err();
+
+    assert_eq!(err.to_string(), "Timeout");
+}
+
+fn three_readings_script() -> MeterScript {
+    MeterScript::new()
+        .association_request()
+        .config_report(MOCK_PM_STORE_HANDLE)
+        .mds_attributes()
+        .segment_info_with(MOCK_PM_STORE_HANDLE, segment_attributes(3))
+        .trigger_transfer(MOCK_PM_STORE_HANDLE)
+        .segment_data(
+            MOCK_PM_STORE_HANDLE,
+            0,
+            &[entry(14, 7, 5, 105), entry(14, 12, 30, 142)],
+            false,
+        )
+        .segment_data(MOCK_PM_STORE_HANDLE, 2, &[entry(14, 19, 0, 98)], true)
+        .release_response()
+}
+
+#[test]
+fn reports_the_progress_of_the_transfer() {
+    use accuchek_core::usb::Progress;
+    use std::sync::{Arc, Mutex};
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let observed = events.clone();
+    let mut handler = ProtocolHandler::new(MockMeter::new(three_readings_script()))
+        .with_progress(move |progress| observed.lock().unwrap().push(progress.clone()));
+    handler.download().unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(
+        events[0],
+        Progress::Phase {
+            phase: 1,
+            name: "initial control transfer".to_string()
+        }
+    );
+    assert!(events.contains(&Progress::SegmentsListed {
+        segments: 1,
+        expected_entries: Some(3)
+    }));
+
+    let received: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            Progress::EntriesReceived {
+                received_entries,
+                decoded_samples,
+                expected_entries,
+                ..
+            } => Some((*received_entries, *decoded_samples, *expected_entries)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(received, [(2, 2, Some(3)), (3, 3, Some(3))]);
+}
+
+#[test]
+fn aborts_the_association_when_cancelled() {
+    use accuchek_core::usb::{CancelToken, Progress, UsbError};
+
+    // Cancel as soon as the first entries arrive
+    let token = CancelToken::new();
+    let canceller = token.clone();
+    let mut handler = ProtocolHandler::new(MockMeter::new(three_readings_script()))
+        .with_cancel_token(token)
+        .with_progress(move |progress| {
+            if matches!(progress, Progress::EntriesReceived { .. }) {
+                canceller.cancel();
+            }
+        });
+    let err = handler.download().unwrap_err();
+
+    assert!(matches!(err.downcast_ref(), Some(UsbError::Cancelled)));
+
+    // The first event is acknowledged, then the association is aborted
+    let meter = handler.into_transport();
+    let sent = meter.sent_apdus().unwrap();
+    assert!(matches!(sent.last(), Some(Apdu::Abort(_))));
+    assert!(matches!(
+        &sent[sent.len() - 2],
+        Apdu::Presentation(data) if matches!(data.message, DataMessage::EventReportResult(_))
+    ));
+    assert_eq!(meter.remaining(), 2);
+}